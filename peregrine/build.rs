@@ -1,18 +1,36 @@
-use tethys::graphics::model::{LoadModel, LoadMaterial};
+use strum::IntoEnumIterator;
+use tethys::graphics::model::AssetManager;
 
-fn main() {
-    println!("cargo:rerun-if-changed=assets/parts");
-    println!("cargo:rerun-if-changed=assets/attachments");
-    println!("cargo:rerun-if-changed=assets/panels");
+#[path = "src/ship/manifest.rs"]
+mod manifest;
 
-    LoadModel::load_obj("assets/attachments/hub-fluid.obj").save();
-    LoadModel::load_obj("assets/attachments/hub-circuit.obj").save();
+/// Check every asset named by the manifest, so that a missing or broken asset stops the build
+/// instead of panicking when the part is first drawn
+fn main() {
+    println!("cargo:rerun-if-changed=assets");
+    println!("cargo:rerun-if-changed=src/ship/manifest.rs");
 
-    LoadModel::load_obj("assets/parts/tank-cap.obj").save();
-    LoadModel::load_obj("assets/parts/tank-body.obj").save();
-    LoadModel::load_obj("assets/parts/scaffold.obj").save();
-    LoadModel::load_obj("assets/parts/thruster.obj").save();
-    LoadModel::load_obj("assets/parts/battery.obj").save();
+    let assets = AssetManager::new("assets");
+    let mut problems = Vec::new();
+    let models = manifest::PartModel::iter().map(manifest::PartModel::asset)
+        .chain(manifest::AttachmentModel::iter().map(manifest::AttachmentModel::asset));
+    for name in models {
+        match assets.load_model(name) {
+            Ok(model) => problems.extend(model.validate().into_iter().map(|problem| format!("{name}: {problem}"))),
+            Err(e) => problems.push(format!("{e:#}")),
+        }
+    }
+    for name in manifest::PanelMaterial::iter().map(manifest::PanelMaterial::asset) {
+        match assets.load_material(name) {
+            Ok(material) => problems.extend(material.validate().into_iter().map(|problem| format!("{name}: {problem}"))),
+            Err(e) => problems.push(format!("{e:#}")),
+        }
+    }
 
-    LoadMaterial::load_mtl("assets/panels/metal.mtl").save();
-}
\ No newline at end of file
+    for problem in &problems {
+        println!("cargo:warning={problem}");
+    }
+    if !problems.is_empty() {
+        panic!("{} problems were found in the assets", problems.len());
+    }
+}
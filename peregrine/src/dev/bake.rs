@@ -0,0 +1,23 @@
+use std::path::Path;
+
+use anyhow::Result;
+use strum::IntoEnumIterator;
+use tethys::prelude::*;
+
+use crate::ship::manifest::{AttachmentModel, PanelMaterial, PartModel};
+
+/// Optimize every model in the manifest, compress the textures of every model and material, and
+/// save each as a `.bin` beside its source. The game loads the baked assets instead of the sources
+/// until they are deleted.
+pub fn bake_assets(directory: &Path) -> Result<()> {
+    let assets = AssetManager::new(directory);
+    for name in PartModel::iter().map(PartModel::asset).chain(AttachmentModel::iter().map(AttachmentModel::asset)) {
+        let path = assets.bake_model(name)?;
+        println!("Baked {}", path.display());
+    }
+    for name in PanelMaterial::iter().map(PanelMaterial::asset) {
+        let path = assets.bake_material(name)?;
+        println!("Baked {}", path.display());
+    }
+    Ok(())
+}
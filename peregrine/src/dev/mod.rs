@@ -1 +1,2 @@
+pub mod bake;
 pub mod normal;
\ No newline at end of file
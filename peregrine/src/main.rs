@@ -1,27 +1,69 @@
-use log::info;
+use std::path::PathBuf;
+use log::{error, info};
 use tethys::prelude::*;
-use cgmath::{Quaternion, Vector3};
+use cgmath::Vector3;
 use clap::Parser;
 
 pub mod dev;
+pub mod planet;
 pub mod ship;
 pub mod ui;
 pub mod util;
 
-use ship::{Panel, PanelLayout, Part, PartData, PartLayout, SaveShipInterior, ShipInterior};
+use planet::{Planet, Terrain};
+use ship::{Part, PartData, SaveShipInterior, ShipInterior, ThrusterGroup};
 use ui::{FpsCounter, UiMode};
 use util::Save;
 
+/// Keys which fire each thruster group at full throttle while flying
+const THROTTLE_KEYS: [(Key, ThrusterGroup); 6] = [
+    (Key::Up, ThrusterGroup::Forward),
+    (Key::Down, ThrusterGroup::Backward),
+    (Key::Left, ThrusterGroup::Left),
+    (Key::Right, ThrusterGroup::Right),
+    (Key::LShift, ThrusterGroup::Up),
+    (Key::LCtrl, ThrusterGroup::Down),
+];
+
+/// Width of each cascade of the sun's shadow map in texels
+const SHADOW_MAP_SIZE: u32 = 2048;
+/// Width of each face of the environment map in texels
+const ENVIRONMENT_SIZE: u32 = 64;
+/// Width of each face of the sky in texels
+const SKYBOX_SIZE: u32 = 512;
+/// Seed of the stars in the sky
+const STARFIELD_SEED: u64 = 1;
+/// Number of stars in the sky, about as many as can be seen from Earth
+const STAR_COUNT: usize = 9000;
+/// Seed of the hills of the planet
+const PLANET_SEED: u64 = 1;
+/// Mean radius of the planet in meters
+const PLANET_RADIUS: f64 = 1000.;
+/// About the greatest height of the hills of the planet above or below its mean radius
+const PLANET_HILL_HEIGHT: f64 = 30.;
+/// Width of the largest hills of the planet
+const PLANET_HILL_WIDTH: f64 = 400.;
+/// Acceleration of gravity at the mean radius of the planet in m/s^2, about that of the Moon
+const PLANET_SURFACE_GRAVITY: f64 = 1.62;
+/// Height of the ship above the mean radius of the planet when the game starts
+const PLANET_CLEARANCE: f64 = 50.;
+/// Greatest distance at which anything is drawn, which is far enough to see the whole planet
+const DRAW_DISTANCE: f32 = 5000.;
+
 struct Peregrine<'a> {
     shader_3d: Shader,
     shader_2d: Shader,
     shader_placement: Shader,
     camera: Camera,
+    lights: Lights,
+    skybox: Skybox,
+    planet: Planet,
     graphics: Graphics<'a>,
     part_data: PartData,
     exit: bool,
     ui_mode: UiMode,
     fps_counter: FpsCounter,
+    blueprint: PathBuf,
     
     ship: Option<ShipInterior>,
 }
@@ -32,8 +74,9 @@ impl<'a> App for Peregrine<'a> {
         env_logger::init();
         let shader_3d = ShaderBuilder::<TexVertex>::new(include_str!("shaders/shader_3d.wgsl"), &[
             ShaderBinding::Camera,
-            ShaderBinding::Object,
-            ShaderBinding::NoisyTexture,
+            ShaderBinding::Instanced,
+            ShaderBinding::Pbr,
+            ShaderBinding::Lights,
         ]).build(&graphics);
         let shader_placement = ShaderBuilder::<TexVertex>::new(include_str!("shaders/shader_placement.wgsl"), &[
             ShaderBinding::Camera,
@@ -42,8 +85,29 @@ impl<'a> App for Peregrine<'a> {
         let shader_2d = ShaderBuilder::<ScreenVertex>::new(include_str!("shaders/shader_2d.wgsl"), &[
             ShaderBinding::Texture,
         ]).build(&graphics);
-        let camera = Camera::new(&graphics, Vector3::new(-2., 0., 0.), 1.57, 0., 0.1, 10., 1.5);
-        let part_data = PartData::new();
+        let camera = Camera::new(&graphics, Vector3::new(-2., 0., 0.), 1.57, 0., 0.1, DRAW_DISTANCE, 1.5);
+        let mut lights = Lights::new(&graphics, SHADOW_MAP_SIZE);
+        lights.directional.push(DirectionalLight {
+            direction: Vector3::new(1., 1., 1.),
+            color: Vector3::new(1., 1., 1.),
+            intensity: 1.,
+        });
+        let mut sky = CubeMap::from_fn(SKYBOX_SIZE, galaxy_glow);
+        Starfield::new(STARFIELD_SEED, STAR_COUNT).draw(&mut sky);
+        let skybox = Skybox::new(&graphics, &sky);
+        lights.set_environment(&graphics, Environment::from_cube_map(&graphics, sky.downsample(ENVIRONMENT_SIZE)));
+        let planet = Planet::new(
+            &graphics,
+            Vector3::new(0., 0., -PLANET_RADIUS - PLANET_CLEARANCE),
+            Terrain::new(PLANET_SEED, PLANET_RADIUS, PLANET_HILL_HEIGHT, PLANET_HILL_WIDTH),
+            PLANET_SURFACE_GRAVITY,
+        );
+        let args = Args::parse();
+        if !args.assets.is_dir() {
+            error!("The asset directory {} does not exist. Pass the path to peregrine/assets with --assets.", args.assets.display());
+            std::process::exit(1);
+        }
+        let part_data = PartData::new(AssetManager::new(args.assets));
     
         let ui_mode = UiMode::Flying;
     
@@ -53,68 +117,27 @@ impl<'a> App for Peregrine<'a> {
             shader_2d,
             ship: None,
             camera,
+            lights,
+            skybox,
+            planet,
             ui_mode,
             fps_counter: FpsCounter::new(),
             graphics,
             part_data,
             shader_placement,
+            blueprint: args.blueprint,
         }
     }
 
     fn initialize(&mut self) {
         let part_loader = self.part_data.get_loader(&self.graphics);
-        let parts = vec![
-            Part::Tank {length: 3},
-            Part::Tank {length: 3},
-            Part::Scaffold { length: 1, width: 1, height: 1},
-            Part::Scaffold { length: 1, width: 1, height: 1},//TODO clean up
-            Part::Thruster {},
-            Part::Thruster {},
-            Part::Battery {},
-            // , Part::FuelCell
-        ];
-        let layout = vec![
-            PartLayout { x: 0, y: 1, z: 0, orientation: 8 },
-            PartLayout { x: 0, y: -1, z: 0, orientation: 8 },
-            PartLayout { x: 0, y: 0, z: 0, orientation: 0 },
-            PartLayout { x: 0, y: 0, z: 1, orientation: 0 },
-            PartLayout { x: -2, y: 1, z: 0, orientation: 8 },
-            PartLayout { x: -2, y: -1, z: 0, orientation: 8 },
-            PartLayout { x: 2, y: 0, z: 0, orientation: 0 },
-        ];
-        let rigid_body = RigidBody {
-            angvel: Quaternion::new(0., 0., 0., 0.),
-            // orientation: Quaternion::new(0., 0., 0., 1.),
-            ..Default::default()
-        };
-        let save = SaveShipInterior {
-            parts,
-            part_layouts: layout,
-            panels: vec![Panel::Metal; 16],
-            panel_layouts: vec![
-                PanelLayout { vertices:[(0,0,2),(0,1,2),(-1,1,1)], },
-                PanelLayout { vertices:[(0,0,2),(-1,1,1),(-1,0,1)], },
-                PanelLayout { vertices:[(0,0,2),(-1,-1,1),(-1,0,1)], },
-                PanelLayout { vertices:[(0,1,2),(-1,2,1),(-1,1,1)], },
-                PanelLayout { vertices:[(0,1,2),(-1,2,1),(3,2,1)], },
-                PanelLayout { vertices:[(0,0,2),(-1,-1,1),(3,-1,1)], },
-    
-                PanelLayout { vertices:[(-1,-1,0),(-1,2,0),(3,2,0)], },//Bottom
-                PanelLayout { vertices:[(-1,-1,0),(3,2,0),(3,-1,0)], },
-                PanelLayout { vertices:[(-1,-1,1),(-1,2,0),(-1,-1,0)], },//Back
-                PanelLayout { vertices:[(-1,-1,1),(-1,2,0),(-1,2,1)], },
-                PanelLayout { vertices:[(3,-1,1),(3,2,0),(3,-1,0)], },//Front
-                PanelLayout { vertices:[(3,-1,1),(3,2,0),(3,2,1)], },
-                PanelLayout { vertices:[(-1,2,1),(3,2,0),(3,2,1)], },//Left
-                PanelLayout { vertices:[(-1,2,1),(3,2,0),(-1,2,0)], },
-                PanelLayout { vertices:[(-1,-1,1),(3,-1,0),(3,-1,1)], },//Right
-                PanelLayout { vertices:[(-1,-1,1),(3,-1,0),(-1,-1,0)], },
-            ],
-            attachments: Vec::new(),
-            attachment_layouts: Vec::new(),
-            rigid_body,
+        let ship = match SaveShipInterior::from_file(&self.blueprint, part_loader.clone()) {
+            Ok(ship) => ship,
+            Err(e) => {
+                error!("{e:#}");
+                SaveShipInterior::default().build(part_loader.clone())
+            },
         };
-        let ship = save.build(part_loader.clone());
         // self.ui_mode = UiMode::PlaceConnection(ui::PlaceConnectionState::new(ship::Fluid::Electricity, part_loader));
         self.ui_mode = UiMode::PlacePart(ui::PlacePartState::new(part_loader, Part::Tank { length: 3 }, &ship));
         // self.ui_mode = UiMode::PlacePanel(PlacePanelState::new(part_loader, PanelModel::Metal));
@@ -123,8 +146,21 @@ impl<'a> App for Peregrine<'a> {
 
     fn tick(&mut self, key_state: &KeyState, delta_t: f64) {
         info!("FPS: {}", self.fps_counter.get_fps());
+        self.part_data.reload_changed(&self.graphics);
         if let Some(ship) = &mut self.ship {
+            // The ship only feels the planet in flight, so that it holds still while it is built
+            let flying = matches!(self.ui_mode, UiMode::Flying);
+            if flying {
+                for (key, group) in THROTTLE_KEYS {
+                    ship.set_throttle(group, if key_state.is_down(key) { 1. } else { 0. });
+                }
+                // Gravity is added before the ship moves, and the ground pushes back on whatever it moved into
+                self.planet.gravity.apply(&mut ship.rigid_body);
+            }
             ship.update(&self.graphics, &self.camera, delta_t);
+            if flying {
+                ship.collide_with_static(self.planet.collider_package());
+            }
             self.ui_mode.update(&self.graphics, &self.camera, ship);
         }
 
@@ -153,6 +189,7 @@ impl<'a> App for Peregrine<'a> {
             self.camera.position -= 2. * delta_t * self.camera.get_up();
         }
 
+        self.planet.update(&self.graphics, &self.camera);
         self.fps_counter.update();
     }
 
@@ -190,8 +227,9 @@ impl<'a> App for Peregrine<'a> {
             },
             UiMode::Flying => (),
             UiMode::PlaceConnection(place_connection_state) => {
+                let part_loader = self.part_data.get_loader(&self.graphics);
                 if let Some(ship) = &mut self.ship {
-                    place_connection_state.place(ship);
+                    place_connection_state.place(part_loader, ship);
                 }
             },
         }
@@ -205,13 +243,21 @@ impl<'a> App for Peregrine<'a> {
         self.camera.theta = self.camera.theta.clamp(0., std::f32::consts::PI);
     }
     
-    fn render<'c, 'b: 'c> (&'b self, mut render_pass: RenderPass<'c>) {
+    fn render<'c, 'b: 'c> (&'b self, mut render_pass: RenderPass<'c>, _alpha: f64) {
         // 3D
         render_pass.set_camera(&self.camera);
+        render_pass.set_lights(&self.lights);
+        render_pass.render_skybox(&self.skybox);
+        let objects = || {
+            let mut objects = self.planet.objects();
+            if let Some(ship) = &self.ship {
+                objects.extend(ship.objects());
+            }
+            objects
+        };
+        render_pass.render_shadows(objects());
         render_pass.set_shader(&self.shader_3d);
-        if let Some(ship) = &self.ship {
-            render_pass.render(ship.objects());
-        }
+        render_pass.render(objects());
 
         render_pass.set_shader(&self.shader_placement);
         match &self.ui_mode {
@@ -245,64 +291,34 @@ impl<'a> App for Peregrine<'a> {
 struct Args {
     #[arg(short, long)]
     normal: bool,
+    /// Optimize the models and compress the textures in the asset directory and save them as `.bin`
+    /// files, then exit. Delete the `.bin` files to go back to loading the sources.
+    #[arg(long)]
+    bake: bool,
+    /// Ship blueprint to load, either a RON file or a binary save
+    #[arg(short, long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/ships/starter.ron"))]
+    blueprint: PathBuf,
+    /// Directory to load models and materials from. Changed files are reloaded while the game runs.
+    #[arg(short, long, default_value = concat!(env!("CARGO_MANIFEST_DIR"), "/assets"))]
+    assets: PathBuf,
+}
+
+/// Faint light from the stars, which is brightest along the band of the galaxy around the horizon
+fn galaxy_glow(direction: Vector3<f32>) -> Vector3<f32> {
+    let band = (1. - direction.z.abs()).powi(8);
+    Vector3::new(0.02, 0.022, 0.03) * (1. + 4. * band)
 }
 
 fn main() {
     let args = Args::parse();
     if args.normal {
         dev::normal::fourier_save_bumpmap();
+    } else if args.bake {
+        if let Err(e) = dev::bake::bake_assets(&args.assets) {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
     } else {
         tethys::main::<Peregrine>();
     }
 }
-
-/* Ship block code
-let parts = vec![
-            Part::Tank {length: 3},
-            Part::Tank {length: 3},
-            Part::Scaffold { length: 1, width: 1, height: 1},
-            Part::Scaffold { length: 1, width: 1, height: 1},//TODO clean up
-            Part::Thruster {},
-            Part::Thruster {},
-            // , Part::FuelCell
-        ];
-        let layout = vec![
-            PartLayout { x: 0, y: 1, z: 0, orientation: 8 },
-            PartLayout { x: 0, y: -1, z: 0, orientation: 8 },
-            PartLayout { x: 0, y: 0, z: 0, orientation: 0 },
-            PartLayout { x: 0, y: 0, z: 1, orientation: 0 },
-            PartLayout { x: -2, y: 1, z: 0, orientation: 8 },
-            PartLayout { x: -2, y: -1, z: 0, orientation: 8 },
-        ];
-        let panels = vec![
-            Panel {panel_model: PanelModel::Metal, vertices:[(0,0,2),(0,1,2),(-1,1,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(0,0,2),(-1,1,1),(-1,0,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(0,0,2),(-1,-1,1),(-1,0,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(0,1,2),(-1,2,1),(-1,1,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(0,1,2),(-1,2,1),(3,2,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(0,0,2),(-1,-1,1),(3,-1,1)], },
-
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,-1,0),(-1,2,0),(3,2,0)], },//Bottom
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,-1,0),(3,2,0),(3,-1,0)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,-1,1),(-1,2,0),(-1,-1,0)], },//Back
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,-1,1),(-1,2,0),(-1,2,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(3,-1,1),(3,2,0),(3,-1,0)], },//Front
-            Panel {panel_model: PanelModel::Metal, vertices:[(3,-1,1),(3,2,0),(3,2,1)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,2,1),(3,2,0),(3,2,1)], },//Left
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,2,1),(3,2,0),(-1,2,0)], },
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,-1,1),(3,-1,0),(3,-1,1)], },//Right
-            Panel {panel_model: PanelModel::Metal, vertices:[(-1,-1,1),(3,-1,0),(-1,-1,0)], },
-        ];
-        let rigid_body = RigidBody {
-            angvel: Quaternion::new(0., 0., 0., 0.0),
-            // orientation: Quaternion::new(0., 0., 0., 1.),
-            ..Default::default()
-        };
-        let save = SaveShipInterior {
-            parts,
-            part_layouts: layout,
-            panels: panels.clone(),
-            panel_layouts: vec![PanelLayout{}; panels.len()],
-            rigid_body,
-        };
- */
\ No newline at end of file
@@ -0,0 +1,80 @@
+use cgmath::Vector3;
+use tethys::{graphics::model::{AddressMode, SamplerDescription}, physics::{collisions::ColliderPackage, gravity::{GravityField, PointMass}}, prelude::*};
+
+mod quadtree;
+mod terrain;
+
+use quadtree::Node;
+pub use terrain::Terrain;
+
+/// A planet whose ground is a cube blown up into a sphere. Each face of the cube is a quadtree of
+/// tiles which are split as the camera comes closer, and the edges of tiles next to coarser ones
+/// follow the coarser tiles so that there are no cracks between them.
+pub struct Planet {
+    pub center: Vector3<f64>,
+    terrain: Terrain,
+    faces: [Node; 6],
+    material: Material,
+    collider: Collider,
+    /// The pull of the planet, which is added to ships by hand because each ship owns its rigid body
+    /// rather than living in a `World`
+    pub gravity: GravityField,
+}
+
+impl Planet {
+    /// Make a planet whose gravity at its mean radius is `surface_gravity` in m/s^2
+    pub fn new(graphics: &Graphics, center: Vector3<f64>, terrain: Terrain, surface_gravity: f64) -> Self {
+        let material = Material::new_pbr(graphics, &LoadMaterial {
+            name: "Ground".to_string(),
+            diffuse: [0.33, 0.29, 0.26],
+            specular: [0.; 3],
+            shininess: 0.,
+            metallic: 0.,
+            roughness: 0.9,
+            occlusion_strength: 1.,
+            normal_texture: Vec::new(),
+            diffuse_texture: Vec::new(),
+            metallic_roughness_texture: Vec::new(),
+            occlusion_texture: Vec::new(),
+            normal_sampler: SamplerDescription::linear(AddressMode::Repeat),
+            diffuse_sampler: SamplerDescription::linear(AddressMode::Repeat),
+            metallic_roughness_sampler: SamplerDescription::linear(AddressMode::Repeat),
+            occlusion_sampler: SamplerDescription::linear(AddressMode::Repeat),
+        });
+        let gravity = GravityField::new(vec![PointMass { pos: center, mu: surface_gravity * terrain.radius * terrain.radius }]);
+        let ground = terrain.clone();
+        let collider = Collider::Terrain(TerrainCollider::new(center, terrain.max_radius(), move |direction| ground.radius_at(direction)));
+        Self {
+            center,
+            faces: std::array::from_fn(|face| Node::root(face, &terrain)),
+            terrain,
+            material,
+            collider,
+            gravity,
+        }
+    }
+
+    /// Choose the detail of the ground for the camera, remake the tiles which changed, and move
+    /// the tiles to where they are seen from the camera
+    pub fn update(&mut self, graphics: &Graphics, camera: &Camera) {
+        for face in &mut self.faces {
+            face.update_level(&self.terrain, camera.position - self.center);
+        }
+        Node::update_tiles(&mut self.faces, graphics, &self.terrain, &self.material);
+        for face in &self.faces {
+            face.update_graphics(graphics, camera, self.center);
+        }
+    }
+
+    pub fn objects(&self) -> Vec<ObjectHandle<'_>> {
+        let mut output = Vec::new();
+        for face in &self.faces {
+            face.objects(&mut output);
+        }
+        output
+    }
+
+    pub(crate) fn collider_package(&self) -> ColliderPackage<'_> {
+        (&self.collider).into()
+    }
+}
@@ -0,0 +1,397 @@
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4};
+
+use cgmath::{InnerSpace, Quaternion, Vector3};
+use tethys::prelude::*;
+
+use super::Terrain;
+
+/// Number of quads along each side of a tile
+const TILE_SEGMENTS: u32 = 16;
+/// Tiles are split when the camera is closer to their middle than this many tile widths
+const SPLIT_DISTANCE: f64 = 2.5;
+/// Tiles are not split once their vertices would be closer together than this, in meters
+const FINEST_SPACING: f64 = 1.;
+/// Distance past the middle of an edge, in face coordinates, at which the tile on the other side
+/// is looked up
+const NEIGHBOR_PROBE: f64 = 1e-6;
+
+/// Outward normal of each face of the cube, followed by the directions in which the face
+/// coordinates u and v increase. The cross product of u and v is the normal, so triangles wound
+/// counterclockwise in u and v face outward.
+const FACES: [[Vector3<f64>; 3]; 6] = [
+    [Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.), Vector3::new(0., 0., 1.)],
+    [Vector3::new(-1., 0., 0.), Vector3::new(0., 0., 1.), Vector3::new(0., 1., 0.)],
+    [Vector3::new(0., 1., 0.), Vector3::new(0., 0., 1.), Vector3::new(1., 0., 0.)],
+    [Vector3::new(0., -1., 0.), Vector3::new(1., 0., 0.), Vector3::new(0., 0., 1.)],
+    [Vector3::new(0., 0., 1.), Vector3::new(1., 0., 0.), Vector3::new(0., 1., 0.)],
+    [Vector3::new(0., 0., -1.), Vector3::new(0., 1., 0.), Vector3::new(1., 0., 0.)],
+];
+
+/// Unit vector through a point on a face, where u and v run from -1 to 1. The coordinates are
+/// warped with a tangent so that tiles at the corners of the cube are not much smaller on the
+/// sphere than tiles in the middle of a face.
+fn direction(face: usize, u: f64, v: f64) -> Vector3<f64> {
+    let [normal, u_axis, v_axis] = FACES[face];
+    (normal + u_axis * (u * FRAC_PI_4).tan() + v_axis * (v * FRAC_PI_4).tan()).normalize()
+}
+
+/// Face which a direction passes through and the coordinates of the point on it, undoing
+/// `direction`
+fn face_coordinates(direction: Vector3<f64>) -> (usize, f64, f64) {
+    let face = (0..6).max_by(|&a, &b| direction.dot(FACES[a][0]).total_cmp(&direction.dot(FACES[b][0]))).unwrap();
+    let [normal, u_axis, v_axis] = FACES[face];
+    let major = direction.dot(normal);
+    let unwarp = |x: f64| (x / major).atan() / FRAC_PI_4;
+    (face, unwarp(direction.dot(u_axis)), unwarp(direction.dot(v_axis)))
+}
+
+/// Place of a tile in the quadtree of a face. At each level the face is cut into 2^level tiles
+/// along each side, and this is the `x`th along u and the `y`th along v.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct TileId {
+    face: usize,
+    level: u32,
+    x: u32,
+    y: u32,
+}
+
+impl TileId {
+    /// Face coordinate of the `index`th vertex along a side of the face, counting the vertices of
+    /// every tile at this level
+    fn coordinate(&self, index: i64) -> f64 {
+        2. * index as f64 / (TILE_SEGMENTS << self.level) as f64 - 1.
+    }
+
+    /// Face coordinates of the corner of the tile with the lowest u and v, and of the middle
+    fn corner_and_middle(&self) -> ((f64, f64), (f64, f64)) {
+        let n = TILE_SEGMENTS as i64;
+        let (x, y) = (self.x as i64 * n, self.y as i64 * n);
+        ((self.coordinate(x), self.coordinate(y)), (self.coordinate(x + n / 2), self.coordinate(y + n / 2)))
+    }
+
+    /// The four quarters of the tile, with u increasing first
+    fn children(&self) -> [TileId; 4] {
+        std::array::from_fn(|i| TileId {
+            face: self.face,
+            level: self.level + 1,
+            x: 2 * self.x + (i % 2) as u32,
+            y: 2 * self.y + (i / 2) as u32,
+        })
+    }
+}
+
+/// A square of one face of the planet. It is drawn as a single tile until the camera comes close,
+/// when it is split into four finer nodes.
+pub(super) struct Node {
+    id: TileId,
+    /// Point on the ground in the middle of the node, relative to the center of the planet
+    middle: Vector3<f64>,
+    /// Width of the node along the ground
+    width: f64,
+    children: Option<Box<[Node; 4]>>,
+    tile: Option<Tile>,
+}
+
+/// Drawn ground of a node which has not been split
+struct Tile {
+    object: Object,
+    /// Level of the tile across each edge, or of this tile if that one is finer, in the order of
+    /// `Node::neighbor_probes`
+    stitches: [u32; 4],
+}
+
+impl Node {
+    /// The root of the quadtree of a face, which covers the whole face
+    pub(super) fn root(face: usize, terrain: &Terrain) -> Self {
+        Self::new(TileId { face, level: 0, x: 0, y: 0 }, terrain)
+    }
+
+    fn new(id: TileId, terrain: &Terrain) -> Self {
+        let (_, (u, v)) = id.corner_and_middle();
+        let middle = direction(id.face, u, v);
+        Self {
+            id,
+            middle: middle * terrain.radius_at(middle),
+            width: terrain.radius * FRAC_PI_2 / (1 << id.level) as f64,
+            children: None,
+            tile: None,
+        }
+    }
+
+    /// Split the nodes close to the camera and merge those which are far away. The camera is
+    /// relative to the center of the planet. Nodes which change lose their tiles.
+    pub(super) fn update_level(&mut self, terrain: &Terrain, camera: Vector3<f64>) {
+        let split = (camera - self.middle).magnitude() < SPLIT_DISTANCE * self.width
+            && self.width / (2 * TILE_SEGMENTS) as f64 >= FINEST_SPACING;
+        if !split {
+            self.children = None;
+            return;
+        }
+        self.tile = None;
+        let children = self.children.get_or_insert_with(|| {
+            Box::new(self.id.children().map(|id| Node::new(id, terrain)))
+        });
+        for child in children.iter_mut() {
+            child.update_level(terrain, camera);
+        }
+    }
+
+    /// Give a tile to each leaf which has none, and rebuild the tiles whose coarser neighbors have
+    /// changed level, so that their edges keep following them
+    pub(super) fn update_tiles(faces: &mut [Node; 6], graphics: &Graphics, terrain: &Terrain, material: &Material) {
+        let mut leaves = Vec::new();
+        for face in faces.iter() {
+            face.leaves(&mut leaves);
+        }
+        let changed = leaves.into_iter()
+            .filter_map(|leaf| {
+                let stitches = leaf.stitches(faces);
+                match &leaf.tile {
+                    Some(tile) if tile.stitches == stitches => None,
+                    _ => Some((leaf.id, stitches)),
+                }
+            })
+            .collect::<Vec<_>>();
+        for (id, stitches) in changed {
+            let node = faces[id.face].find_mut(id);
+            node.tile = Some(node.build_tile(graphics, terrain, material, stitches));
+        }
+    }
+
+    /// Move the tiles to where they are seen from the camera
+    pub(super) fn update_graphics(&self, graphics: &Graphics, camera: &Camera, center: Vector3<f64>) {
+        if let Some(tile) = &self.tile {
+            tile.object.update(graphics, ObjectUniform::new(camera, center + self.middle, Quaternion::new(1., 0., 0., 0.)));
+        }
+        for child in self.children.iter().flat_map(|children| children.iter()) {
+            child.update_graphics(graphics, camera, center);
+        }
+    }
+
+    pub(super) fn objects<'a>(&'a self, output: &mut Vec<ObjectHandle<'a>>) {
+        if let Some(tile) = &self.tile {
+            output.push(ObjectHandle::Ref(&tile.object));
+        }
+        for child in self.children.iter().flat_map(|children| children.iter()) {
+            child.objects(output);
+        }
+    }
+
+    fn leaves<'a>(&'a self, output: &mut Vec<&'a Node>) {
+        match &self.children {
+            Some(children) => children.iter().for_each(|child| child.leaves(output)),
+            None => output.push(self),
+        }
+    }
+
+    /// Index of the child which contains a point on the face
+    fn child_index(&self, u: f64, v: f64) -> usize {
+        let (_, (middle_u, middle_v)) = self.id.corner_and_middle();
+        (u >= middle_u) as usize + 2 * (v >= middle_v) as usize
+    }
+
+    /// Descend to a node below this one, which must exist
+    fn find_mut(&mut self, id: TileId) -> &mut Node {
+        let mut node = self;
+        while node.id != id {
+            let child = path_index(node.id, id);
+            node = &mut node.children.as_mut().expect("Node is not in the tree")[child];
+        }
+        node
+    }
+
+    /// Level of the leaf across each edge of this leaf, or of this leaf if that one is finer
+    fn stitches(&self, faces: &[Node; 6]) -> [u32; 4] {
+        self.neighbor_probes().map(|(u, v)| leaf(faces, direction(self.id.face, u, v)).id.level.min(self.id.level))
+    }
+
+    /// Face coordinates just across the middle of each edge of the node, in the order low v, high
+    /// u, high v and low u
+    fn neighbor_probes(&self) -> [(f64, f64); 4] {
+        let ((u0, v0), (um, vm)) = self.id.corner_and_middle();
+        let (u1, v1) = (2. * um - u0, 2. * vm - v0);
+        [
+            (um, v0 - NEIGHBOR_PROBE),
+            (u1 + NEIGHBOR_PROBE, vm),
+            (um, v1 + NEIGHBOR_PROBE),
+            (u0 - NEIGHBOR_PROBE, vm),
+        ]
+    }
+
+    /// Mesh the ground of the node
+    fn build_tile(&self, graphics: &Graphics, terrain: &Terrain, material: &Material, stitches: [u32; 4]) -> Tile {
+        let n = TILE_SEGMENTS as i64;
+        let index = |i: i64, j: i64| (j * (n + 1) + i) as u16;
+        let mut indices = Vec::with_capacity((6 * n * n) as usize);
+        for j in 0..n {
+            for i in 0..n {
+                indices.extend([
+                    index(i, j), index(i + 1, j), index(i + 1, j + 1),
+                    index(i, j), index(i + 1, j + 1), index(i, j + 1),
+                ]);
+            }
+        }
+
+        let vertices = self.vertices(terrain, stitches);
+        let model = Model::from_vertices_and_material(graphics, &vertices, &indices, material.clone());
+        Tile {
+            object: Object::zeroed::<ObjectUniform>(graphics, model),
+            stitches,
+        }
+    }
+
+    /// Vertices of the ground of the node, relative to its middle, with u increasing first. Along
+    /// edges whose neighbor is coarser, every vertex is moved onto the straight line between the
+    /// neighbor's vertices on either side, so that the edges meet exactly.
+    fn vertices(&self, terrain: &Terrain, stitches: [u32; 4]) -> Vec<TexVertex> {
+        let id = self.id;
+        let n = TILE_SEGMENTS as i64;
+        let (x0, y0) = (id.x as i64 * n, id.y as i64 * n);
+        let surface = |x: i64, y: i64| {
+            let d = direction(id.face, id.coordinate(x), id.coordinate(y));
+            d * terrain.radius_at(d)
+        };
+        // The ground at each vertex, with an extra ring around the tile to find the normals
+        let side = n + 3;
+        let ground = (-1..=n + 1)
+            .flat_map(|j| (-1..=n + 1).map(move |i| (i, j)))
+            .map(|(i, j)| surface(x0 + i, y0 + j))
+            .collect::<Vec<_>>();
+        let at = |i: i64, j: i64| ground[((j + 1) * side + i + 1) as usize];
+        // Position of a vertex on an edge along the grid of a neighbor `levels` coarser, where
+        // `along` is the index along the edge and `edge` places it on the edge
+        let stitch = |levels: u32, along: i64, edge: &dyn Fn(i64) -> Vector3<f64>| {
+            let r = 1 << levels;
+            let start = along.div_euclid(r) * r;
+            let t = (along - start) as f64 / r as f64;
+            edge(start) * (1. - t) + edge(start + r) * t
+        };
+
+        let mut vertices = Vec::with_capacity(((n + 1) * (n + 1)) as usize);
+        for j in 0..=n {
+            for i in 0..=n {
+                let mut position = at(i, j);
+                let levels = stitches.map(|level| id.level - level);
+                if j == 0 && levels[0] > 0 {
+                    position = stitch(levels[0], x0 + i, &|x| surface(x, y0));
+                }
+                if i == n && levels[1] > 0 {
+                    position = stitch(levels[1], y0 + j, &|y| surface(x0 + n, y));
+                }
+                if j == n && levels[2] > 0 {
+                    position = stitch(levels[2], x0 + i, &|x| surface(x, y0 + n));
+                }
+                if i == 0 && levels[3] > 0 {
+                    position = stitch(levels[3], y0 + j, &|y| surface(x0, y));
+                }
+                let along_u = at(i + 1, j) - at(i - 1, j);
+                let along_v = at(i, j + 1) - at(i, j - 1);
+                let normal = along_u.cross(along_v).normalize();
+                let tangent = along_u.normalize();
+                vertices.push(TexVertex {
+                    position: (position - self.middle).cast::<f32>().unwrap().into(),
+                    tex_coords: [i as f32 / n as f32, j as f32 / n as f32],
+                    normal: normal.cast::<f32>().unwrap().into(),
+                    tangent: [tangent.x as f32, tangent.y as f32, tangent.z as f32, 1.],
+                });
+            }
+        }
+        vertices
+    }
+}
+
+/// Index of the child of `node` on the way down to `target`
+fn path_index(node: TileId, target: TileId) -> usize {
+    let shift = target.level - node.level - 1;
+    ((target.x >> shift) % 2 + 2 * ((target.y >> shift) % 2)) as usize
+}
+
+/// Leaf which a direction from the center of the planet passes through
+fn leaf(faces: &[Node; 6], direction: Vector3<f64>) -> &Node {
+    let (face, u, v) = face_coordinates(direction);
+    let mut node = &faces[face];
+    while let Some(children) = &node.children {
+        node = &children[node.child_index(u, v)];
+    }
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Place of a vertex of a tile, relative to the center of the planet
+    fn position(node: &Node, vertex: &TexVertex) -> Vector3<f64> {
+        node.middle + Vector3::from(vertex.position).cast::<f64>().unwrap()
+    }
+
+    /// Grid indices of the vertices along an edge of a tile, in the order of `Node::neighbor_probes`
+    fn edge(edge: usize) -> impl Iterator<Item = (usize, usize)> {
+        let n = TILE_SEGMENTS as usize;
+        (0..=n).map(move |k| match edge {
+            0 => (k, 0),
+            1 => (n, k),
+            2 => (k, n),
+            _ => (0, k),
+        })
+    }
+
+    /// Distance from a point to the nearest segment of the outline of a tile
+    fn distance_to_outline(point: Vector3<f64>, node: &Node, vertices: &[TexVertex]) -> f64 {
+        let n = TILE_SEGMENTS as usize;
+        (0..4).flat_map(|side| {
+            let outline = edge(side).map(|(i, j)| position(node, &vertices[j * (n + 1) + i])).collect::<Vec<_>>();
+            outline.windows(2).map(|segment| {
+                let along = segment[1] - segment[0];
+                let t = ((point - segment[0]).dot(along) / along.magnitude2()).clamp(0., 1.);
+                (point - segment[0] - along * t).magnitude()
+            }).collect::<Vec<_>>()
+        }).fold(f64::INFINITY, f64::min)
+    }
+
+    #[test]
+    fn face_coordinates_undo_direction() {
+        for face in 0..6 {
+            for (u, v) in [(0., 0.), (0.5, -0.25), (-0.9, 0.9)] {
+                let (found, found_u, found_v) = face_coordinates(direction(face, u, v));
+                assert_eq!(found, face);
+                assert!((found_u - u).abs() < 1e-12 && (found_v - v).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn edges_follow_coarser_neighbors() {
+        let terrain = Terrain::new(1, 1000., 30., 400.);
+        let mut faces = std::array::from_fn(|face| Node::root(face, &terrain));
+        // Near a corner of the cube, so that tiles of different levels meet across faces
+        let camera = Vector3::new(1., 0.9, 0.8).normalize() * 1020.;
+        for face in &mut faces {
+            face.update_level(&terrain, camera);
+        }
+        let mut leaves = Vec::new();
+        for face in &faces {
+            face.leaves(&mut leaves);
+        }
+
+        let n = TILE_SEGMENTS as usize;
+        let (mut stitched, mut across_faces) = (0, 0);
+        for fine in leaves {
+            let vertices = fine.vertices(&terrain, fine.stitches(&faces));
+            for (side, (u, v)) in fine.neighbor_probes().into_iter().enumerate() {
+                let coarse = leaf(&faces, direction(fine.id.face, u, v));
+                if coarse.id.level >= fine.id.level {
+                    continue;
+                }
+                let coarse_vertices = coarse.vertices(&terrain, coarse.stitches(&faces));
+                for (i, j) in edge(side) {
+                    let distance = distance_to_outline(position(fine, &vertices[j * (n + 1) + i]), coarse, &coarse_vertices);
+                    assert!(distance < 1e-2, "{:?} is {distance} m from the edge of {:?}", fine.id, coarse.id);
+                }
+                stitched += 1;
+                across_faces += (coarse.id.face != fine.id.face) as usize;
+            }
+        }
+        assert!(stitched > 0 && across_faces > 0, "{stitched} edges were stitched, {across_faces} across faces");
+    }
+}
@@ -0,0 +1,41 @@
+use cgmath::Vector3;
+
+use crate::util::Perlin;
+
+/// Layers of noise in the ground, from the largest hills down to bumps 32 times smaller
+const OCTAVES: u32 = 6;
+/// Largest value of the noise, with a margin since its bound is only approximate
+const NOISE_BOUND: f64 = 1.1;
+
+/// Shape of the ground of a planet, as its distance from the center along each direction
+#[derive(Clone)]
+pub struct Terrain {
+    noise: Perlin,
+    /// Mean distance from the center to the ground
+    pub radius: f64,
+    /// About the greatest height of the ground above or below the mean radius
+    pub amplitude: f64,
+    /// Width of the largest hills
+    pub wavelength: f64,
+}
+
+impl Terrain {
+    pub fn new(seed: u64, radius: f64, amplitude: f64, wavelength: f64) -> Self {
+        Self {
+            noise: Perlin::new(seed),
+            radius,
+            amplitude,
+            wavelength,
+        }
+    }
+
+    /// Distance from the center to the ground along a unit vector
+    pub fn radius_at(&self, direction: Vector3<f64>) -> f64 {
+        self.radius + self.amplitude * self.noise.fractal(direction * (self.radius / self.wavelength), OCTAVES)
+    }
+
+    /// Distance from the center which the ground never reaches beyond
+    pub fn max_radius(&self) -> f64 {
+        self.radius + self.amplitude * NOISE_BOUND
+    }
+}
@@ -1,25 +1,44 @@
 struct CameraUniform {
     view_proj: mat4x4<f32>,
-    light_pos: vec4<f32>,
 };
 struct ObjectUniform {
     world: mat4x4<f32>,
     rot_mat: mat4x4<f32>,
 };
 struct MaterialUniform {
-    light_info: vec4<f32>
+    light_info: vec4<f32>,
+    base_color: vec4<f32>,
+    pbr_info: vec4<f32>,
+};
+struct DirectionalLight {
+    direction: vec4<f32>,
+    color: vec4<f32>,
+};
+struct PointLight {
+    position: vec4<f32>,
+    color: vec4<f32>,
+};
+struct LightUniform {
+    cascades: array<mat4x4<f32>, 4>,
+    directional: array<DirectionalLight, 4>,
+    point: array<PointLight, 16>,
+    counts: vec4<u32>,
+    irradiance: array<vec4<f32>, 9>,
 };
 
+const PI: f32 = 3.14159265;
+
 // Vertex shader
 @group(0) @binding(0)
 var<uniform> camera: CameraUniform;
 @group(1) @binding(0)
-var<uniform> model: ObjectUniform;
+var<storage, read> objects: array<ObjectUniform>;
 
 struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -27,16 +46,20 @@ struct VertexOutput {
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_position: vec4<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
 }
 
 @vertex
 fn vs_main(
     in: VertexInput,
+    @builtin(instance_index) instance: u32,
 ) -> VertexOutput {
+    let model = objects[instance];
     var out: VertexOutput;
     out.tex_coords = in.tex_coords;
     out.world_position = model.world * vec4<f32>(in.position, 1.0);
     out.normal = (model.rot_mat * vec4<f32>(in.normal, 1.)).xyz;
+    out.tangent = vec4((model.rot_mat * vec4<f32>(in.tangent.xyz, 1.)).xyz, in.tangent.w);
     if dot(-out.world_position.xyz, out.normal) < 0. {
         out.normal *= -1.;// Normal was backwards
     }
@@ -47,38 +70,151 @@ fn vs_main(
 
 /// Fragment shader
 @group(2) @binding(0)
-var t_diffuse: texture_2d<f32>;
+var t_albedo: texture_2d<f32>;
 @group(2) @binding(1)
-var s_diffuse: sampler;
+var s_albedo: sampler;
 @group(2) @binding(2)
 var t_normal: texture_2d<f32>;
 @group(2) @binding(3)
 var s_normal: sampler;
 @group(2) @binding(4)
+var t_metallic_roughness: texture_2d<f32>;
+@group(2) @binding(5)
+var s_metallic_roughness: sampler;
+@group(2) @binding(6)
+var t_occlusion: texture_2d<f32>;
+@group(2) @binding(7)
+var s_occlusion: sampler;
+@group(2) @binding(8)
 var<uniform> material: MaterialUniform;
+@group(3) @binding(0)
+var<uniform> lights: LightUniform;
+@group(3) @binding(1)
+var shadow_map: texture_depth_2d_array;
+@group(3) @binding(2)
+var shadow_sampler: sampler_comparison;
+@group(3) @binding(3)
+var environment_map: texture_cube<f32>;
+@group(3) @binding(4)
+var environment_sampler: sampler;
+
+/// Fraction of the sunlight which reaches a point, read from the most detailed cascade containing
+/// it and averaged over the nearest texels so that shadow edges are soft
+fn shadow(position: vec3<f32>) -> f32 {
+    let texel = 1. / vec2<f32>(textureDimensions(shadow_map));
+    for (var i = 0u; i < lights.counts.z; i++) {
+        let clip = lights.cascades[i] * vec4(position, 1.);
+        let uv = clip.xy * vec2(0.5, -0.5) + 0.5;
+        if all(uv >= vec2(0.)) && all(uv <= vec2(1.)) && clip.z <= 1. {
+            var lit = 0.;
+            for (var x = -1; x <= 1; x++) {
+                for (var y = -1; y <= 1; y++) {
+                    lit += textureSampleCompareLevel(shadow_map, shadow_sampler, uv + vec2(f32(x), f32(y)) * texel, i, clip.z);
+                }
+            }
+            return lit / 9.;
+        }
+    }
+    return 1.;
+}
+
+/// Diffuse light from the environment reaching a surface, divided by pi
+fn irradiance(n: vec3<f32>) -> vec3<f32> {
+    let c = lights.irradiance;
+    return (c[0] * 0.282095
+        + (c[1] * n.y + c[2] * n.z + c[3] * n.x) * 0.488603
+        + (c[4] * n.x * n.y + c[5] * n.y * n.z + c[7] * n.x * n.z) * 1.092548
+        + c[6] * 0.315392 * (3. * n.z * n.z - 1.)
+        + c[8] * 0.546274 * (n.x * n.x - n.y * n.y)).rgb;
+}
+
+/// Normal of the surface after the normal map. The map may only hold X and Y, so Z is rebuilt.
+fn surface_normal(in: VertexOutput) -> vec3<f32> {
+    let normal = normalize(in.normal);
+    let xy = textureSample(t_normal, s_normal, in.tex_coords).xy * 2. - 1.;
+    let z = sqrt(max(1. - dot(xy, xy), 0.));
+    let bitangent = in.tangent.w * cross(normal, in.tangent.xyz);
+    return normalize(in.tangent.xyz * xy.x + bitangent * xy.y + normal * z);
+}
+
+/// Fresnel reflectance at the angle whose cosine is given, from the reflectance head on
+fn fresnel(cos_theta: f32, f0: vec3<f32>) -> vec3<f32> {
+    return f0 + (1. - f0) * pow(1. - cos_theta, 5.);
+}
+
+/// Light reflected towards the camera by light of the given color arriving from `l`, with the
+/// GGX distribution and Smith shadowing
+fn reflected(n: vec3<f32>, v: vec3<f32>, l: vec3<f32>, color: vec3<f32>, albedo: vec3<f32>, f0: vec3<f32>, metallic: f32, roughness: f32) -> vec3<f32> {
+    let n_dot_l = dot(n, l);
+    if n_dot_l <= 0. {
+        return vec3(0.);
+    }
+    let h = normalize(v + l);
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let n_dot_h = max(dot(n, h), 0.);
+    let alpha = roughness * roughness;
+    let d = alpha * alpha / (PI * pow(n_dot_h * n_dot_h * (alpha * alpha - 1.) + 1., 2.));
+    let k = pow(roughness + 1., 2.) / 8.;
+    let g = n_dot_v / (n_dot_v * (1. - k) + k) * n_dot_l / (n_dot_l * (1. - k) + k);
+    let f = fresnel(max(dot(h, v), 0.), f0);
+    let specular = d * g * f / (4. * n_dot_v * n_dot_l);
+    let diffuse = (1. - f) * (1. - metallic) * albedo / PI;
+    return (diffuse + specular) * color * n_dot_l;
+}
+
+/// Scale and bias of the reflectance head on which gives the reflected light from the whole
+/// environment, fitted to the integral of the GGX lobe by Karis
+fn environment_brdf(n_dot_v: f32, roughness: f32) -> vec2<f32> {
+    let r = roughness * vec4(-1., -0.0275, -0.572, 0.022) + vec4(1., 0.0425, 1.04, -0.04);
+    let a = min(r.x * r.x, exp2(-9.28 * n_dot_v)) * r.x + r.y;
+    return vec2(-1.04, 1.04) * a + r.zw;
+}
 
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    // Lighting
+    let albedo = textureSample(t_albedo, s_albedo, in.tex_coords).rgb * material.base_color.rgb;
+    let metallic_roughness = textureSample(t_metallic_roughness, s_metallic_roughness, in.tex_coords);
+    let metallic = metallic_roughness.b * material.pbr_info.x;
+    // Perfectly smooth surfaces would reflect point lights as points which are never seen
+    let roughness = clamp(metallic_roughness.g * material.pbr_info.y, 0.04, 1.);
+    let occlusion = mix(1., textureSample(t_occlusion, s_occlusion, in.tex_coords).r, material.pbr_info.z);
+
     let pos = in.world_position.xyz;
-    let surface_to_light = normalize(camera.light_pos.xyz - pos);
-    let normal = in.normal;
-    let surface_to_light_dot_normal = dot(surface_to_light, normal);
-    if surface_to_light_dot_normal < 0. {
-        return vec4(0., 0., 0., 1.);
+    let n = surface_normal(in);
+    let v = normalize(-pos);
+    // Dielectrics reflect about 4% of light head on, and metals reflect their color
+    let f0 = mix(vec3(0.04), albedo, metallic);
+
+    var color = vec3(0.);
+    for (var i = 0u; i < lights.counts.x; i++) {
+        let light = lights.directional[i];
+        var light_color = light.color.rgb;
+        if i == 0u {
+            // Offset along the normal so that surfaces facing away from the sun do not shadow themselves
+            light_color *= shadow(pos + normalize(in.normal) * 0.02);
+        }
+        color += reflected(n, v, light.direction.xyz, light_color, albedo, f0, metallic, roughness);
+    }
+    for (var i = 0u; i < lights.counts.y; i++) {
+        let light = lights.point[i];
+        let to_light = light.position.xyz - pos;
+        let distance = length(to_light);
+        // Inverse square falloff, smoothly brought to zero at the range
+        let window = pow(clamp(1. - pow(distance / light.position.w, 4.), 0., 1.), 2.);
+        let light_color = light.color.rgb * window / max(distance * distance, 0.01);
+        color += reflected(n, v, to_light / distance, light_color, albedo, f0, metallic, roughness);
     }
 
-    let surface_to_reflect = 2 * surface_to_light_dot_normal * normal - surface_to_light;
-    let surface_to_camera = normalize(-pos);
-    let diffuse_color = vec3(1., 1., 1.) ;
-    let diffuse_coeff = 1.;
-    let power = material.light_info.z * 1.;
+    // Light from the environment
+    let n_dot_v = max(dot(n, v), 1e-4);
+    let f = fresnel(n_dot_v, f0);
+    let diffuse = (1. - f) * (1. - metallic) * albedo * irradiance(n);
+    let level = roughness * f32(textureNumLevels(environment_map) - 1);
+    let prefiltered = textureSampleLevel(environment_map, environment_sampler, reflect(-v, n), level).rgb;
+    let brdf = environment_brdf(n_dot_v, roughness);
+    let specular = prefiltered * (f0 * brdf.x + brdf.y);
+    color += (diffuse + specular) * occlusion;
 
-    let specular_color = vec3(1., 1., 1.);
-    let color = (
-        diffuse_coeff * diffuse_color * material.light_info.x * surface_to_light_dot_normal// Diffuse
-        // + diffuse_color * material.light_info.y * pow(max(dot(surface_to_camera, surface_to_reflect), 0.), power)// Specular
-    );
     let overage = max(color.r, 1.) + max(color.g, 1.) + max(color.b, 1.) - 3.;
     return vec4(
         min(color.r + overage, 1.),
@@ -86,4 +222,4 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
         min(color.b + overage, 1.),
         1.
     );
-}
\ No newline at end of file
+}
@@ -1,6 +1,5 @@
 struct CameraUniform {
     view_proj: mat4x4<f32>,
-    light_pos: vec4<f32>,
 };
 struct SolidUniform {
     world: mat4x4<f32>,
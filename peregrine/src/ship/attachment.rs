@@ -1,18 +1,11 @@
 use serde::{Deserialize, Serialize};
 use cgmath::{Quaternion, Vector3};
-use strum::FromRepr;
 use tethys::prelude::*;
 
+use super::manifest::AttachmentModel;
 use super::orientation;
 use super::part_loader::PartLoader;
 
-#[repr(usize)]
-#[derive(Clone, Copy, Debug, FromRepr)]
-pub enum AttachmentModel {
-    HydrogenHub,
-    CircuitHub,
-}
-
 /// The physical position of an entire part, or the blocks within a part
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct AttachmentLayout {
@@ -49,7 +42,7 @@ pub enum Attachment {
 }
 
 impl Attachment {
-    pub(super) fn get_objects(&self, part_loader: PartLoader, layout: AttachmentLayout) -> Object {
+    pub(super) fn get_object(&self, part_loader: PartLoader) -> Object {
 
         let model_type = match self {
             Attachment::HydrogenHub => AttachmentModel::HydrogenHub,
@@ -1,6 +1,9 @@
 use cgmath::Vector3;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+use super::Part;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
 pub enum Fluid {
     Electricity,
     Hydrogen
@@ -14,13 +17,205 @@ impl Fluid {
     }
 }
 
+/// A connected network of parts sharing one fluid. Quantities are in Joules for electricity and
+/// kilograms for hydrogen.
 pub struct Circuit {
     pub fluid: Fluid,
+    /// Indices of the parts in the network
     parts: Vec<usize>,
+    /// Pairs of attachment indices which make up the network
     pub connections: Vec<(usize, usize)>,
+    /// Amount of fluid held in the storage parts of the network
     quantity: f64,
 }
 
 impl Circuit {
-    
-}
\ No newline at end of file
+    /// Split a set of connections between attachments into connected circuits. Attachments
+    /// mounted on the same part are connected through that part.
+    pub fn build(fluid: Fluid, connections: &[(usize, usize)], attachment_parts: &[Option<usize>]) -> Vec<Circuit> {
+        let mut roots = (0..attachment_parts.len()).collect::<Vec<_>>();
+        for &(a, b) in connections {
+            union(&mut roots, a, b);
+        }
+        for a in 0..attachment_parts.len() {
+            let Some(part) = attachment_parts[a] else { continue };
+            if let Some(b) = attachment_parts.iter().position(|p| *p == Some(part)) {
+                union(&mut roots, a, b);
+            }
+        }
+
+        // Only attachments which are part of a connection form circuits
+        let mut circuits: Vec<(usize, Circuit)> = Vec::new();
+        for &(a, b) in connections {
+            let root = find(&mut roots, a);
+            let index = match circuits.iter().position(|(r, _)| *r == root) {
+                Some(index) => index,
+                None => {
+                    circuits.push((root, Circuit {
+                        fluid,
+                        parts: Vec::new(),
+                        connections: Vec::new(),
+                        quantity: 0.,
+                    }));
+                    circuits.len() - 1
+                },
+            };
+            let circuit = &mut circuits[index].1;
+            circuit.connections.push((a, b));
+            for attachment in [a, b] {
+                if let Some(part) = attachment_parts[attachment] {
+                    if !circuit.parts.contains(&part) {
+                        circuit.parts.push(part);
+                    }
+                }
+            }
+        }
+        circuits.into_iter().map(|(_, circuit)| circuit).collect()
+    }
+
+    pub fn parts(&self) -> &[usize] {
+        &self.parts
+    }
+
+    pub fn quantity(&self) -> f64 {
+        self.quantity
+    }
+
+    /// Total amount of fluid the circuit can store
+    pub fn capacity(&self, parts: &[Part]) -> f64 {
+        self.parts.iter().filter_map(|i| parts[*i].capacity(self.fluid)).sum()
+    }
+
+    /// Set the stored quantity to the given fraction of each storage part's capacity
+    pub fn fill(&mut self, parts: &[Part], fill_fraction: impl Fn(usize) -> f64) {
+        self.quantity = self.parts.iter()
+            .filter_map(|i| parts[*i].capacity(self.fluid).map(|c| c * fill_fraction(*i)))
+            .sum();
+    }
+
+    /// Fraction of the storage capacity which is full, or zero if there is no storage
+    pub fn fill_fraction(&self, parts: &[Part]) -> f64 {
+        let capacity = self.capacity(parts);
+        if capacity > 0. { self.quantity / capacity } else { 0. }
+    }
+
+    /// Balance supply against demand for one timestep.
+    ///
+    /// `activity` is how hard each part is working, between 0 and 1. `satisfaction` holds, for each
+    /// part, the fraction of its demand that was met. Producers only produce in proportion to their
+    /// own satisfaction, so a fuel cell without hydrogen generates no electricity. The satisfaction of
+    /// every consumer in this circuit is lowered to the fraction of the total demand that could be met.
+    pub fn step(&mut self, parts: &[Part], activity: &[f64], satisfaction: &mut [f64], delta_t: f64) {
+        let mut production = 0.;
+        let mut demand = 0.;
+        let mut max_discharge = 0.;
+        for &i in &self.parts {
+            let part = &parts[i];
+            let Some(rate) = part.typical_draw(self.fluid) else { continue };
+            if part.capacity(self.fluid).is_some() {
+                // Storage parts supply up to their rate and absorb any surplus
+                max_discharge += rate.abs();
+            } else if rate > 0. {
+                production += rate * satisfaction[i];
+            } else {
+                demand -= rate * activity[i];
+            }
+        }
+
+        let capacity = self.capacity(parts);
+        let surplus = production - demand;
+        let fraction = if surplus >= 0. {
+            self.quantity = (self.quantity + surplus * delta_t).min(capacity);
+            1.
+        } else {
+            let discharge = max_discharge.min(self.quantity / delta_t).min(-surplus);
+            self.quantity = (self.quantity - discharge * delta_t).max(0.);
+            (production + discharge) / demand
+        };
+
+        for &i in &self.parts {
+            let part = &parts[i];
+            if part.capacity(self.fluid).is_none() && part.typical_draw(self.fluid).is_some_and(|r| r < 0.) {
+                satisfaction[i] = satisfaction[i].min(fraction);
+            }
+        }
+    }
+}
+
+fn find(roots: &mut [usize], mut a: usize) -> usize {
+    while roots[a] != a {
+        roots[a] = roots[roots[a]];
+        a = roots[a];
+    }
+    a
+}
+
+fn union(roots: &mut [usize], a: usize, b: usize) {
+    let a = find(roots, a);
+    let b = find(roots, b);
+    roots[a] = b;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A battery with one attachment, and thrusters with an attachment each wired to it
+    fn battery_and_thrusters(thrusters: usize) -> (Vec<Part>, Vec<Circuit>) {
+        let parts = std::iter::once(Part::Battery).chain(std::iter::repeat_n(Part::Thruster, thrusters)).collect::<Vec<_>>();
+        let attachment_parts = (0..parts.len()).map(Some).collect::<Vec<_>>();
+        let connections = (1..parts.len()).map(|i| (0, i)).collect::<Vec<_>>();
+        let mut circuits = Circuit::build(Fluid::Electricity, &connections, &attachment_parts);
+        for circuit in &mut circuits {
+            circuit.fill(&parts, |_| 1.);
+        }
+        (parts, circuits)
+    }
+
+    #[test]
+    fn attachments_on_one_part_share_a_circuit() {
+        // The tank has two attachments, each piped to its own thruster. A third thruster is not piped.
+        let attachment_parts = [Some(0), Some(0), Some(1), Some(2), Some(3)];
+        let circuits = Circuit::build(Fluid::Hydrogen, &[(0, 2), (1, 3)], &attachment_parts);
+        assert_eq!(circuits.len(), 1);
+        let mut parts = circuits[0].parts().to_vec();
+        parts.sort();
+        assert_eq!(parts, vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn battery_satisfies_thruster() {
+        let (parts, mut circuits) = battery_and_thrusters(1);
+        assert_eq!(circuits.len(), 1);
+        let mut satisfaction = vec![1.; parts.len()];
+        circuits[0].step(&parts, &[1.; 2], &mut satisfaction, 1.);
+        assert_eq!(satisfaction, vec![1., 1.]);
+        assert_eq!(circuits[0].quantity(), 360_000. - 10.);
+    }
+
+    #[test]
+    fn demand_above_supply_is_shared() {
+        // Ten thrusters draw 100 W from a battery which supplies at most 50 W
+        let (parts, mut circuits) = battery_and_thrusters(10);
+        let mut satisfaction = vec![1.; parts.len()];
+        circuits[0].step(&parts, &[1.; 11], &mut satisfaction, 1.);
+        for &s in &satisfaction[1..] {
+            assert!((s - 0.5).abs() < 1e-12, "{s}");
+        }
+
+        // Idle thrusters draw nothing, so the rest are supplied in full
+        let activity = [[1.; 5].as_slice(), &[0.; 6]].concat();
+        let mut satisfaction = vec![1.; parts.len()];
+        circuits[0].step(&parts, &activity, &mut satisfaction, 1.);
+        assert!(satisfaction.iter().all(|&s| s == 1.));
+    }
+
+    #[test]
+    fn empty_battery_starves_thruster() {
+        let (parts, mut circuits) = battery_and_thrusters(1);
+        circuits[0].fill(&parts, |_| 0.);
+        let mut satisfaction = vec![1.; parts.len()];
+        circuits[0].step(&parts, &[1.; 2], &mut satisfaction, 1.);
+        assert_eq!(satisfaction[1], 0.);
+    }
+}
@@ -0,0 +1,62 @@
+//! The asset behind every part model, attachment model and panel material, named relative to the
+//! asset directory. Each asset is matched to its variant, so adding a variant without an asset does
+//! not compile and the order of the variants does not matter.
+//!
+//! `build.rs` includes this file to check every asset before the game is built, so it must not
+//! refer to anything else in the crate.
+
+use strum::{EnumCount, EnumIter, FromRepr};
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromRepr, EnumCount, EnumIter)]
+pub enum PartModel {
+    TankCap,
+    TankBody,
+    Scaffold,
+    Thruster,
+    FuelCell,
+    Battery,
+}
+
+impl PartModel {
+    pub fn asset(self) -> &'static str {
+        match self {
+            PartModel::TankCap => "parts/tank-cap",
+            PartModel::TankBody => "parts/tank-body",
+            PartModel::Scaffold => "parts/scaffold",
+            PartModel::Thruster => "parts/thruster",
+            PartModel::FuelCell => "parts/fuel-cell",
+            PartModel::Battery => "parts/battery",
+        }
+    }
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromRepr, EnumCount, EnumIter)]
+pub enum AttachmentModel {
+    HydrogenHub,
+    CircuitHub,
+}
+
+impl AttachmentModel {
+    pub fn asset(self) -> &'static str {
+        match self {
+            AttachmentModel::HydrogenHub => "attachments/hub-fluid",
+            AttachmentModel::CircuitHub => "attachments/hub-circuit",
+        }
+    }
+}
+
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, FromRepr, EnumCount, EnumIter)]
+pub enum PanelMaterial {
+    Metal,
+}
+
+impl PanelMaterial {
+    pub fn asset(self) -> &'static str {
+        match self {
+            PanelMaterial::Metal => "panels/metal",
+        }
+    }
+}
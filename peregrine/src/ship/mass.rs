@@ -0,0 +1,80 @@
+use cgmath::{Matrix3, SquareMatrix, Vector3, Zero};
+
+use super::{Panel, PanelLayout, Part, PartLayout};
+
+/// Running totals of the mass moments of a ship, expressed in the grid frame. Parts are modelled
+/// as uniform unit cubes and panels as uniform thin triangles.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct MassProperties {
+    mass: f64,
+    /// Sum of `m r`
+    first_moment: Vector3<f64>,
+    /// Sum of `m r r^T`
+    second_moment: Matrix3<f64>,
+}
+
+impl MassProperties {
+    pub fn new() -> Self {
+        Self {
+            mass: 0.,
+            first_moment: Vector3::zero(),
+            second_moment: Matrix3::zero(),
+        }
+    }
+
+    pub fn mass(&self) -> f64 {
+        self.mass
+    }
+
+    /// Add a uniform unit cube centred on `center`
+    pub fn add_cube(&mut self, mass: f64, center: Vector3<f64>) {
+        self.mass += mass;
+        self.first_moment += center * mass;
+        self.second_moment += (outer(center, center) + Matrix3::identity() / 12.) * mass;
+    }
+
+    /// Add a uniform thin triangle
+    pub fn add_triangle(&mut self, mass: f64, vertices: [Vector3<f64>; 3]) {
+        let sum = vertices[0] + vertices[1] + vertices[2];
+        self.mass += mass;
+        self.first_moment += sum * (mass / 3.);
+        self.second_moment += (outer(vertices[0], vertices[0])
+            + outer(vertices[1], vertices[1])
+            + outer(vertices[2], vertices[2])
+            + outer(sum, sum)) * (mass / 12.);
+    }
+
+    /// Add a part, with its mass shared evenly between its blocks
+    pub fn add_part(&mut self, part: &Part, layout: PartLayout) {
+        let blocks = part.get_blocks(layout);
+        let block_mass = part.mass() / blocks.len() as f64;
+        for block in blocks {
+            self.add_cube(block_mass, block.as_physical().0);
+        }
+    }
+
+    pub fn add_panel(&mut self, panel: &Panel, layout: PanelLayout) {
+        self.add_triangle(panel.mass(layout), layout.as_physical());
+    }
+
+    /// Center of mass in the grid frame, or the origin if there is no mass
+    pub fn center_of_mass(&self) -> Vector3<f64> {
+        if self.mass > 0. {
+            self.first_moment / self.mass
+        } else {
+            Vector3::zero()
+        }
+    }
+
+    /// Inertia tensor about the center of mass
+    pub fn inertia(&self) -> Matrix3<f64> {
+        let com = self.center_of_mass();
+        let covariance = self.second_moment - outer(com, com) * self.mass;
+        let trace = covariance.x.x + covariance.y.y + covariance.z.z;
+        Matrix3::identity() * trace - covariance
+    }
+}
+
+fn outer(a: Vector3<f64>, b: Vector3<f64>) -> Matrix3<f64> {
+    Matrix3::from_cols(a * b.x, a * b.y, a * b.z)
+}
@@ -1,30 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use cgmath::{Rotation, Vector3};
+use anyhow::{bail, Context, Result};
+use cgmath::{Matrix3, Quaternion, Rotation, SquareMatrix, Vector3};
 use part::Block;
+use strum::EnumCount;
 use serde::{Deserialize, Serialize};
-use tethys::{physics::collisions::{ColliderPackage, GridCollider}, prelude::*};
+use tethys::{physics::{collisions::{ColliderPackage, GridCollider}, resolve_collision, ContactMaterial}, prelude::*};
 
 mod part;
 mod attachment;
 mod panel;
 mod grid;
 mod circuit;
+mod mass;
+mod thruster;
 pub mod orientation;
 mod part_loader;
+pub mod manifest;
 
 pub use part_loader::{PartData, PartLoader};
 pub use part::{Part, PartLayout};
 pub use panel::{Panel, PanelLayout};
-pub use attachment::{Attachment, AttachmentModel, AttachmentLayout};
+pub use attachment::{Attachment, AttachmentLayout};
 pub use grid::*;
 pub use circuit::{Circuit, Fluid};
+pub use thruster::ThrusterGroup;
 
 use crate::util::Save;
+use mass::MassProperties;
 
 /// This is the maximum number of parts, because the panel index will start to take over from here
 const PANEL_START_INDEX: usize = 65536;
 
+/// Distance from an attachment within which a cell is considered to hold it
+const ATTACHMENT_TOLERANCE: f64 = 0.01;
+
 /// Contains the data of a single ship, including its internal components, its hull model, its 
 /// physics data, and its simulated properties
 pub struct ShipInterior {
@@ -35,79 +45,268 @@ pub struct ShipInterior {
     pub attachments: Vec<Attachment>,
     pub attachment_layouts: Vec<AttachmentLayout>,
     pub connections: HashMap<Fluid, Vec<(usize,usize)>>,
+    pub circuits: Vec<Circuit>,
+    /// Fraction of each part's fluid demand which was met during the last update
+    satisfaction: Vec<f64>,
 
     // Physics
     pub rigid_body: RigidBody,
     pub collider: Collider,
+    mass_properties: MassProperties,
+
+    // Controls
+    /// Throttle of each thruster group, between 0 and 1
+    throttles: [f64; ThrusterGroup::COUNT],
     
     // Graphics
     panel_objects: Vec<Object>,
     part_objects: Vec<Block>,
+    attachment_objects: Vec<Object>,
 }
 
 impl ShipInterior {
     pub fn new(loader: PartLoader, template: SaveShipInterior) -> Self {
+        Self::assemble(template, Some(loader))
+    }
+
+    /// Build the ship from a save. Without a loader the ship has no models, which is enough to
+    /// check its physics and circuits.
+    fn assemble(template: SaveShipInterior, loader: Option<PartLoader>) -> Self {
         let mut part_objects = Vec::with_capacity(template.parts.len());
         let mut panel_objects = Vec::with_capacity(template.panels.len());
         let mut grid = GridCollider::new();
+        let mut mass_properties = MassProperties::new();
         for (i, (part, layout)) in template.parts.iter().zip(&template.part_layouts).enumerate() {
-            part_objects.append(&mut part.get_objects(loader.clone(), *layout));
+            if let Some(loader) = &loader {
+                part_objects.append(&mut part.get_objects(loader.clone(), *layout));
+            }
             add_part_to_grid(&mut grid, part, *layout, i);
+            mass_properties.add_part(part, *layout);
         }
         for (i, (panel, layout)) in template.panels.iter().zip(&template.panel_layouts).enumerate() {
-            if let Some(object) = panel.get_object(loader.clone(), *layout) {
+            if let Some(object) = loader.as_ref().and_then(|loader| panel.get_object(loader.clone(), *layout)) {
                 panel_objects.push(object);
             }
             add_panel_to_grid(&mut grid, *layout, (PANEL_START_INDEX + i) as isize);
+            mass_properties.add_panel(panel, *layout);
         }
-        Self {
+        let attachment_objects = match &loader {
+            Some(loader) => template.attachments.iter().map(|attachment| attachment.get_object(loader.clone())).collect(),
+            None => Vec::new(),
+        };
+        let mut ship = Self {
             parts: template.parts,
             part_layouts: template.part_layouts,
             collider: Collider::Grid(grid),
             rigid_body: template.rigid_body,
+            mass_properties,
+            throttles: [0.; ThrusterGroup::COUNT],
             panels: template.panels,
             panel_layouts: template.panel_layouts,
             part_objects,
             panel_objects,
-            connections: HashMap::new(),
-            attachments: Vec::new(),
-            attachment_layouts: Vec::new(),
+            attachment_objects,
+            connections: template.connections.into_iter().collect(),
+            circuits: Vec::new(),
+            satisfaction: Vec::new(),
+            attachments: template.attachments,
+            attachment_layouts: template.attachment_layouts,
+        };
+        ship.update_mass();
+        ship.rebuild_circuits();
+        ship
+    }
+
+    /// Produce a save of the ship which builds back into the same design. Storage is saved full.
+    pub fn to_save(&self) -> SaveShipInterior {
+        SaveShipInterior {
+            parts: self.parts.clone(),
+            part_layouts: self.part_layouts.clone(),
+            panels: self.panels.clone(),
+            panel_layouts: self.panel_layouts.clone(),
+            attachments: self.attachments.clone(),
+            attachment_layouts: self.attachment_layouts.clone(),
+            rigid_body: self.rigid_body.clone(),
+            connections: self.connections.iter().map(|(fluid, connections)| (*fluid, connections.clone())).collect(),
         }
     }
 
+    /// Copy the mass, center of mass and inertia tensor of the placed parts into the rigid body
+    fn update_mass(&mut self) {
+        // Placement previews can be empty, in which case the rigid body is left alone
+        if self.mass_properties.mass() <= 0. {
+            return;
+        }
+        self.rigid_body.mass = self.mass_properties.mass();
+        self.rigid_body.moi = self.mass_properties.inertia();
+        self.rigid_body.set_com(self.mass_properties.center_of_mass());
+    }
+
     /// Update all the objects within the ship according to the physics component
     pub fn update(&mut self, graphics: &Graphics, camera: &Camera, delta_t: f64) {
+        self.update_circuits(delta_t);
+        self.apply_thrust();
         self.rigid_body.update(delta_t);
         self.update_graphics(graphics, camera);
     }
 
+    /// Push the ship out of something which does not move, like the ground, if they touch
+    pub(crate) fn collide_with_static(&mut self, other: ColliderPackage) {
+        let report = Collider::check_intersection(self.collider_package(), other);
+        if report.collision() {
+            resolve_collision(&report, Some(&mut self.rigid_body), None, ContactMaterial::default());
+        }
+    }
+
+    pub fn throttle(&self, group: ThrusterGroup) -> f64 {
+        self.throttles[group as usize]
+    }
+
+    /// Set the throttle of a thruster group. The throttle is clamped between 0 and 1.
+    pub fn set_throttle(&mut self, group: ThrusterGroup, throttle: f64) {
+        self.throttles[group as usize] = throttle.clamp(0., 1.);
+    }
+
+    /// Accumulate the force and torque of every thruster into the rigid body
+    fn apply_thrust(&mut self) {
+        for (index, (part, layout)) in self.parts.iter().zip(&self.part_layouts).enumerate() {
+            let Some(max_thrust) = part.max_thrust() else { continue };
+            let direction = thruster::thrust_direction(*layout);
+            let throttle = self.throttles[ThrusterGroup::from_direction(direction) as usize]
+                * self.satisfaction.get(index).copied().unwrap_or(0.);
+            if throttle == 0. {
+                continue;
+            }
+            let force = self.rigid_body.orientation.rotate_vector(direction * max_thrust * throttle);
+            let (position, _) = layout.as_physical();
+            self.rigid_body.apply_force_at(force, self.rigid_body.to_global(position));
+        }
+    }
+
+    /// Fraction of a part's fluid demand which was met during the last update
+    pub fn satisfaction(&self, part: usize) -> f64 {
+        self.satisfaction.get(part).copied().unwrap_or(0.)
+    }
+
+    /// Run every circuit for one timestep. Hydrogen is solved first so that fuel cells only generate
+    /// electricity when they are supplied.
+    fn update_circuits(&mut self, delta_t: f64) {
+        let activity = self.parts.iter().zip(&self.part_layouts).map(|(part, layout)| match part {
+            Part::Thruster => self.throttles[ThrusterGroup::from_direction(thruster::thrust_direction(*layout)) as usize],
+            _ => 1.,
+        }).collect::<Vec<_>>();
+
+        self.satisfaction.clear();
+        self.satisfaction.resize(self.parts.len(), 1.);
+        for fluid in [Fluid::Hydrogen, Fluid::Electricity] {
+            // Consumers which are not connected to any circuit get nothing
+            for (i, part) in self.parts.iter().enumerate() {
+                let consumes = part.capacity(fluid).is_none() && part.typical_draw(fluid).is_some_and(|r| r < 0.);
+                if consumes && !self.circuits.iter().any(|c| c.fluid == fluid && c.parts().contains(&i)) {
+                    self.satisfaction[i] = 0.;
+                }
+            }
+            for circuit in self.circuits.iter_mut().filter(|c| c.fluid == fluid) {
+                circuit.step(&self.parts, &activity, &mut self.satisfaction, delta_t);
+            }
+        }
+    }
+
+    /// Find the part an attachment is mounted on
+    pub fn attachment_part(&self, attachment: usize) -> Option<usize> {
+        let (pos, _) = self.attachment_layouts[attachment].as_physical();
+        let grid = self.collider.get_grid_collider().unwrap();
+        // Attachments sit on the faces of cells, so look at every cell touching the point
+        for dx in [-ATTACHMENT_TOLERANCE, ATTACHMENT_TOLERANCE] {
+            for dy in [-ATTACHMENT_TOLERANCE, ATTACHMENT_TOLERANCE] {
+                for dz in [-ATTACHMENT_TOLERANCE, ATTACHMENT_TOLERANCE] {
+                    let entry = grid.get_entry(
+                        (pos.x + dx).floor() as i32,
+                        (pos.y + dy).floor() as i32,
+                        (pos.z + dz).floor() as i32,
+                    );
+                    if entry >= 0 && (entry as usize) < PANEL_START_INDEX {
+                        return Some(entry as usize);
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// Rebuild the circuits after the connections have changed, keeping how full each storage part is
+    fn rebuild_circuits(&mut self) {
+        let mut fill = HashMap::new();
+        for circuit in &self.circuits {
+            let fraction = circuit.fill_fraction(&self.parts);
+            for part in circuit.parts() {
+                fill.insert((circuit.fluid, *part), fraction);
+            }
+        }
+
+        let attachment_parts = (0..self.attachments.len()).map(|i| self.attachment_part(i)).collect::<Vec<_>>();
+        self.circuits.clear();
+        for (fluid, connections) in &self.connections {
+            for mut circuit in Circuit::build(*fluid, connections, &attachment_parts) {
+                // Storage which was not connected before starts full
+                circuit.fill(&self.parts, |part| fill.get(&(*fluid, part)).copied().unwrap_or(1.));
+                self.circuits.push(circuit);
+            }
+        }
+    }
+
+    /// Add an attachment and return its index
+    pub(crate) fn add_attachment(&mut self, loader: PartLoader, attachment: Attachment, layout: AttachmentLayout) -> usize {
+        self.attachments.push(attachment);
+        self.attachment_layouts.push(layout);
+        self.attachment_objects.push(attachment.get_object(loader));
+        self.attachments.len() - 1
+    }
+
+    /// Connect two attachments with a pipe or wire carrying `fluid`
+    pub(crate) fn add_connection(&mut self, fluid: Fluid, a: usize, b: usize) {
+        self.connections.entry(fluid).or_default().push((a, b));
+        self.rebuild_circuits();
+    }
+
     /// Update all the objects within the ship according to the physics component
     pub fn update_graphics(&self, graphics: &Graphics, camera: &Camera) {
         for block in &self.part_objects {
             let (position, orientation) = block.layout.as_physical();
             block.object.update(graphics, ObjectUniform::new(
                 camera,
-                self.rigid_body.pos + self.rigid_body.orientation.rotate_vector(position),
+                self.rigid_body.to_global(position),
                 self.rigid_body.orientation * orientation
             ));
         }
         for object in &self.panel_objects {
             object.update(graphics, ObjectUniform::new(
                 camera,
-                self.rigid_body.pos,
+                self.rigid_body.to_global(Vector3::new(0., 0., 0.)),
                 self.rigid_body.orientation
             ));
         }
+        for (object, layout) in self.attachment_objects.iter().zip(&self.attachment_layouts) {
+            let (position, orientation) = layout.as_physical();
+            object.update(graphics, ObjectUniform::new(
+                camera,
+                self.rigid_body.to_global(position),
+                self.rigid_body.orientation * orientation
+            ));
+        }
     }
     
     pub fn objects(&self) -> Vec<ObjectHandle<'_>> {
-        let mut output = Vec::with_capacity(self.parts.len() + self.panels.len());
+        let mut output = Vec::with_capacity(self.part_objects.len() + self.panel_objects.len() + self.attachment_objects.len());
         for block in &self.part_objects {
             output.push(ObjectHandle::Ref(&block.object));
         }
         for object in &self.panel_objects {
             output.push(ObjectHandle::Ref(object));
         }
+        for object in &self.attachment_objects {
+            output.push(ObjectHandle::Ref(object));
+        }
         output
     }
 
@@ -149,6 +348,8 @@ impl ShipInterior {
         let mut objects = part.get_objects(part_loader.clone(), layout);
         self.part_objects.append(&mut objects);
         add_part_to_grid(self.collider.get_grid_collider_mut().unwrap(), &part, layout, part_index);
+        self.mass_properties.add_part(&part, layout);
+        self.update_mass();
     }
     
     pub(crate) fn add_panel(&mut self, loader: PartLoader, panel: Panel, layout: PanelLayout) {
@@ -160,6 +361,8 @@ impl ShipInterior {
         }
         let grid = self.collider.get_grid_collider_mut().unwrap();
         add_panel_to_grid(grid, layout, panel_index);
+        self.mass_properties.add_panel(&panel, layout);
+        self.update_mass();
     }
 }
 
@@ -170,15 +373,88 @@ pub struct SaveShipInterior {
     pub part_layouts: Vec<PartLayout>,
     pub panels: Vec<Panel>,
     pub panel_layouts: Vec<PanelLayout>,
+    #[serde(default)]
     pub attachments: Vec<Attachment>,
+    #[serde(default)]
     pub attachment_layouts: Vec<AttachmentLayout>,
+    /// Mass properties are recalculated from the parts, so blueprints only need the position and motion
+    #[serde(default)]
     pub rigid_body: RigidBody,
+    /// Pairs of attachment indices joined by a pipe or wire of each fluid. This must stay the last
+    /// field for the version 1 migration.
+    #[serde(default)]
+    pub connections: BTreeMap<Fluid, Vec<(usize, usize)>>,
 }
 
 impl Save<ShipInterior, PartLoader<'_>> for SaveShipInterior {
+    const MAGIC: [u8; 4] = *b"PGSH";
+    const VERSION: u32 = 2;
+
     fn build(self, loader: PartLoader) -> ShipInterior {
         ShipInterior::new(loader, self)
     }
+
+    fn migrate(version: u32, mut payload: Vec<u8>) -> Result<Vec<u8>> {
+        match version {
+            // Saves without a header had the old rigid body, whose angular velocity was a
+            // quaternion and whose inertia was only the diagonal. Only the position and motion are
+            // kept, since the mass properties are recalculated from the parts.
+            0 => {
+                let legacy: LegacySaveShipInterior = bincode::deserialize(&payload)
+                    .context("Could not decode legacy unversioned ship save")?;
+                let body = legacy.rigid_body;
+                let rigid_body = RigidBody::new(
+                    body.pos.into(),
+                    body.vel.into(),
+                    Quaternion::new(body.orientation[0], body.orientation[1], body.orientation[2], body.orientation[3]),
+                    Vector3::new(body.angvel[1], body.angvel[2], body.angvel[3]),
+                    body.mass,
+                    Matrix3::from_diagonal(Vector3::new(body.moi.0, body.moi.1, body.moi.2)),
+                );
+                Ok(bincode::serialize(&(
+                    legacy.parts,
+                    legacy.part_layouts,
+                    legacy.panels,
+                    legacy.panel_layouts,
+                    legacy.attachments,
+                    legacy.attachment_layouts,
+                    rigid_body,
+                ))?)
+            },
+            // Version 1 had no connections, so append an empty map. Bincode writes a map as its
+            // length followed by the entries.
+            1 => {
+                payload.extend_from_slice(&0u64.to_le_bytes());
+                Ok(payload)
+            },
+            _ => bail!("No migration from ship save version {version}"),
+        }
+    }
+}
+
+/// Layout of ship saves from before they had a header
+#[derive(Deserialize)]
+struct LegacySaveShipInterior {
+    parts: Vec<Part>,
+    part_layouts: Vec<PartLayout>,
+    panels: Vec<Panel>,
+    panel_layouts: Vec<PanelLayout>,
+    attachments: Vec<Attachment>,
+    attachment_layouts: Vec<AttachmentLayout>,
+    rigid_body: LegacyRigidBody,
+}
+
+#[derive(Deserialize)]
+struct LegacyRigidBody {
+    pos: [f64; 3],
+    orientation: [f64; 4],
+    vel: [f64; 3],
+    /// Angular velocity in the vector part of a quaternion
+    angvel: [f64; 4],
+    _force: [f64; 3],
+    _torque: [f64; 4],
+    mass: f64,
+    moi: (f64, f64, f64),
 }
 
 impl Default for SaveShipInterior {
@@ -191,6 +467,98 @@ impl Default for SaveShipInterior {
             attachments: Vec::new(),
             attachment_layouts: Vec::new(),
             rigid_body: RigidBody::default(),
+            connections: BTreeMap::new(),
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Prefix a payload with the header of a ship save
+    fn with_header(version: u32, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = SaveShipInterior::MAGIC.to_vec();
+        bytes.extend_from_slice(&version.to_le_bytes());
+        bytes.extend_from_slice(&crc32fast::hash(payload).to_le_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    fn parts() -> (Vec<Part>, Vec<PartLayout>) {
+        (vec![Part::Tank { length: 3 }, Part::Thruster], vec![
+            PartLayout { x: 0, y: 0, z: 0, orientation: 0 },
+            PartLayout { x: -2, y: 0, z: 0, orientation: 0 },
+        ])
+    }
+
+    #[test]
+    fn migrate_version_1() {
+        let (parts, part_layouts) = parts();
+        let rigid_body = RigidBody { pos: Vector3::new(1., 2., 3.), ..Default::default() };
+        let payload = bincode::serialize(&(
+            &parts, &part_layouts, Vec::<Panel>::new(), Vec::<PanelLayout>::new(),
+            Vec::<Attachment>::new(), Vec::<AttachmentLayout>::new(), &rigid_body,
+        )).unwrap();
+        let save = SaveShipInterior::decode_bytes(&with_header(1, &payload)).unwrap();
+        assert_eq!(save.parts.len(), 2);
+        assert_eq!(save.rigid_body.pos, rigid_body.pos);
+        assert!(save.connections.is_empty());
+    }
+
+    #[test]
+    fn migrate_legacy_save() {
+        let (parts, part_layouts) = parts();
+        let rigid_body = (
+            [1., 2., 3.], [1., 0., 0., 0.], [4., 5., 6.], [0., 0., 0., 0.5],
+            [0., 0., 0.], [0., 0., 0., 0.], 10., (1., 1., 1.),
+        );
+        let payload = bincode::serialize(&(
+            &parts, &part_layouts, Vec::<Panel>::new(), Vec::<PanelLayout>::new(),
+            Vec::<Attachment>::new(), Vec::<AttachmentLayout>::new(), rigid_body,
+        )).unwrap();
+        let save = SaveShipInterior::decode_bytes(&payload).unwrap();
+        assert_eq!(save.parts.len(), 2);
+        assert_eq!(save.rigid_body.pos, Vector3::new(1., 2., 3.));
+        assert_eq!(save.rigid_body.vel, Vector3::new(4., 5., 6.));
+        assert_eq!(save.rigid_body.angvel, Vector3::new(0., 0., 0.5));
+    }
+
+    #[test]
+    fn current_version_round_trip() {
+        let (parts, part_layouts) = parts();
+        let mut connections = BTreeMap::new();
+        connections.insert(Fluid::Hydrogen, vec![(0, 1)]);
+        let save = SaveShipInterior { parts, part_layouts, connections, ..Default::default() };
+        let decoded = SaveShipInterior::decode_bytes(&save.to_bytes().unwrap()).unwrap();
+        assert_eq!(decoded.connections, save.connections);
+    }
+
+    #[test]
+    fn unconnected_thruster_is_starved() {
+        let (parts, part_layouts) = parts();
+        let mut ship = ShipInterior::assemble(SaveShipInterior { parts, part_layouts, ..Default::default() }, None);
+        for group in (0..ThrusterGroup::COUNT).filter_map(ThrusterGroup::from_repr) {
+            ship.set_throttle(group, 1.);
         }
+        ship.update_circuits(0.01);
+        assert_eq!(ship.satisfaction(1), 0.);
+        ship.apply_thrust();
+        assert_eq!(ship.rigid_body.force, Vector3::new(0., 0., 0.));
     }
-}
\ No newline at end of file
+
+    /// The starter blueprint feeds its thrusters with hydrogen and electricity, so it can fly
+    #[test]
+    fn starter_ship_has_thrust() {
+        let template = ron::from_str(include_str!("../../assets/ships/starter.ron")).unwrap();
+        let mut ship = ShipInterior::assemble(template, None);
+        ship.set_throttle(ThrusterGroup::Forward, 1.);
+        ship.update_circuits(0.01);
+        ship.apply_thrust();
+        for (i, part) in ship.parts.iter().enumerate() {
+            if let Part::Thruster = part {
+                assert_eq!(ship.satisfaction(i), 1., "thruster {i} is not supplied");
+            }
+        }
+        assert!(ship.rigid_body.force.x > 0., "the ship is not pushed forward: {:?}", ship.rigid_body.force);
+    }
+}
@@ -1,8 +1,8 @@
 use cgmath::{InnerSpace, Vector3};
 use serde::{Deserialize, Serialize};
-use strum::FromRepr;
 use tethys::prelude::*;
 
+use super::manifest::PanelMaterial;
 use super::PartLoader;
 
 
@@ -10,13 +10,42 @@ use super::PartLoader;
 pub struct PanelLayout {
     pub vertices: [(i32, i32, i32); 3],
 }
+impl PanelLayout {
+    pub fn as_physical(&self) -> [Vector3<f64>; 3] {
+        self.vertices.map(|(x, y, z)| Vector3::new(x as f64, y as f64, z as f64))
+    }
+
+    /// Area of the panel in m^2
+    pub fn area(&self) -> f64 {
+        let [a, b, c] = self.as_physical();
+        (b - a).cross(c - a).magnitude() / 2.
+    }
+}
 
-#[repr(usize)]
-#[derive(Clone, Copy, Debug, Serialize, FromRepr, Deserialize)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Panel {
     Metal,
 }
 impl Panel {
+    /// Material the panel is drawn with
+    pub fn material(&self) -> PanelMaterial {
+        match self {
+            Panel::Metal => PanelMaterial::Metal,
+        }
+    }
+
+    /// Areal density of the panel in kg/m^2
+    pub fn density(&self) -> f64 {
+        match self {
+            Panel::Metal => 20.,
+        }
+    }
+
+    /// Total mass of the panel in kg
+    pub fn mass(&self, layout: PanelLayout) -> f64 {
+        self.density() * layout.area()
+    }
+
     pub(crate) fn get_object(&self, loader: PartLoader, layout: PanelLayout) -> Option<Object> {
         // Do not try to make an object for a panel that is actually a line
         if layout.vertices[0] == layout.vertices[1] || layout.vertices[0] == layout.vertices[2] || layout.vertices[1] == layout.vertices[2] {return None;}
@@ -48,9 +77,11 @@ impl Panel {
                 position: [v.0 as f32, v.1 as f32, v.2 as f32],
                 tex_coords: [offset.dot(tangent_x), offset.dot(tangent_y)],// TODO
                 normal: [normal.x, normal.y, normal.z],
+                // The V axis is tangent_y, which is the opposite of normal x tangent_x
+                tangent: [tangent_x.x, tangent_x.y, tangent_x.z, -1.],
             }
         }).collect::<Vec<_>>();
-        let indices = [0, 1, 2, 0, 2, 1];
+        let indices: [u16; 6] = [0, 1, 2, 0, 2, 1];
         let model = Model::from_vertices_and_material(&loader.graphics, &vertices, &indices, material);
         Some(Object::zeroed::<ObjectUniform>(&loader.graphics, model))
     }
@@ -1,10 +1,11 @@
 use std::ops::Mul;
 use cgmath::{Quaternion, Vector3};
 use serde::{Deserialize, Serialize};
-use strum::FromRepr;
 use tethys::prelude::*;
 
+use super::manifest::PartModel;
 use super::part_loader::PartLoader;
+use super::Fluid;
 use super::orientation;
 
 pub(super) struct Block {
@@ -50,17 +51,6 @@ impl Mul for PartLayout {
     }
 }
 
-#[repr(usize)]
-#[derive(Clone, Copy, Debug, FromRepr)]
-pub enum PartModel {
-    TankCap,
-    TankBody,
-    Scaffold,
-    Thruster,
-    FuelCell,
-    Battery,
-}
-
 #[allow(dead_code)]
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub enum Part {
@@ -199,7 +189,36 @@ impl Part {
         )
     }
 
-    /// Return power drawn in Watts (a negative number indicates draw, while a positive number is power generation). Returns None if object does not participate in the power system
+    /// Density of each block of the part in kg/m^3
+    pub fn density(&self) -> f64 {
+        match self {
+            Part::Tank { .. } => 150.,
+            Part::Scaffold { .. } => 50.,
+            Part::Thruster => 600.,
+            Part::FuelCell => 800.,
+            Part::Battery => 2000.,
+        }
+    }
+
+    /// Total mass of the part in kg. Each block occupies one cubic meter.
+    pub fn mass(&self) -> f64 {
+        let volume = match self {
+            Part::Tank { length } => *length,
+            Part::Scaffold { length, width, height } => length * width * height,
+            Part::Thruster | Part::FuelCell | Part::Battery => 1,
+        };
+        self.density() * volume as f64
+    }
+
+    /// Return the thrust in Newtons at full throttle. Returns None if the part cannot produce thrust
+    pub fn max_thrust(&self) -> Option<f64> {
+        match self {
+            Part::Thruster => Some(2000.),
+            _ => None,
+        }
+    }
+
+    /// Return power drawn in Watts (a negative number indicates draw, while a positive number is power generation). For storage parts this is the maximum discharge rate. Returns None if object does not participate in the power system
     pub fn typical_power_draw(&self) -> Option<f64> {
         match self {
             Part::Tank { .. } => None,
@@ -209,4 +228,33 @@ impl Part {
             Part::Battery => Some(50.),
         }
     }
+
+    /// Return hydrogen drawn in kg/s, with the same sign convention as `typical_power_draw`. Tanks are
+    /// not limited in how fast they can be drained. Returns None if object does not use hydrogen
+    pub fn typical_hydrogen_draw(&self) -> Option<f64> {
+        match self {
+            Part::Tank { .. } => Some(f64::INFINITY),
+            Part::Scaffold { .. } => None,
+            Part::Thruster => Some(-0.5),
+            Part::FuelCell => Some(-1e-6),
+            Part::Battery => None,
+        }
+    }
+
+    pub fn typical_draw(&self, fluid: Fluid) -> Option<f64> {
+        match fluid {
+            Fluid::Electricity => self.typical_power_draw(),
+            Fluid::Hydrogen => self.typical_hydrogen_draw(),
+        }
+    }
+
+    /// Return the amount of fluid the part can store, in Joules for electricity and kg for hydrogen.
+    /// Returns None if the part does not store the fluid
+    pub fn capacity(&self, fluid: Fluid) -> Option<f64> {
+        match (self, fluid) {
+            (Part::Battery, Fluid::Electricity) => Some(360_000.),
+            (Part::Tank { length }, Fluid::Hydrogen) => Some(70. * *length as f64),
+            _ => None,
+        }
+    }
 }
\ No newline at end of file
@@ -1,59 +1,80 @@
+use anyhow::Result;
+use log::{error, info};
+use strum::{EnumCount, IntoEnumIterator};
 use tethys::prelude::*;
 
 use crate::ship::Panel;
 
-use super::part::PartModel;
-use super::attachment::AttachmentModel;
+use super::manifest::{AttachmentModel, PanelMaterial, PartModel};
 
 pub const MODEL_CAPACITY: usize = 64;
 pub const MATERIAL_CAPACITY: usize = 64;
 pub const ATTACHMENT_CAPACITY: usize = 64;
 
+// Every variant needs a place in its container
+const _: () = assert!(PartModel::COUNT <= MODEL_CAPACITY);
+const _: () = assert!(AttachmentModel::COUNT <= ATTACHMENT_CAPACITY);
+const _: () = assert!(PanelMaterial::COUNT <= MATERIAL_CAPACITY);
+
 // Stores the ship part models and provides a seamless interface to load them
 pub struct PartData {
     model_container: ModelContainer<MODEL_CAPACITY>,
+    attachment_container: ModelContainer<ATTACHMENT_CAPACITY>,
     material_container: MaterialContainer<MATERIAL_CAPACITY>,
+    assets: AssetManager,
 }
 impl PartData {
-    pub fn new() -> Self {
+    pub fn new(assets: AssetManager) -> Self {
         Self {
             model_container: ModelContainer::new(),
-            material_container: MaterialContainer::new()
+            attachment_container: ModelContainer::new(),
+            material_container: MaterialContainer::new(),
+            assets,
         }
     }
 
     pub fn get_loader<'a>(&'a self, graphics: &'a Graphics) -> PartLoader<'a> {
         PartLoader {
             model_loader: self.model_container.loader(|index| {
-                let part = PartModel::from_repr(index).unwrap();
-                let loaded_obj = match part {
-                    PartModel::TankCap => include_model!("tank-cap"),
-                    PartModel::TankBody => include_model!("tank-body"),
-                    PartModel::Scaffold => include_model!("scaffold"),
-                    PartModel::FuelCell => include_model!("fuel-cell"),
-                    PartModel::Thruster => include_model!("thruster"),
-                    PartModel::Battery => include_model!("battery"),
-                };
-                Model::from_obj(graphics, loaded_obj)
+                self.load_model(graphics, PartModel::from_repr(index).unwrap().asset()).unwrap_or_else(|e| panic!("{e:#}"))
             }),
-            attachment_loader: self.model_container.loader(|index| {
-                let part = AttachmentModel::from_repr(index).unwrap();
-                let loaded_obj = match part {
-                    AttachmentModel::HydrogenHub => include_model!("hub-fluid"),
-                    AttachmentModel::CircuitHub => include_model!("hub-circuit"),
-                };
-                Model::from_obj(graphics, loaded_obj)
+            attachment_loader: self.attachment_container.loader(|index| {
+                self.load_model(graphics, AttachmentModel::from_repr(index).unwrap().asset()).unwrap_or_else(|e| panic!("{e:#}"))
             }),
             material_loader: self.material_container.loader(|index| {
-                let panel = Panel::from_repr(index).unwrap();
-                let loaded_material = match panel {
-                    Panel::Metal => include_material!("metal")
-                };
-                Material::new(graphics, &loaded_material)
+                self.load_material(graphics, PanelMaterial::from_repr(index).unwrap().asset()).unwrap_or_else(|e| panic!("{e:#}"))
             }),
             graphics,
         }
     }
+
+    /// Reload any models and materials whose files have changed. The old asset is kept if the new
+    /// one cannot be loaded.
+    pub fn reload_changed(&self, graphics: &Graphics) {
+        for name in self.assets.poll() {
+            let result = if let Some(part) = PartModel::iter().find(|part| part.asset() == name) {
+                self.load_model(graphics, &name).map(|model| self.model_container.reload(part as usize, model))
+            } else if let Some(attachment) = AttachmentModel::iter().find(|attachment| attachment.asset() == name) {
+                self.load_model(graphics, &name).map(|model| self.attachment_container.reload(attachment as usize, model))
+            } else if let Some(panel) = PanelMaterial::iter().find(|panel| panel.asset() == name) {
+                self.load_material(graphics, &name).map(|material| self.material_container.reload(panel as usize, material))
+            } else {
+                continue;
+            };
+            match result {
+                Ok(()) => info!("Reloaded {name}"),
+                Err(e) => error!("Could not reload {name}: {e:#}"),
+            }
+        }
+    }
+
+    fn load_model(&self, graphics: &Graphics, name: &str) -> Result<Model> {
+        Ok(Model::from_obj_pbr(graphics, self.assets.load_model(name)?))
+    }
+
+    fn load_material(&self, graphics: &Graphics, name: &str) -> Result<Material> {
+        Ok(Material::new_pbr(graphics, &self.assets.load_material(name)?))
+    }
 }
 
 #[derive(Clone)]
@@ -72,6 +93,6 @@ impl<'a> PartLoader<'a> {
         self.model_loader.borrow(part as usize)
     }
     pub fn load_panel(&self, panel: Panel) -> Material {
-        self.material_loader.borrow(panel as usize)
+        self.material_loader.borrow(panel.material() as usize)
     }
 }
\ No newline at end of file
@@ -0,0 +1,39 @@
+use cgmath::{Rotation, Vector3};
+use strum::{EnumCount, FromRepr};
+
+use super::{orientation, PartLayout};
+
+/// Thrusters are grouped by the direction they push the ship in the grid frame, where +x is
+/// forward, +y is left and +z is up. Each group shares a throttle.
+#[repr(usize)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumCount, FromRepr)]
+pub enum ThrusterGroup {
+    Forward,
+    Backward,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+impl ThrusterGroup {
+    /// Find the group whose direction is closest to `direction`
+    pub fn from_direction(direction: Vector3<f64>) -> Self {
+        let abs = direction.map(f64::abs);
+        if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x > 0. { Self::Forward } else { Self::Backward }
+        } else if abs.y >= abs.z {
+            if direction.y > 0. { Self::Left } else { Self::Right }
+        } else if direction.z > 0. {
+            Self::Up
+        } else {
+            Self::Down
+        }
+    }
+}
+
+/// Direction in the grid frame in which a thruster pushes. The nozzle of the model points along
+/// its local -z axis, so the thrust acts along +z.
+pub fn thrust_direction(layout: PartLayout) -> Vector3<f64> {
+    orientation::to_quat(layout.orientation).rotate_vector(Vector3::new(0., 0., 1.))
+}
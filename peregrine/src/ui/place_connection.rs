@@ -97,29 +97,19 @@ impl PlaceConnectionState {
         self.tools.update(graphics, camera, ship, pos_in_grid);
     }
 
-    pub fn place(&mut self, ship: &mut ShipInterior) {
+    pub fn place(&mut self, part_loader: PartLoader, ship: &mut ShipInterior) {
         // Place a hub
         let old_attachment_index = match self.existing {
             Some(i) => i,
             None => match self.get_placed_layout() {
-                Some(layout) => {
-                    ship.attachments.push(self.tools.interior.attachments[0]);
-                    ship.attachment_layouts.push(layout);
-                    ship.attachments.len() - 1
-                },
+                Some(layout) => ship.add_attachment(part_loader, self.tools.interior.attachments[0], layout),
                 None => return,
             }
         };
 
         // Now connect the pipe
         match self.selected_index {
-            Some(new_attachment_index) => {
-                let pair = (new_attachment_index, old_attachment_index);
-                match ship.connections.get_mut(&self.fluid) {
-                    Some(value) => {value.push(pair);},
-                    None => {ship.connections.insert(self.fluid, vec![pair]).unwrap();},
-                }
-            },
+            Some(new_attachment_index) => ship.add_connection(self.fluid, new_attachment_index, old_attachment_index),
             None => (),
         }
     }
@@ -88,7 +88,7 @@ impl PlacementTools {
         match pos_in_grid {
             Some(pos) => {
                 self.interior.rigid_body.orientation = ship.rigid_body.orientation * orientation::to_quat(self.roll);
-                self.interior.rigid_body.pos = ship.rigid_body.to_global(pos);
+                self.interior.rigid_body.set_origin(ship.rigid_body.to_global(pos));
                 self.display = true;
             },
             None => {
@@ -98,11 +98,10 @@ impl PlacementTools {
 
         self.interior.update_graphics(graphics, camera);
         let mut i = 0;
-        let orientation = Quaternion::new(1., 0., 0., 0.,);
         for ((x, y, z), part_number) in ship.collider.get_grid_collider().unwrap().indexed_iter() {
             if part_number < 0 {continue;}
             let pos = Vector3::new(x as f64 + 0.5, y as f64 + 0.5,z as f64 + 0.5);
-            self.placement_objects[i].update(graphics, ObjectUniform::new(camera, pos, orientation));
+            self.placement_objects[i].update(graphics, ObjectUniform::new(camera, ship.rigid_body.to_global(pos), ship.rigid_body.orientation));
             i += 1;
         }
     }
@@ -1,3 +1,5 @@
+mod perlin;
 mod save;
 
+pub use perlin::Perlin;
 pub use save::Save;
\ No newline at end of file
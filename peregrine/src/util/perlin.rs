@@ -0,0 +1,104 @@
+use cgmath::Vector3;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+/// Gradient noise in three dimensions, after Ken Perlin's improved noise. The same seed always
+/// gives the same noise.
+#[derive(Clone)]
+pub struct Perlin {
+    /// A shuffle of 0 to 255, repeated so that it can be indexed past the end
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut shuffle = (0..=255).collect::<Vec<u8>>();
+        shuffle.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+        Self {
+            permutation: std::array::from_fn(|i| shuffle[i % 256]),
+        }
+    }
+
+    /// Smooth noise between about -1 and 1 which varies over a distance of about one, and is zero
+    /// at points with integer coordinates
+    pub fn noise(&self, p: Vector3<f64>) -> f64 {
+        let cell = p.map(|x| x.floor());
+        let f = p - cell;
+        let [x, y, z] = [cell.x, cell.y, cell.z].map(|x| x.rem_euclid(256.) as usize);
+        let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+
+        let hash = |dx: usize, dy: usize, dz: usize| {
+            let p = &self.permutation;
+            p[p[p[x + dx] as usize + y + dy] as usize + z + dz]
+        };
+        let corner = |dx: usize, dy: usize, dz: usize| gradient(hash(dx, dy, dz), f.x - dx as f64, f.y - dy as f64, f.z - dz as f64);
+
+        lerp(w,
+            lerp(v,
+                lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+                lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+            lerp(v,
+                lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+                lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+    }
+
+    /// Sum of `octaves` layers of noise, each at twice the frequency and half the amplitude of the
+    /// last, scaled to lie between about -1 and 1
+    pub fn fractal(&self, p: Vector3<f64>, octaves: u32) -> f64 {
+        let mut total = 0.;
+        let mut amplitude = 1.;
+        let mut frequency = 1.;
+        let mut total_amplitude = 0.;
+        for _ in 0..octaves {
+            total += amplitude * self.noise(p * frequency);
+            total_amplitude += amplitude;
+            amplitude /= 2.;
+            frequency *= 2.;
+        }
+        total / total_amplitude
+    }
+}
+
+/// Ease from 0 to 1 with zero first and second derivatives at both ends
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6. - 15.) + 10.)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+/// Dot product of the offset from a corner with one of twelve gradients along the edges of a cube,
+/// chosen by the hash of the corner
+fn gradient(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = if h < 4 { y } else if h == 12 || h == 14 { x } else { z };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(perlin: &Perlin) -> Vec<f64> {
+        (0..100).map(|i| perlin.fractal(Vector3::new(i as f64 * 0.37, i as f64 * -1.13, i as f64 * 2.71), 4)).collect()
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        assert_eq!(samples(&Perlin::new(7)), samples(&Perlin::new(7)));
+        assert_ne!(samples(&Perlin::new(7)), samples(&Perlin::new(8)));
+    }
+
+    #[test]
+    fn noise_is_bounded_and_zero_on_the_lattice() {
+        let perlin = Perlin::new(1);
+        for i in -20..20 {
+            assert_eq!(perlin.noise(Vector3::new(i as f64, (3 * i) as f64, (-7 * i) as f64)), 0.);
+        }
+        for value in samples(&perlin) {
+            assert!(value.abs() <= 1.5, "{value} is out of range");
+        }
+    }
+}
@@ -1,25 +1,157 @@
 #![allow(dead_code)]
 use std::{fs, path::Path};
 use serde::{Serialize, Deserialize};
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
+use ron::ser::PrettyConfig;
 
+/// Length of the header which precedes the payload: magic, version and checksum
+const HEADER_LENGTH: usize = 12;
+
+/// A type which can be written to disk and built back into `Output`.
+///
+/// Saved bytes start with a header of `MAGIC`, the format version and a CRC32 checksum of the
+/// payload, all little-endian. Older versions are upgraded one at a time with `migrate` before the
+/// payload is decoded, so `VERSION` must be bumped and a migration added whenever the layout of
+/// the saved type changes. Saves from before the header was added are raw bincode, and are read as
+/// version 0.
+///
+/// Files with a `.ron` extension are instead read and written as human-readable RON, which is
+/// meant for blueprints that are edited by hand and reviewed in git. RON is not versioned, so new
+/// fields should be given a `#[serde(default)]` to keep older blueprints loading.
 pub trait Save<Output, Additional> : Serialize + for<'de> Deserialize<'de> {
+    /// Identifies the kind of file
+    const MAGIC: [u8; 4];
+    /// Version of the current layout
+    const VERSION: u32;
+
     fn build(self, _additional: Additional) -> Output;
+
+    /// Upgrade a payload saved with `version` to `version + 1`
+    fn migrate(version: u32, _payload: Vec<u8>) -> Result<Vec<u8>> {
+        if version == 0 {
+            bail!("Legacy unversioned save is not supported");
+        }
+        bail!("No migration from save version {version}")
+    }
+
+    /// Check the header, upgrade the payload to the current version and decode it
+    fn decode_bytes(bytes: &[u8]) -> Result<Self> {
+        let (version, payload) = if bytes.starts_with(&Self::MAGIC) {
+            if bytes.len() < HEADER_LENGTH {
+                bail!("Save is too short to contain a header ({} bytes)", bytes.len());
+            }
+            let (header, payload) = bytes.split_at(HEADER_LENGTH);
+            let version = u32::from_le_bytes(header[4..8].try_into().unwrap());
+            let checksum = u32::from_le_bytes(header[8..12].try_into().unwrap());
+            if version > Self::VERSION {
+                bail!("Save has version {version}, which is newer than the supported version {}", Self::VERSION);
+            }
+            if crc32fast::hash(payload) != checksum {
+                bail!("Save checksum does not match; the file is corrupt");
+            }
+            (version, payload)
+        } else {
+            // Saves without the magic number predate the header
+            (0, bytes)
+        };
+
+        let mut payload = payload.to_vec();
+        for from in version..Self::VERSION {
+            payload = Self::migrate(from, payload)
+                .with_context(|| format!("Could not migrate save from version {from} to {}", from + 1))?;
+        }
+        bincode::deserialize(&payload)
+            .with_context(|| format!("Could not decode save with version {}", Self::VERSION))
+    }
+
     fn from_bytes(bytes: &[u8], additional: Additional) -> Result<Output> {
-        let template = bincode::deserialize(bytes)?;
-        Ok(Self::build(template, additional))
+        Ok(Self::build(Self::decode_bytes(bytes)?, additional))
     }
     fn to_bytes(&self) -> Result<Vec<u8>> {
-        let output = bincode::serialize(self)?;
+        let payload = bincode::serialize(self)?;
+        let mut output = Vec::with_capacity(HEADER_LENGTH + payload.len());
+        output.extend_from_slice(&Self::MAGIC);
+        output.extend_from_slice(&Self::VERSION.to_le_bytes());
+        output.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        output.extend_from_slice(&payload);
+        Ok(output)
+    }
+    fn from_text(text: &str, additional: Additional) -> Result<Output> {
+        let template = ron::from_str(text)?;
+        Ok(Self::build(template, additional))
+    }
+    fn to_text(&self) -> Result<String> {
+        let output = ron::ser::to_string_pretty(self, PrettyConfig::default().depth_limit(2))?;
         Ok(output)
     }
     fn from_file(path: &Path, additional: Additional) -> Result<Output> {
-        let bytes = fs::read(path)?;
-        Self::from_bytes(&bytes, additional)
+        let bytes = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+        let output = if is_text(path) {
+            Self::from_text(std::str::from_utf8(&bytes)?, additional)
+        } else {
+            Self::from_bytes(&bytes, additional)
+        };
+        output.with_context(|| format!("Could not load {}", path.display()))
     }
     fn to_file(&self, path: &Path) -> Result<()> {
-        let bytes = self.to_bytes()?;
-        fs::write(path, &bytes)?;
+        let bytes = if is_text(path) {
+            self.to_text()?.into_bytes()
+        } else {
+            self.to_bytes()?
+        };
+        fs::write(path, &bytes).with_context(|| format!("Could not write {}", path.display()))?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+fn is_text(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "ron")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Counter {
+        count: u32,
+    }
+
+    impl Save<Counter, ()> for Counter {
+        const MAGIC: [u8; 4] = *b"TEST";
+        const VERSION: u32 = 1;
+
+        fn build(self, _additional: ()) -> Counter {
+            self
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let bytes = Counter { count: 7 }.to_bytes().unwrap();
+        assert_eq!(Counter::from_bytes(&bytes, ()).unwrap(), Counter { count: 7 });
+    }
+
+    #[test]
+    fn corrupt_checksum() {
+        let mut bytes = Counter { count: 7 }.to_bytes().unwrap();
+        *bytes.last_mut().unwrap() ^= 1;
+        let error = Counter::from_bytes(&bytes, ()).unwrap_err();
+        assert!(format!("{error:#}").contains("corrupt"), "{error:#}");
+    }
+
+    #[test]
+    fn future_version() {
+        let mut bytes = Counter { count: 7 }.to_bytes().unwrap();
+        bytes[4..8].copy_from_slice(&2u32.to_le_bytes());
+        let error = Counter::from_bytes(&bytes, ()).unwrap_err();
+        assert!(format!("{error:#}").contains("newer than the supported version"), "{error:#}");
+    }
+
+    #[test]
+    fn legacy_save_without_migration() {
+        let bytes = bincode::serialize(&Counter { count: 7 }).unwrap();
+        let error = Counter::from_bytes(&bytes, ()).unwrap_err();
+        assert!(format!("{error:#}").contains("Legacy unversioned save is not supported"), "{error:#}");
+    }
+}
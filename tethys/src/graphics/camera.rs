@@ -18,14 +18,12 @@ struct CameraUniform {
     // We can't use cgmath with bytemuck directly, so we'll have
     // to convert the Matrix4 into a 4x4 f32 array
     view_proj: [[f32; 4]; 4],
-    light_pos: [f32; 4],
 }
 
 impl CameraUniform {
     fn new(matrix: Matrix4<f32>) -> Self {
         Self {
             view_proj: matrix.into(),
-            light_pos: [10., 10., 10., 0.]
         }
     }
 }
@@ -95,6 +93,26 @@ impl Camera {
         OPENGL_TO_WGPU_MATRIX * self.get_proj() * self.get_view()
     }
 
+    /// Distances to the near and far clipping planes
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.znear, self.zfar)
+    }
+
+    /// Corners of the slice of the view frustum between the distances `near` and `far` along the
+    /// forward direction, relative to the camera
+    pub(crate) fn frustum_corners(&self, near: f32, far: f32) -> [Vector3<f32>; 8] {
+        let (forward, up, right) = (self.get_forward::<f32>(), self.get_up::<f32>(), -self.get_left::<f32>());
+        let tan_half_fovy = (self.fovy / 2.).tan();
+        let mut corners = [Vector3::new(0., 0., 0.); 8];
+        for (i, distance) in [near, far].into_iter().enumerate() {
+            let (half_height, half_width) = (distance * tan_half_fovy, distance * tan_half_fovy * self.aspect);
+            for (j, (x, y)) in [(-1., -1.), (1., -1.), (1., 1.), (-1., 1.)].into_iter().enumerate() {
+                corners[4 * i + j] = forward * distance + right * (x * half_width) + up * (y * half_height);
+            }
+        }
+        corners
+    }
+
     pub fn update(&self, graphics: &Graphics) {
         let uniform = CameraUniform::new(self.get_view_proj());
         graphics.queue.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[uniform]))
@@ -0,0 +1,166 @@
+use cgmath::{InnerSpace, Vector3};
+
+use super::Graphics;
+
+/// A cube map of linear radiance held on the CPU, with `size` by `size` texels on each face. The
+/// faces are +X, -X, +Y, -Y, +Z and -Z in turn, as in a wgpu cube texture.
+#[derive(Clone)]
+pub struct CubeMap {
+    pub(super) size: u32,
+    pub(super) texels: Vec<Vector3<f32>>,
+}
+
+impl CubeMap {
+    /// Make a cube map from the radiance arriving from each direction, sampled at the centers of
+    /// the texels
+    pub fn from_fn(size: u32, f: impl Fn(Vector3<f32>) -> Vector3<f32>) -> Self {
+        let size = size.max(1);
+        let texels = (0..6).flat_map(|face| (0..size).flat_map(move |y| (0..size).map(move |x| (face, x, y))))
+            .map(|(face, x, y)| f(texel_direction(face, (x as f32 + 0.5) / size as f32, (y as f32 + 0.5) / size as f32)))
+            .collect();
+        Self { size, texels }
+    }
+
+    /// Width of each face in texels
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Add the light of a point, like a star, to the texel which contains its direction. The
+    /// irradiance is spread over the solid angle of the texel, so the total light does not depend
+    /// on the size of the cube map.
+    pub fn add_point(&mut self, direction: Vector3<f32>, irradiance: Vector3<f32>) {
+        let (face, x, y) = self.texel(direction);
+        let solid_angle = self.texel_solid_angle(x, y);
+        self.texels[((face * self.size + y) * self.size + x) as usize] += irradiance / solid_angle;
+    }
+
+    /// Each level of averages from this cube map down to a single texel per face, which are the
+    /// mip levels of a texture
+    pub(super) fn mip_levels(&self) -> Vec<Self> {
+        let mut levels = vec![self.clone()];
+        while levels.last().unwrap().size > 1 {
+            levels.push(levels.last().unwrap().halve());
+        }
+        levels
+    }
+
+    /// Average squares of texels until the faces are at most `size` texels across
+    pub fn downsample(&self, size: u32) -> Self {
+        if self.size <= size.max(1) {
+            return self.clone();
+        }
+        let mut cube_map = self.halve();
+        while cube_map.size > size.max(1) {
+            cube_map = cube_map.halve();
+        }
+        cube_map
+    }
+
+    /// Average each square of four texels
+    fn halve(&self) -> Self {
+        let size = self.size / 2;
+        let texel = |face: u32, x: u32, y: u32| self.texels[((face * self.size + y) * self.size + x) as usize];
+        let texels = (0..6).flat_map(|face| (0..size).flat_map(move |y| (0..size).map(move |x| (face, x, y))))
+            .map(|(face, x, y)| (texel(face, 2 * x, 2 * y) + texel(face, 2 * x + 1, 2 * y) + texel(face, 2 * x, 2 * y + 1) + texel(face, 2 * x + 1, 2 * y + 1)) / 4.)
+            .collect();
+        Self { size, texels }
+    }
+
+    /// Radiance of the texel which contains the direction
+    pub(super) fn sample(&self, direction: Vector3<f32>) -> Vector3<f32> {
+        let (face, x, y) = self.texel(direction);
+        self.texels[((face * self.size + y) * self.size + x) as usize]
+    }
+
+    /// Face and position of the texel which contains the direction
+    fn texel(&self, direction: Vector3<f32>) -> (u32, u32, u32) {
+        let (face, s, t) = direction_texel(direction);
+        let x = ((s * self.size as f32) as u32).min(self.size - 1);
+        let y = ((t * self.size as f32) as u32).min(self.size - 1);
+        (face, x, y)
+    }
+
+    /// Solid angle of a texel as seen from the center of the cube, which is the same on every face
+    pub(super) fn texel_solid_angle(&self, x: u32, y: u32) -> f32 {
+        let (u, v) = (2. * (x as f32 + 0.5) / self.size as f32 - 1., 2. * (y as f32 + 0.5) / self.size as f32 - 1.);
+        4. / (self.size * self.size) as f32 / (1. + u * u + v * v).powf(1.5)
+    }
+}
+
+/// Upload cube maps as the mip levels of a cube texture, each half the size of the last
+pub(super) fn make_cube_texture(graphics: &Graphics, label: &str, levels: &[CubeMap]) -> wgpu::TextureView {
+    let size = levels[0].size;
+    let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: 6,
+        },
+        mip_level_count: levels.len() as u32,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+        view_formats: &[],
+    });
+    for (level, cube_map) in levels.iter().enumerate() {
+        let data = cube_map.texels.iter()
+            .flat_map(|texel| [texel.x, texel.y, texel.z, 1.])
+            .flat_map(|channel| half::f16::from_f32(channel).to_le_bytes())
+            .collect::<Vec<_>>();
+        graphics.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: level as u32,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &data,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(8 * cube_map.size),
+                rows_per_image: Some(cube_map.size),
+            },
+            wgpu::Extent3d {
+                width: cube_map.size,
+                height: cube_map.size,
+                depth_or_array_layers: 6,
+            },
+        );
+    }
+    texture.create_view(&wgpu::TextureViewDescriptor {
+        dimension: Some(wgpu::TextureViewDimension::Cube),
+        ..Default::default()
+    })
+}
+
+/// Direction through a point on a face of a cube map, where `s` and `t` run from 0 to 1 across
+/// and down the face
+pub(super) fn texel_direction(face: u32, s: f32, t: f32) -> Vector3<f32> {
+    let (u, v) = (2. * s - 1., 2. * t - 1.);
+    match face {
+        0 => Vector3::new(1., -v, -u),
+        1 => Vector3::new(-1., -v, u),
+        2 => Vector3::new(u, 1., v),
+        3 => Vector3::new(u, -1., -v),
+        4 => Vector3::new(u, -v, 1.),
+        _ => Vector3::new(-u, -v, -1.),
+    }.normalize()
+}
+
+/// Face of a cube map which a direction passes through, and the point on it
+fn direction_texel(direction: Vector3<f32>) -> (u32, f32, f32) {
+    let (x, y, z) = (direction.x, direction.y, direction.z);
+    let (face, u, v, major) = if x.abs() >= y.abs() && x.abs() >= z.abs() {
+        if x > 0. { (0, -z, -y, x.abs()) } else { (1, z, -y, x.abs()) }
+    } else if y.abs() >= z.abs() {
+        if y > 0. { (2, x, z, y.abs()) } else { (3, x, -z, y.abs()) }
+    } else if z > 0. {
+        (4, x, -y, z.abs())
+    } else {
+        (5, -x, -y, z.abs())
+    };
+    (face, (u / major + 1.) / 2., (v / major + 1.) / 2.)
+}
@@ -0,0 +1,139 @@
+use std::f32::consts::PI;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use super::{cube_map::{make_cube_texture, texel_direction, CubeMap}, Graphics};
+
+/// Directions sampled around the reflection to filter each texel of the rough levels
+const SAMPLE_COUNT: u32 = 64;
+
+/// Light arriving from every direction around a scene, like the sky, which lights surfaces
+/// through `ShaderBinding::Lights` once it is given to `Lights::set_environment`. The light
+/// reflected by surfaces of each roughness is stored in the mip levels of a cube map, and the
+/// diffuse light is stored as spherical harmonics.
+pub struct Environment {
+    pub(crate) view: wgpu::TextureView,
+    pub(crate) sampler: wgpu::Sampler,
+    /// Spherical harmonic coefficients of the irradiance divided by pi, in the order of
+    /// `ShaderBinding::Lights`
+    pub(crate) irradiance: [[f32; 4]; 9],
+}
+
+impl Environment {
+    /// Make an environment from the linear radiance arriving from each direction, sampled at the
+    /// centers of the texels of a cube map `size` texels across
+    pub fn new(graphics: &Graphics, size: u32, radiance: impl Fn(Vector3<f32>) -> Vector3<f32>) -> Self {
+        Self::from_cube_map(graphics, CubeMap::from_fn(size, radiance))
+    }
+
+    /// Make an environment from a cube map, which should be small since every texel of the rough
+    /// levels is filtered on the CPU. Large maps can be shrunk with `CubeMap::downsample`.
+    pub fn from_cube_map(graphics: &Graphics, base: CubeMap) -> Self {
+        // Averages of the base level, from which the rough levels are sampled without aliasing
+        let averages = base.mip_levels();
+
+        let level_count = averages.len();
+        let levels = (0..level_count).map(|level| if level == 0 {
+            averages[0].clone()
+        } else {
+            let roughness = level as f32 / (level_count - 1) as f32;
+            CubeMap::from_fn(averages[0].size >> level, |direction| prefilter(&averages, direction, roughness))
+        }).collect::<Vec<_>>();
+        let irradiance = irradiance(&averages[0]);
+
+        let view = make_cube_texture(graphics, "Environment map", &levels);
+        let sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        Self {
+            view,
+            sampler,
+            irradiance,
+        }
+    }
+
+    /// An environment which gives no light
+    pub fn dark(graphics: &Graphics) -> Self {
+        Self::new(graphics, 1, |_| Vector3::zero())
+    }
+}
+
+/// Project the radiance of a cube map onto the spherical harmonics up to the second band, and
+/// convolve them with the cosine of the angle to the normal to give the irradiance divided by pi
+fn irradiance(cube_map: &CubeMap) -> [[f32; 4]; 9] {
+    // Convolution with the cosine for each band, divided by pi
+    const BAND_FACTORS: [f32; 3] = [1., 2. / 3., 1. / 4.];
+    let size = cube_map.size;
+    let mut coefficients = [Vector3::zero(); 9];
+    for face in 0..6 {
+        for y in 0..size {
+            for x in 0..size {
+                let direction = texel_direction(face, (x as f32 + 0.5) / size as f32, (y as f32 + 0.5) / size as f32);
+                let radiance = cube_map.texels[((face * size + y) * size + x) as usize];
+                for (coefficient, basis) in coefficients.iter_mut().zip(spherical_harmonics(direction)) {
+                    *coefficient += radiance * basis * cube_map.texel_solid_angle(x, y);
+                }
+            }
+        }
+    }
+    let bands = [0, 1, 1, 1, 2, 2, 2, 2, 2];
+    std::array::from_fn(|i| (coefficients[i] * BAND_FACTORS[bands[i]]).extend(0.).into())
+}
+
+/// Average the radiance reflected towards the normal by a surface of the given roughness, with
+/// directions chosen by importance sampling the GGX distribution. Each sample is read from the
+/// level of averages whose texels cover about the solid angle of the sample.
+fn prefilter(averages: &[CubeMap], normal: Vector3<f32>, roughness: f32) -> Vector3<f32> {
+    let alpha = roughness * roughness;
+    let up = if normal.z.abs() < 0.999 { Vector3::unit_z() } else { Vector3::unit_x() };
+    let tangent = up.cross(normal).normalize();
+    let bitangent = normal.cross(tangent);
+    let texel_solid_angle = 4. * PI / (6 * averages[0].size * averages[0].size) as f32;
+
+    let mut total = Vector3::zero();
+    let mut total_weight = 0.;
+    for i in 0..SAMPLE_COUNT {
+        // Hammersley point set
+        let (u, v) = (i as f32 / SAMPLE_COUNT as f32, i.reverse_bits() as f32 / 2f32.powi(32));
+        let phi = 2. * PI * u;
+        let cos_theta = ((1. - v) / (1. + (alpha * alpha - 1.) * v)).sqrt();
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let half = tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + normal * cos_theta;
+        // The view is along the normal, so the light is the normal reflected about the half vector
+        let light = half * (2. * normal.dot(half)) - normal;
+        let n_dot_l = normal.dot(light);
+        if n_dot_l <= 0. {
+            continue;
+        }
+
+        let d = alpha * alpha / (PI * (cos_theta * cos_theta * (alpha * alpha - 1.) + 1.).powi(2));
+        let pdf = d / 4.;
+        let sample_solid_angle = 1. / (SAMPLE_COUNT as f32 * pdf + 1e-4);
+        let level = (0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.).max(0.).round() as usize;
+        total += averages[level.min(averages.len() - 1)].sample(light) * n_dot_l;
+        total_weight += n_dot_l;
+    }
+    if total_weight > 0. { total / total_weight } else { Vector3::zero() }
+}
+
+/// Real spherical harmonics up to the second band, in the order of `ShaderBinding::Lights`
+fn spherical_harmonics(n: Vector3<f32>) -> [f32; 9] {
+    [
+        0.282095,
+        0.488603 * n.y,
+        0.488603 * n.z,
+        0.488603 * n.x,
+        1.092548 * n.x * n.y,
+        1.092548 * n.y * n.z,
+        0.315392 * (3. * n.z * n.z - 1.),
+        1.092548 * n.x * n.z,
+        0.546274 * (n.x * n.x - n.y * n.y),
+    ]
+}
@@ -0,0 +1,326 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Point3, SquareMatrix, Vector3, Vector4};
+use wgpu::util::DeviceExt;
+
+use super::{camera::Camera, environment::Environment, primitives::{TexVertex, Vertex}, shader::ShaderBinding, Graphics};
+
+/// Number of shadow maps which split the view of the main directional light by distance
+pub const CASCADES: usize = 4;
+/// Directional lights beyond this number are ignored
+pub const MAX_DIRECTIONAL_LIGHTS: usize = 4;
+/// Point lights beyond this number are ignored
+pub const MAX_POINT_LIGHTS: usize = 16;
+/// Distance behind each cascade in m within which objects still cast shadows into it
+const SHADOW_CASTER_MARGIN: f32 = 50.;
+/// Share of each cascade split which is spaced logarithmically rather than evenly
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+/// Maps the depths of an orthographic projection from the -1 to 1 of OpenGL to the 0 to 1 of wgpu,
+/// leaving W alone so that the projection stays parallel. The matrix is given column by column.
+const ORTHO_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+    1., 0., 0., 0.,
+    0., 1., 0., 0.,
+    0., 0., 0.5, 0.,
+    0., 0., 0.5, 1.,
+);
+
+/// A light infinitely far away, like the sun
+#[derive(Clone, Copy, Debug)]
+pub struct DirectionalLight {
+    /// Direction from surfaces towards the light
+    pub direction: Vector3<f32>,
+    /// Linear color of the light
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+}
+
+/// A light which shines in every direction from a point, fading to nothing at its range
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Vector3<f64>,
+    /// Linear color of the light
+    pub color: Vector3<f32>,
+    pub intensity: f32,
+    /// Distance in m beyond which the light has no effect
+    pub range: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LightUniform {
+    cascades: [[[f32; 4]; 4]; CASCADES],
+    /// Direction and color of each light
+    directional: [[[f32; 4]; 2]; MAX_DIRECTIONAL_LIGHTS],
+    /// Position relative to the camera with the range in W, and color of each light
+    point: [[[f32; 4]; 2]; MAX_POINT_LIGHTS],
+    /// Number of directional lights, point lights and cascades
+    counts: [u32; 4],
+    /// Spherical harmonics of the light from the environment
+    irradiance: [[f32; 4]; 9],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CascadeUniform {
+    view_proj: [[f32; 4]; 4],
+}
+
+/// The lights of a scene. The first directional light casts shadows, which are drawn with
+/// `RenderPass::render_shadows` into a cascade of shadow maps, each covering a further stretch of
+/// the view. Light from the environment reaches everything. Shaders read the lights with
+/// `ShaderBinding::Lights`.
+pub struct Lights {
+    pub directional: Vec<DirectionalLight>,
+    pub point: Vec<PointLight>,
+    /// Distance from the camera in m out to which shadows are drawn, if the camera sees that far
+    pub shadow_distance: f32,
+    shadow_map_size: u32,
+    light_buffer: wgpu::Buffer,
+    shadow_view: wgpu::TextureView,
+    shadow_sampler: wgpu::Sampler,
+    environment: Environment,
+    cascade_buffers: Vec<wgpu::Buffer>,
+    pub(crate) cascade_bind_groups: Vec<wgpu::BindGroup>,
+    /// View of each layer of the shadow map, to be drawn into
+    pub(crate) cascade_views: Vec<wgpu::TextureView>,
+    pub(crate) shadow_pipeline: wgpu::RenderPipeline,
+    pub(crate) bind_group: wgpu::BindGroup,
+}
+
+impl Lights {
+    /// Make a scene with no lights and a dark environment, whose shadow maps are `shadow_map_size`
+    /// texels across
+    pub fn new(graphics: &Graphics, shadow_map_size: u32) -> Self {
+        let light_buffer = graphics.device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Light Buffer"),
+                contents: bytemuck::bytes_of(&<LightUniform as bytemuck::Zeroable>::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+
+        let shadow_map = graphics.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Shadow map"),
+            size: wgpu::Extent3d {
+                width: shadow_map_size,
+                height: shadow_map_size,
+                depth_or_array_layers: CASCADES as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Depth32Float,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let shadow_view = shadow_map.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let cascade_views = (0..CASCADES as u32).map(|layer| shadow_map.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2),
+            base_array_layer: layer,
+            array_layer_count: Some(1),
+            ..Default::default()
+        })).collect();
+        // Compares depths and blends the results of the nearest texels, for soft edges
+        let shadow_sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let environment = Environment::dark(graphics);
+        let bind_group = make_bind_group(graphics, &light_buffer, &shadow_view, &shadow_sampler, &environment);
+
+        // Each cascade is drawn with its own matrix, which is bound like a camera
+        let cascade_buffers = (0..CASCADES).map(|_| graphics.device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Cascade Buffer"),
+                contents: bytemuck::bytes_of(&<CascadeUniform as bytemuck::Zeroable>::zeroed()),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        )).collect::<Vec<_>>();
+        let cascade_bind_groups = cascade_buffers.iter().map(|buffer| graphics.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &ShaderBinding::Camera.get_bind_group_layout(graphics),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("cascade_bind_group"),
+        })).collect();
+
+        Self {
+            directional: Vec::new(),
+            point: Vec::new(),
+            shadow_distance: 100.,
+            shadow_map_size,
+            light_buffer,
+            shadow_view,
+            shadow_sampler,
+            environment,
+            cascade_buffers,
+            cascade_bind_groups,
+            cascade_views,
+            shadow_pipeline: make_shadow_pipeline(graphics),
+            bind_group,
+        }
+    }
+
+    /// Light the scene with an environment in place of the last one
+    pub fn set_environment(&mut self, graphics: &Graphics, environment: Environment) {
+        self.bind_group = make_bind_group(graphics, &self.light_buffer, &self.shadow_view, &self.shadow_sampler, &environment);
+        self.environment = environment;
+    }
+
+    /// Fit the cascades to the view of the camera and upload the lights. Positions are relative to
+    /// the camera, like the world matrices of objects.
+    pub fn update(&self, graphics: &Graphics, camera: &Camera) {
+        let mut uniform = <LightUniform as bytemuck::Zeroable>::zeroed();
+        for (i, light) in self.directional.iter().take(MAX_DIRECTIONAL_LIGHTS).enumerate() {
+            let color = light.color * light.intensity;
+            uniform.directional[i] = [light.direction.normalize().extend(0.).into(), color.extend(0.).into()];
+        }
+        for (i, light) in self.point.iter().take(MAX_POINT_LIGHTS).enumerate() {
+            let position = (light.position - camera.position).cast::<f32>().unwrap();
+            let color = light.color * light.intensity;
+            uniform.point[i] = [position.extend(light.range).into(), color.extend(0.).into()];
+        }
+        let n_directional = self.directional.len().min(MAX_DIRECTIONAL_LIGHTS) as u32;
+        let n_point = self.point.len().min(MAX_POINT_LIGHTS) as u32;
+        let n_cascades = if let Some(sun) = self.directional.first() {
+            for (i, matrix) in self.cascade_matrices(camera, sun.direction.normalize()).into_iter().enumerate() {
+                uniform.cascades[i] = matrix.into();
+                graphics.queue.write_buffer(&self.cascade_buffers[i], 0, bytemuck::bytes_of(&CascadeUniform { view_proj: matrix.into() }));
+            }
+            CASCADES as u32
+        } else {
+            0
+        };
+        uniform.counts = [n_directional, n_point, n_cascades, 0];
+        uniform.irradiance = self.environment.irradiance;
+        graphics.queue.write_buffer(&self.light_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+
+    /// Matrix from camera-relative positions to the shadow map of each cascade. Each cascade covers
+    /// a sphere around a slice of the view frustum, and is moved in whole texels so that shadow
+    /// edges do not shimmer as the camera moves.
+    fn cascade_matrices(&self, camera: &Camera, direction: Vector3<f32>) -> [Matrix4<f32>; CASCADES] {
+        let (znear, zfar) = camera.depth_range();
+        let far = zfar.min(self.shadow_distance.max(znear));
+        // Blend logarithmic splits, which match the falloff of detail with distance, with even ones
+        let split = |i: usize| {
+            let fraction = i as f32 / CASCADES as f32;
+            let logarithmic = znear * (far / znear).powf(fraction);
+            let even = znear + (far - znear) * fraction;
+            CASCADE_SPLIT_LAMBDA * logarithmic + (1. - CASCADE_SPLIT_LAMBDA) * even
+        };
+        let up = if direction.z.abs() > 0.99 { Vector3::unit_x() } else { Vector3::unit_z() };
+        let rotation = Matrix4::look_to_rh(Point3::origin(), -direction.cast::<f64>().unwrap(), up.cast::<f64>().unwrap());
+        let inverse_rotation = rotation.invert().unwrap();
+
+        std::array::from_fn(|i| {
+            let corners = camera.frustum_corners(split(i), split(i + 1));
+            let center = corners.iter().sum::<Vector3<f32>>() / 8.;
+            let radius = corners.iter().map(|corner| (corner - center).magnitude()).fold(0., f32::max);
+            // Keep the size fixed as the camera turns by rounding it up
+            let radius = (radius * 16.).ceil() / 16.;
+
+            // Snap the center to the texels of the map, in absolute coordinates
+            let texel = 2. * radius as f64 / self.shadow_map_size as f64;
+            let absolute = rotation * (camera.position + center.cast::<f64>().unwrap()).extend(1.);
+            let snapped = Vector4::new((absolute.x / texel).round() * texel, (absolute.y / texel).round() * texel, absolute.z, 1.);
+            let center = ((inverse_rotation * snapped).truncate() - camera.position).cast::<f32>().unwrap();
+
+            let eye = Point3::from_vec(center + direction * (radius + SHADOW_CASTER_MARGIN));
+            let view = Matrix4::look_to_rh(eye, -direction, up);
+            let proj = cgmath::ortho(-radius, radius, -radius, radius, 0., 2. * radius + SHADOW_CASTER_MARGIN);
+            ORTHO_TO_WGPU_MATRIX * proj * view
+        })
+    }
+}
+
+fn make_bind_group(graphics: &Graphics, light_buffer: &wgpu::Buffer, shadow_view: &wgpu::TextureView, shadow_sampler: &wgpu::Sampler, environment: &Environment) -> wgpu::BindGroup {
+    graphics.device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout: &ShaderBinding::Lights.get_bind_group_layout(graphics),
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: light_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(shadow_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(shadow_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(&environment.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 4,
+                resource: wgpu::BindingResource::Sampler(&environment.sampler),
+            },
+        ],
+        label: Some("light_bind_group"),
+    })
+}
+
+fn make_shadow_pipeline(graphics: &Graphics) -> wgpu::RenderPipeline {
+    let shader = graphics.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Shadow Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("shadow.wgsl").into()),
+    });
+    let layout = graphics.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Shadow Pipeline Layout"),
+        bind_group_layouts: &[
+            &ShaderBinding::Camera.get_bind_group_layout(graphics),
+            &ShaderBinding::Instanced.get_bind_group_layout(graphics),
+        ],
+        push_constant_ranges: &[],
+    });
+    graphics.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Shadow Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[TexVertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: None,
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            // Panels are drawn from both sides, so both faces must cast shadows
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            // Push the depths back so that surfaces do not shadow themselves
+            bias: wgpu::DepthBiasState {
+                constant: 2,
+                slope_scale: 2.,
+                clamp: 0.,
+            },
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
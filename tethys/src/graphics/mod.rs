@@ -3,19 +3,51 @@ pub mod shader;
 pub mod primitives;
 pub mod object;
 pub mod camera;
+pub mod cube_map;
+pub mod environment;
+pub mod light;
+pub mod skybox;
+pub mod starfield;
 pub mod render_pass;
 
+use std::cell::RefCell;
+
+use anyhow::{anyhow, Result};
 use wgpu::{CommandEncoder, SurfaceConfiguration, SurfaceTexture, TextureView};
 use winit::window::Window;
 
+use render_pass::{InstanceBuffer, RenderPass};
+
+/// Format of the offscreen texture used by headless graphics
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Where the frames are drawn
+enum RenderTarget<'a> {
+    /// Present to the surface of a window
+    Window {
+        surface: wgpu::Surface<'a>,
+        window: &'a Window,
+    },
+    /// Render to a texture that can be read back with `Graphics::render_to_image`
+    Offscreen {
+        texture: wgpu::Texture,
+        view: wgpu::TextureView,
+    },
+}
+
 pub struct Graphics<'a> {
-    surface: wgpu::Surface<'a>,
+    target: RenderTarget<'a>,
     device: wgpu::Device,
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     pub size: (u32, u32),
-    window: &'a Window,
     depth_texture_view: wgpu::TextureView,
+    /// Whether samplers may filter anisotropically
+    anisotropic_filtering: bool,
+    /// Whether BC compressed textures can be sampled
+    texture_compression_bc: bool,
+    /// Uniforms of instanced objects, made when they are first drawn
+    instances: RefCell<Option<InstanceBuffer>>,
 }
 
 impl<'a> Graphics<'a> {
@@ -42,7 +74,7 @@ impl<'a> Graphics<'a> {
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                required_features: adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC,
                 required_limits: wgpu::Limits::default(),
                 label: None,
                 memory_hints: wgpu::MemoryHints::Performance,
@@ -50,6 +82,8 @@ impl<'a> Graphics<'a> {
             None, // Trace path
         ).await.unwrap();
 
+        let anisotropic_filtering = supports_anisotropic_filtering(&adapter);
+        let texture_compression_bc = device.features().contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps.formats.iter()
             .find(|f| f.is_srgb())
@@ -72,16 +106,102 @@ impl<'a> Graphics<'a> {
 
         
         Self {
-            window,
-            surface,
+            target: RenderTarget::Window { surface, window },
             device,
             queue,
             config,
             size: (size.width, size.height),
             depth_texture_view,
+            anisotropic_filtering,
+            texture_compression_bc,
+            instances: RefCell::new(None),
         }
     }
 
+    /// Create graphics with no window which render into an offscreen texture. The software fallback
+    /// adapter is preferred so that frames can be rendered on machines without a GPU.
+    pub async fn new_headless(size: (u32, u32)) -> Result<Graphics<'static>> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(), // Software adapters are often only exposed through GL
+            ..Default::default()
+        });
+
+        let mut adapter = None;
+        for force_fallback_adapter in [true, false] {
+            adapter = instance.request_adapter(
+                &wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::default(),
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                },
+            ).await;
+            if adapter.is_some() { break; }
+        }
+        let adapter = adapter.ok_or(anyhow!("No graphics adapter is available for headless rendering"))?;
+
+        let (device, queue) = adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                required_features: adapter.features() & wgpu::Features::TEXTURE_COMPRESSION_BC,
+                required_limits: wgpu::Limits::default(),
+                label: None,
+                memory_hints: wgpu::MemoryHints::Performance,
+            },
+            None, // Trace path
+        ).await?;
+
+        let anisotropic_filtering = supports_anisotropic_filtering(&adapter);
+        let texture_compression_bc = device.features().contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+        let config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            format: HEADLESS_FORMAT,
+            width: size.0,
+            height: size.1,
+            present_mode: wgpu::PresentMode::Fifo,
+            alpha_mode: wgpu::CompositeAlphaMode::Opaque,
+            view_formats: vec![],
+            desired_maximum_frame_latency: 2,
+        };
+
+        let (texture, view) = Self::make_offscreen_texture(&device, &config);
+        let (_depth_texture, depth_texture_view, _depth_sampler) = Self::make_depth_texture(&device, &config);
+
+        Ok(Graphics {
+            target: RenderTarget::Offscreen { texture, view },
+            device,
+            queue,
+            config,
+            size,
+            depth_texture_view,
+            anisotropic_filtering,
+            texture_compression_bc,
+            instances: RefCell::new(None),
+        })
+    }
+
+    /// Blocking version of `Graphics::new_headless`
+    pub fn headless(size: (u32, u32)) -> Result<Graphics<'static>> {
+        pollster::block_on(Self::new_headless(size))
+    }
+
+    fn make_offscreen_texture(device: &wgpu::Device, config: &SurfaceConfiguration) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen texture"),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
     pub(crate) fn make_depth_texture(device: &wgpu::Device, config: &SurfaceConfiguration) -> (wgpu::Texture, wgpu::TextureView, wgpu::Sampler) {
         let size = wgpu::Extent3d { // 2.
             width: config.width,
@@ -119,8 +239,12 @@ impl<'a> Graphics<'a> {
         (texture, view, sampler)
     }
 
-    pub(crate) fn window(&self) -> &Window {
-        &self.window
+    /// Returns the window being drawn to, or None if the graphics are headless
+    pub(crate) fn window(&self) -> Option<&Window> {
+        match &self.target {
+            RenderTarget::Window { window, .. } => Some(window),
+            RenderTarget::Offscreen { .. } => None,
+        }
     }
 
     pub fn resize(&mut self, new_size: (u32, u32)) {
@@ -128,16 +252,83 @@ impl<'a> Graphics<'a> {
             self.size = new_size;
             self.config.width = new_size.0;
             self.config.height = new_size.1;
-            self.surface.configure(&self.device, &self.config);
+            match &mut self.target {
+                RenderTarget::Window { surface, .. } => surface.configure(&self.device, &self.config),
+                RenderTarget::Offscreen { texture, view } => (*texture, *view) = Self::make_offscreen_texture(&self.device, &self.config),
+            }
+            let (_depth_texture, depth_texture_view, _depth_sampler) = Self::make_depth_texture(&self.device, &self.config);
+            self.depth_texture_view = depth_texture_view;
         }
     }
 
-    pub(crate) fn start_render(&self) -> anyhow::Result<(SurfaceTexture, TextureView)> {
-        let texture = self.surface.get_current_texture()?;
+    pub(crate) fn start_render(&self) -> Result<(SurfaceTexture, TextureView)> {
+        let surface = match &self.target {
+            RenderTarget::Window { surface, .. } => surface,
+            RenderTarget::Offscreen { .. } => return Err(anyhow!("Headless graphics do not have a surface to render to")),
+        };
+        let texture = surface.get_current_texture()?;
         let view = texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
         Ok((texture, view))
     }
 
+    /// Render a frame into the offscreen texture of headless graphics and read it back
+    pub fn render_to_image<'b>(&'b self, render: impl FnOnce(RenderPass<'b>)) -> Result<image::RgbaImage> {
+        let (texture, view) = match &self.target {
+            RenderTarget::Offscreen { texture, view } => (texture, view),
+            RenderTarget::Window { .. } => return Err(anyhow!("Only headless graphics can render to an image")),
+        };
+        render(RenderPass::new(self, view)); // The frame is submitted when the render pass is dropped
+
+        // Rows of the copied buffer must be aligned
+        let (width, height) = (self.config.width, self.config.height);
+        let unpadded_bytes_per_row = 4 * width;
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_bytes_per_row = unpadded_bytes_per_row.div_ceil(align) * align;
+        let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Readback Buffer"),
+            size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self.make_encoder();
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            texture.size(),
+        );
+        self.queue_encoder(encoder);
+
+        let slice = buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| { let _ = sender.send(result); });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+
+        let mut pixels = Vec::with_capacity((unpadded_bytes_per_row * height) as usize);
+        {
+            let data = slice.get_mapped_range();
+            for row in data.chunks(padded_bytes_per_row as usize) {
+                pixels.extend_from_slice(&row[..unpadded_bytes_per_row as usize]);
+            }
+        }
+        buffer.unmap();
+
+        image::RgbaImage::from_raw(width, height, pixels).ok_or(anyhow!("Readback buffer had the wrong size"))
+    }
+
     pub(crate) fn make_encoder(&self) -> CommandEncoder {
         self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("Render Encoder"),
@@ -183,6 +374,12 @@ impl<'a> Graphics<'a> {
     }
     
     pub fn set_mouse_pos(&self, size: (u32, u32)) {
-        self.window.set_cursor_position(winit::dpi::PhysicalPosition{x: size.0, y: size.1}).unwrap();
+        if let Some(window) = self.window() {
+            window.set_cursor_position(winit::dpi::PhysicalPosition{x: size.0, y: size.1}).unwrap();
+        }
     }
-}
\ No newline at end of file
+}
+
+fn supports_anisotropic_filtering(adapter: &wgpu::Adapter) -> bool {
+    adapter.get_downlevel_capabilities().flags.contains(wgpu::DownlevelFlags::ANISOTROPIC_FILTERING)
+}
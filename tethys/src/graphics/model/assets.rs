@@ -0,0 +1,223 @@
+use std::{cell::{Cell, RefCell}, collections::HashMap, fs, path::{Path, PathBuf}, time::{Duration, Instant, SystemTime}};
+
+use anyhow::{bail, Context, Result};
+
+use super::loading::{LoadMaterial, LoadModel};
+
+/// How often the watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Identifies a baked model
+const MODEL_MAGIC: [u8; 4] = *b"TMDL";
+/// Identifies a baked material
+const MATERIAL_MAGIC: [u8; 4] = *b"TMTL";
+/// Version of the layout of baked files. Bump it whenever `LoadModel`, `LoadMaterial` or anything
+/// they hold changes, so that older bakes are passed over for their sources instead of failing to
+/// decode.
+const BAKE_VERSION: u32 = 1;
+/// Length of the header of a baked file: magic and version
+const BAKE_HEADER_LENGTH: usize = 8;
+
+/// Loads models and materials from a directory while the game runs, and notices when they change.
+///
+/// Assets are named by their path within the directory without an extension. A baked `.bin` blob
+/// made by `bake_model` or `bake_material` is preferred, falling back to a raw `.glb`, `.gltf` or `.obj` model or
+/// `.mtl` material. Baked files start with a magic number and `BAKE_VERSION`, and those made by
+/// an older version are skipped. Only the file which was loaded is watched, so a changed texture is not noticed
+/// until the file which refers to it is saved again.
+pub struct AssetManager {
+    directory: PathBuf,
+    /// File each loaded asset came from, with its modification time when it was read
+    watched: RefCell<HashMap<String, (PathBuf, Option<SystemTime>)>>,
+    last_poll: Cell<Instant>,
+}
+
+impl AssetManager {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            watched: RefCell::new(HashMap::new()),
+            last_poll: Cell::new(Instant::now()),
+        }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    pub fn load_model(&self, name: &str) -> Result<LoadModel> {
+        let mut path = self.find(name, &["bin", "glb", "gltf", "obj"])?;
+        if is_baked(&path) {
+            match read_baked(&path, MODEL_MAGIC)? {
+                Some(model) => {
+                    self.watch(name, path);
+                    return Ok(model);
+                },
+                None => {
+                    log::warn!("{} was baked by another version, so its source is loaded instead", path.display());
+                    path = self.find(name, &["glb", "gltf", "obj"])?;
+                },
+            }
+        }
+        let model = read_model(&path)?;
+        self.watch(name, path);
+        Ok(model)
+    }
+
+    /// Load a model from its source file, optimize it with `LoadModel::optimize`, compress the
+    /// textures of its materials and save it as a `.bin` beside the source. The baked file is
+    /// loaded in preference to the source from then on, so it must be baked again or deleted when
+    /// the source changes. Returns the baked path.
+    pub fn bake_model(&self, name: &str) -> Result<PathBuf> {
+        let mut model = read_model(&self.find(name, &["glb", "gltf", "obj"])?)?;
+        model.optimize();
+        for material in &mut model.materials {
+            material.compress_textures()?;
+        }
+        self.write_baked(name, MODEL_MAGIC, &model)
+    }
+
+    /// Load a material from its `.mtl` file, compress its textures and save it as a `.bin` beside
+    /// the source, like `bake_model`
+    pub fn bake_material(&self, name: &str) -> Result<PathBuf> {
+        let path = self.find(name, &["mtl"])?;
+        let mut material = LoadMaterial::load_mtl(path.to_str().context("Asset path is not valid unicode")?)?;
+        material.compress_textures()?;
+        self.write_baked(name, MATERIAL_MAGIC, &material)
+    }
+
+    pub fn load_material(&self, name: &str) -> Result<LoadMaterial> {
+        let mut path = self.find(name, &["bin", "mtl"])?;
+        if is_baked(&path) {
+            match read_baked(&path, MATERIAL_MAGIC)? {
+                Some(material) => {
+                    self.watch(name, path);
+                    return Ok(material);
+                },
+                None => {
+                    log::warn!("{} was baked by another version, so its source is loaded instead", path.display());
+                    path = self.find(name, &["mtl"])?;
+                },
+            }
+        }
+        let material = LoadMaterial::load_mtl(path.to_str().context("Asset path is not valid unicode")?)?;
+        self.watch(name, path);
+        Ok(material)
+    }
+
+    /// Names of the loaded assets whose files have changed since they were last loaded. The files
+    /// are only checked every `POLL_INTERVAL`, so this is cheap to call every frame. Each change is
+    /// reported until the asset is loaded again.
+    pub fn poll(&self) -> Vec<String> {
+        if self.last_poll.get().elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll.set(Instant::now());
+        self.watched.borrow().iter()
+            .filter(|(_, (path, modified))| modified_time(path) != *modified)
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Path of the first file named `name` with one of `extensions`, in order of preference
+    fn find(&self, name: &str, extensions: &[&str]) -> Result<PathBuf> {
+        for extension in extensions {
+            let path = self.directory.join(format!("{name}.{extension}"));
+            if path.is_file() {
+                return Ok(path);
+            }
+        }
+        bail!("Could not find asset {name} in {}", self.directory.display())
+    }
+
+    fn write_baked(&self, name: &str, magic: [u8; 4], asset: &impl serde::Serialize) -> Result<PathBuf> {
+        let path = self.directory.join(format!("{name}.bin"));
+        let mut bytes = Vec::from(magic);
+        bytes.extend_from_slice(&BAKE_VERSION.to_le_bytes());
+        bincode::serialize_into(&mut bytes, asset)?;
+        fs::write(&path, bytes).with_context(|| format!("Could not write {}", path.display()))?;
+        Ok(path)
+    }
+
+    fn watch(&self, name: &str, path: PathBuf) {
+        let modified = modified_time(&path);
+        self.watched.borrow_mut().insert(name.to_owned(), (path, modified));
+    }
+}
+
+/// Load a model from its source file
+fn read_model(path: &Path) -> Result<LoadModel> {
+    let file_path = path.to_str().context("Asset path is not valid unicode")?;
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some("obj") => LoadModel::load_obj(file_path),
+        _ => LoadModel::load_gltf(file_path),
+    }
+}
+
+/// Decode a baked file. Returns None if it does not start with `magic` and `BAKE_VERSION`, as
+/// when it was baked before the header was added or by another version.
+fn read_baked<T: serde::de::DeserializeOwned>(path: &Path, magic: [u8; 4]) -> Result<Option<T>> {
+    let bytes = fs::read(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let mut header = magic.to_vec();
+    header.extend_from_slice(&BAKE_VERSION.to_le_bytes());
+    if !bytes.starts_with(&header) {
+        return Ok(None);
+    }
+    let asset = bincode::deserialize(&bytes[BAKE_HEADER_LENGTH..])
+        .with_context(|| format!("Could not decode {}", path.display()))?;
+    Ok(Some(asset))
+}
+
+fn is_baked(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "bin")
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIANGLE: &str = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvn 0 0 1\nf 1/1/1 2/1/1 3/1/1\n";
+
+    /// An empty directory of its own for each test
+    fn directory(test: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("tethys-assets-{}-{test}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    #[test]
+    fn stale_baked_model_falls_back_to_source() {
+        let directory = directory("model");
+        fs::write(directory.join("triangle.obj"), TRIANGLE).unwrap();
+        // A bake from before the header, which is plain bincode
+        fs::write(directory.join("triangle.bin"), [3, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        let assets = AssetManager::new(&directory);
+        assert_eq!(assets.load_model("triangle").unwrap().meshes.len(), 1);
+        assert_eq!(assets.watched.borrow()["triangle"].0, directory.join("triangle.obj"));
+
+        assets.bake_model("triangle").unwrap();
+        assert_eq!(assets.load_model("triangle").unwrap().meshes.len(), 1);
+        assert_eq!(assets.watched.borrow()["triangle"].0, directory.join("triangle.bin"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn stale_baked_material_falls_back_to_source() {
+        let directory = directory("material");
+        fs::write(directory.join("paint.mtl"), "newmtl paint\nKd 0.5 0.25 0.125\n").unwrap();
+        // A model bake is not a material bake either
+        fs::write(directory.join("paint.bin"), [MODEL_MAGIC.as_slice(), &BAKE_VERSION.to_le_bytes()].concat()).unwrap();
+        let assets = AssetManager::new(&directory);
+        assert_eq!(assets.load_material("paint").unwrap().diffuse, [0.5, 0.25, 0.125]);
+        assert_eq!(assets.watched.borrow()["paint"].0, directory.join("paint.mtl"));
+
+        assets.bake_material("paint").unwrap();
+        assert_eq!(assets.load_material("paint").unwrap().diffuse, [0.5, 0.25, 0.125]);
+        assert_eq!(assets.watched.borrow()["paint"].0, directory.join("paint.bin"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
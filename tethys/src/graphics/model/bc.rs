@@ -0,0 +1,501 @@
+//! Block compression, in which each block of 4x4 texels is stored in a fixed number of bytes. BC7
+//! stores RGBA in 16 bytes and BC5 stores two channels in 16 bytes, which suits normal maps.
+//! Encoding only uses BC7 mode 6, but every mode can be decoded.
+
+/// Texels of a block, in rows from the top left
+pub(crate) type Block = [[u8; 4]; 16];
+
+/// Subset of each texel in the two-subset partitions, with a bit per texel
+const PARTITIONS_2: [u16; 64] = [
+    0xcccc, 0x8888, 0xeeee, 0xecc8, 0xc880, 0xfeec, 0xfec8, 0xec80,
+    0xc800, 0xffec, 0xfe80, 0xe800, 0xffe8, 0xff00, 0xfff0, 0xf000,
+    0xf710, 0x008e, 0x7100, 0x08ce, 0x008c, 0x7310, 0x3100, 0x8cce,
+    0x088c, 0x3110, 0x6666, 0x366c, 0x17e8, 0x0ff0, 0x718e, 0x399c,
+    0xaaaa, 0xf0f0, 0x5a5a, 0x33cc, 0x3c3c, 0x55aa, 0x9696, 0xa55a,
+    0x73ce, 0x13c8, 0x324c, 0x3bdc, 0x6996, 0xc33c, 0x9966, 0x0660,
+    0x0272, 0x04e4, 0x4e40, 0x2720, 0xc936, 0x936c, 0x39c6, 0x639c,
+    0x9336, 0x9cc6, 0x817e, 0xe718, 0xccf0, 0x0fcc, 0x7744, 0xee22,
+];
+
+/// Subset of each texel in the three-subset partitions, with two bits per texel
+const PARTITIONS_3: [u32; 64] = [
+    0xaa685050, 0x6a5a5040, 0x5a5a4200, 0x5450a0a8, 0xa5a50000, 0xa0a05050, 0x5555a0a0, 0x5a5a5050,
+    0xaa550000, 0xaa555500, 0xaaaa5500, 0x90909090, 0x94949494, 0xa4a4a4a4, 0xa9a59450, 0x2a0a4250,
+    0xa5945040, 0x0a425054, 0xa5a5a500, 0x55a0a0a0, 0xa8a85454, 0x6a6a4040, 0xa4a45000, 0x1a1a0500,
+    0x0050a4a4, 0xaaa59090, 0x14696914, 0x69691400, 0xa08585a0, 0xaa821414, 0x50a4a450, 0x6a5a0200,
+    0xa9a58000, 0x5090a0a8, 0xa8a09050, 0x24242424, 0x00aa5500, 0x24924924, 0x24499224, 0x50a50a50,
+    0x500aa550, 0xaaaa4444, 0x66660000, 0xa5a0a5a0, 0x50a050a0, 0x69286928, 0x44aaaa44, 0x66666600,
+    0xaa444444, 0x54a854a8, 0x95809580, 0x96969600, 0xa85454a8, 0x80959580, 0xaa141414, 0x96960000,
+    0xaaaa1414, 0xa05050a0, 0xa0a5a5a0, 0x96000000, 0x40804080, 0xa9a8a9a8, 0xaaaaaa44, 0x2a4a5254,
+];
+
+/// Texel of the second subset whose index is stored with one bit fewer, for two subsets
+const ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15,
+    15, 15, 15, 15, 15, 15, 15, 15,
+    15, 2, 8, 2, 2, 8, 8, 15,
+    2, 8, 2, 2, 8, 8, 2, 2,
+    15, 15, 6, 8, 2, 8, 15, 15,
+    2, 8, 2, 2, 2, 15, 15, 6,
+    6, 2, 6, 8, 15, 15, 2, 2,
+    15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+/// Anchor texels of the second and third subsets, for three subsets
+const ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15,
+        8, 8, 6, 6, 6, 5, 3, 3,
+        3, 3, 8, 15, 3, 3, 6, 10,
+        5, 8, 8, 6, 8, 5, 15, 15,
+        8, 15, 3, 5, 6, 10, 8, 15,
+        15, 3, 15, 5, 15, 15, 15, 15,
+        3, 15, 5, 5, 5, 8, 5, 10,
+        5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8,
+        15, 15, 15, 15, 15, 15, 15, 8,
+        15, 8, 15, 3, 15, 8, 15, 8,
+        3, 15, 6, 10, 15, 15, 10, 8,
+        15, 3, 15, 10, 10, 8, 9, 10,
+        6, 15, 8, 15, 3, 6, 6, 8,
+        15, 3, 15, 15, 15, 15, 15, 15,
+        15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+const WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Layout of a BC7 mode, in bits
+struct Mode {
+    subsets: usize,
+    partition: u32,
+    rotation: u32,
+    index_selection: u32,
+    color: u32,
+    alpha: u32,
+    /// Whether each endpoint has its own P bit, which is the lowest bit of every channel
+    endpoint_p: bool,
+    /// Whether each subset has a P bit shared by both its endpoints
+    shared_p: bool,
+    index: u32,
+    /// Indices of a second set, which interpolate alpha or colour separately
+    secondary_index: u32,
+}
+
+const MODES: [Mode; 8] = [
+    Mode { subsets: 3, partition: 4, rotation: 0, index_selection: 0, color: 4, alpha: 0, endpoint_p: true, shared_p: false, index: 3, secondary_index: 0 },
+    Mode { subsets: 2, partition: 6, rotation: 0, index_selection: 0, color: 6, alpha: 0, endpoint_p: false, shared_p: true, index: 3, secondary_index: 0 },
+    Mode { subsets: 3, partition: 6, rotation: 0, index_selection: 0, color: 5, alpha: 0, endpoint_p: false, shared_p: false, index: 2, secondary_index: 0 },
+    Mode { subsets: 2, partition: 6, rotation: 0, index_selection: 0, color: 7, alpha: 0, endpoint_p: true, shared_p: false, index: 2, secondary_index: 0 },
+    Mode { subsets: 1, partition: 0, rotation: 2, index_selection: 1, color: 5, alpha: 6, endpoint_p: false, shared_p: false, index: 2, secondary_index: 3 },
+    Mode { subsets: 1, partition: 0, rotation: 2, index_selection: 0, color: 7, alpha: 8, endpoint_p: false, shared_p: false, index: 2, secondary_index: 2 },
+    Mode { subsets: 1, partition: 0, rotation: 0, index_selection: 0, color: 7, alpha: 7, endpoint_p: true, shared_p: false, index: 4, secondary_index: 0 },
+    Mode { subsets: 2, partition: 6, rotation: 0, index_selection: 0, color: 5, alpha: 5, endpoint_p: true, shared_p: false, index: 2, secondary_index: 0 },
+];
+
+/// Reads and writes fields of a block from the lowest bit up
+struct Bits {
+    value: u128,
+    position: u32,
+}
+
+impl Bits {
+    fn read(&mut self, n: u32) -> u32 {
+        let field = (self.value >> self.position) as u32 & ((1u64 << n) - 1) as u32;
+        self.position += n;
+        field
+    }
+
+    fn write(&mut self, field: u32, n: u32) {
+        self.value |= (field as u128 & ((1 << n) - 1)) << self.position;
+        self.position += n;
+    }
+}
+
+fn weights(bits: u32) -> &'static [u32] {
+    match bits {
+        2 => &WEIGHTS_2,
+        3 => &WEIGHTS_3,
+        _ => &WEIGHTS_4,
+    }
+}
+
+fn interpolate(e0: u32, e1: u32, weight: u32) -> u8 {
+    (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8
+}
+
+pub(crate) fn decode_bc7(block: &[u8; 16]) -> Block {
+    let value = u128::from_le_bytes(*block);
+    let mode_number = value.trailing_zeros();
+    // The reserved mode decodes to transparent black
+    let Some(mode) = MODES.get(mode_number as usize) else {
+        return [[0; 4]; 16];
+    };
+    let mut bits = Bits { value, position: mode_number + 1 };
+    let partition = bits.read(mode.partition) as usize;
+    let rotation = bits.read(mode.rotation);
+    let index_selection = bits.read(mode.index_selection);
+
+    let n_endpoints = 2 * mode.subsets;
+    let mut endpoints = [[0u32; 4]; 6];
+    let mut precision = [mode.color, mode.color, mode.color, mode.alpha];
+    for channel in 0..4 {
+        for endpoint in &mut endpoints[..n_endpoints] {
+            endpoint[channel] = bits.read(precision[channel]);
+        }
+    }
+    if mode.endpoint_p || mode.shared_p {
+        let p_bits = if mode.endpoint_p {
+            (0..n_endpoints).map(|_| bits.read(1)).collect::<Vec<_>>()
+        } else {
+            (0..mode.subsets).flat_map(|_| [bits.read(1); 2]).collect()
+        };
+        for (endpoint, p) in endpoints.iter_mut().zip(p_bits) {
+            for channel in 0..4 {
+                if precision[channel] > 0 {
+                    endpoint[channel] = endpoint[channel] << 1 | p;
+                }
+            }
+        }
+        for channel_precision in &mut precision {
+            if *channel_precision > 0 {
+                *channel_precision += 1;
+            }
+        }
+    }
+    // Widen to 8 bits by repeating the highest bits
+    for endpoint in &mut endpoints[..n_endpoints] {
+        for channel in 0..4 {
+            endpoint[channel] = match precision[channel] {
+                0 => 255,
+                n => (endpoint[channel] << (8 - n)) | (endpoint[channel] >> (2 * n - 8)),
+            };
+        }
+    }
+
+    let subset = |texel: usize| match mode.subsets {
+        1 => 0,
+        2 => (PARTITIONS_2[partition] >> texel & 1) as usize,
+        _ => (PARTITIONS_3[partition] >> (2 * texel) & 3) as usize,
+    };
+    let is_anchor = |texel: usize| texel == 0 || match mode.subsets {
+        2 => texel == ANCHORS_2[partition] as usize,
+        3 => ANCHORS_3.iter().any(|anchors| texel == anchors[partition] as usize),
+        _ => false,
+    };
+    let mut indices = [0; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = bits.read(mode.index - is_anchor(texel) as u32);
+    }
+    let mut secondary_indices = [0; 16];
+    if mode.secondary_index > 0 {
+        for (texel, index) in secondary_indices.iter_mut().enumerate() {
+            *index = bits.read(mode.secondary_index - (texel == 0) as u32);
+        }
+    }
+
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        let [e0, e1] = [0, 1].map(|end| endpoints[2 * subset(i) + end]);
+        let (color_weight, alpha_weight) = match (mode.secondary_index, index_selection) {
+            (0, _) => (weights(mode.index)[indices[i] as usize], weights(mode.index)[indices[i] as usize]),
+            (_, 0) => (weights(mode.index)[indices[i] as usize], weights(mode.secondary_index)[secondary_indices[i] as usize]),
+            _ => (weights(mode.secondary_index)[secondary_indices[i] as usize], weights(mode.index)[indices[i] as usize]),
+        };
+        for channel in 0..3 {
+            texel[channel] = interpolate(e0[channel], e1[channel], color_weight);
+        }
+        texel[3] = interpolate(e0[3], e1[3], alpha_weight);
+        if rotation > 0 {
+            texel.swap(3, rotation as usize - 1);
+        }
+    }
+    texels
+}
+
+/// Encode a block with mode 6, which has one pair of RGBA endpoints and 16 steps between them.
+/// The endpoints are fitted along the principal axis of the texels and then refined by least
+/// squares.
+pub(crate) fn encode_bc7(texels: &Block) -> [u8; 16] {
+    let points = texels.map(|texel| texel.map(|c| c as f32));
+    let mean = points.iter().fold([0.; 4], |sum, p| [0, 1, 2, 3].map(|c| sum[c] + p[c] / 16.));
+    let axis = principal_axis(&points, mean);
+    let projections = points.map(|p| (0..4).map(|c| (p[c] - mean[c]) * axis[c]).sum::<f32>());
+    let low = projections.iter().copied().fold(f32::INFINITY, f32::min);
+    let high = projections.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut ends = [low, high].map(|t| [0, 1, 2, 3].map(|c| mean[c] + t * axis[c]));
+
+    let mut best = fit_mode_6(texels, ends);
+    // Move the endpoints to best fit the texels with the chosen indices
+    let (mut aa, mut ab, mut bb) = (0., 0., 0.);
+    let (mut ax, mut bx) = ([0.; 4], [0.; 4]);
+    for (point, index) in points.iter().zip(best.indices) {
+        let b = WEIGHTS_4[index as usize] as f32 / 64.;
+        let a = 1. - b;
+        aa += a * a;
+        ab += a * b;
+        bb += b * b;
+        for c in 0..4 {
+            ax[c] += a * point[c];
+            bx[c] += b * point[c];
+        }
+    }
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() > 1e-6 {
+        for c in 0..4 {
+            ends[0][c] = (bb * ax[c] - ab * bx[c]) / determinant;
+            ends[1][c] = (aa * bx[c] - ab * ax[c]) / determinant;
+        }
+        let refined = fit_mode_6(texels, ends);
+        if refined.error < best.error {
+            best = refined;
+        }
+    }
+
+    // The first index is stored without its highest bit, which is made zero by swapping ends
+    if best.indices[0] >= 8 {
+        best.endpoints.swap(0, 1);
+        best.indices = best.indices.map(|index| 15 - index);
+    }
+    let mut bits = Bits { value: 0, position: 0 };
+    bits.write(1 << 6, 7);
+    for c in 0..4 {
+        for endpoint in best.endpoints {
+            bits.write(endpoint[c] >> 1, 7);
+        }
+    }
+    for endpoint in best.endpoints {
+        bits.write(endpoint[0] & 1, 1);
+    }
+    for (i, index) in best.indices.iter().enumerate() {
+        bits.write(*index, if i == 0 { 3 } else { 4 });
+    }
+    bits.value.to_le_bytes()
+}
+
+struct Fit {
+    /// Endpoints widened to 8 bits, whose lowest bits are the P bits
+    endpoints: [[u32; 4]; 2],
+    indices: [u32; 16],
+    error: u32,
+}
+
+/// Quantise the ends to mode 6 endpoints with the best P bits, and pick the nearest indices
+fn fit_mode_6(texels: &Block, ends: [[f32; 4]; 2]) -> Fit {
+    let mut best: Option<Fit> = None;
+    for p_bits in [[0, 0], [0, 1], [1, 0], [1, 1]] {
+        let endpoints = [0, 1].map(|end| ends[end].map(|value| {
+            let p = p_bits[end];
+            let quantised = ((value - p as f32) / 2.).round().clamp(0., 127.) as u32;
+            quantised << 1 | p
+        }));
+        let palette = WEIGHTS_4.map(|weight| [0, 1, 2, 3].map(|c| interpolate(endpoints[0][c], endpoints[1][c], weight)));
+        let mut indices = [0; 16];
+        let mut error = 0;
+        for (texel, index) in texels.iter().zip(&mut indices) {
+            let (nearest, distance) = palette.iter().enumerate()
+                .map(|(i, color)| (i as u32, distance2(texel, color)))
+                .min_by_key(|(_, distance)| *distance)
+                .unwrap();
+            *index = nearest;
+            error += distance;
+        }
+        if best.as_ref().is_none_or(|best| error < best.error) {
+            best = Some(Fit { endpoints, indices, error });
+        }
+    }
+    best.unwrap()
+}
+
+fn distance2(a: &[u8; 4], b: &[u8; 4]) -> u32 {
+    (0..4).map(|c| (a[c] as i32 - b[c] as i32).pow(2) as u32).sum()
+}
+
+/// Direction of greatest variance, found by power iteration on the covariance
+fn principal_axis(points: &[[f32; 4]; 16], mean: [f32; 4]) -> [f32; 4] {
+    let mut covariance = [[0.; 4]; 4];
+    for point in points {
+        for i in 0..4 {
+            for j in 0..4 {
+                covariance[i][j] += (point[i] - mean[i]) * (point[j] - mean[j]);
+            }
+        }
+    }
+    let mut axis = [1.; 4];
+    for _ in 0..8 {
+        let next = covariance.map(|row| (0..4).map(|j| row[j] * axis[j]).sum::<f32>());
+        let length = next.iter().map(|x| x * x).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            return [0.; 4];
+        }
+        axis = next.map(|x| x / length);
+    }
+    axis
+}
+
+/// Decode the red and green channels of a BC5 block
+pub(crate) fn decode_bc5(block: &[u8; 16]) -> Block {
+    let red = decode_bc4(block[..8].try_into().unwrap());
+    let green = decode_bc4(block[8..].try_into().unwrap());
+    std::array::from_fn(|i| [red[i], green[i], 0, 255])
+}
+
+/// Encode the red and green channels of a block with BC5
+pub(crate) fn encode_bc5(texels: &Block) -> [u8; 16] {
+    let mut output = [0; 16];
+    output[..8].copy_from_slice(&encode_bc4(texels.map(|texel| texel[0])));
+    output[8..].copy_from_slice(&encode_bc4(texels.map(|texel| texel[1])));
+    output
+}
+
+/// The eight values a BC4 block can hold. The ends are in the first two bytes, and when the first
+/// is larger the rest are spread between them. Otherwise there are four between them, then 0 and
+/// 255.
+fn bc4_palette(e0: u8, e1: u8) -> [u8; 8] {
+    let (e0, e1) = (e0 as u32, e1 as u32);
+    let mut palette = [e0 as u8, e1 as u8, 0, 0, 0, 0, 0, 255];
+    if e0 > e1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * e0 + i as u32 * e1 + 3) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * e0 + i as u32 * e1 + 2) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn decode_bc4(block: &[u8; 8]) -> [u8; 16] {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut indices = u64::from_le_bytes(*block) >> 16;
+    std::array::from_fn(|_| {
+        let value = palette[(indices & 7) as usize];
+        indices >>= 3;
+        value
+    })
+}
+
+fn encode_bc4(values: [u8; 16]) -> [u8; 8] {
+    let high = *values.iter().max().unwrap();
+    let low = *values.iter().min().unwrap();
+    let palette = bc4_palette(high, low);
+    let mut block = (high as u64) | (low as u64) << 8;
+    for (i, value) in values.iter().enumerate() {
+        let index = (0..8).min_by_key(|j| (palette[*j] as i32 - *value as i32).abs()).unwrap();
+        block |= (index as u64) << (16 + 3 * i);
+    }
+    block.to_le_bytes()
+}
+
+/// Compress RGBA8 texels into blocks, repeating the edge texels to fill partial blocks
+pub(crate) fn compress(rgba: &[u8], width: u32, height: u32, encode: fn(&Block) -> [u8; 16]) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let mut output = Vec::with_capacity(width.div_ceil(4) * height.div_ceil(4) * 16);
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            let block = std::array::from_fn(|i| {
+                let x = (block_x + i % 4).min(width - 1);
+                let y = (block_y + i / 4).min(height - 1);
+                let start = 4 * (y * width + x);
+                rgba[start..start + 4].try_into().unwrap()
+            });
+            output.extend_from_slice(&encode(&block));
+        }
+    }
+    output
+}
+
+/// Decompress blocks into RGBA8 texels
+pub(crate) fn decompress(blocks: &[u8], width: u32, height: u32, decode: fn(&[u8; 16]) -> Block) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    let blocks_wide = width.div_ceil(4);
+    let mut rgba = vec![0; 4 * width * height];
+    for (n, block) in blocks.chunks_exact(16).enumerate() {
+        let texels = decode(block.try_into().unwrap());
+        for (i, texel) in texels.iter().enumerate() {
+            let x = 4 * (n % blocks_wide) + i % 4;
+            let y = 4 * (n / blocks_wide) + i / 4;
+            if x < width && y < height {
+                let start = 4 * (y * width + x);
+                rgba[start..start + 4].copy_from_slice(texel);
+            }
+        }
+    }
+    rgba
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Largest and root mean square difference between two blocks over the given channels
+    fn error(a: &Block, b: &Block, channels: usize) -> (u8, f32) {
+        let mut max = 0;
+        let mut sum = 0.;
+        for (a, b) in a.iter().zip(b) {
+            for c in 0..channels {
+                let difference = a[c].abs_diff(b[c]);
+                max = max.max(difference);
+                sum += (difference as f32).powi(2);
+            }
+        }
+        (max, (sum / (16 * channels) as f32).sqrt())
+    }
+
+    fn solid() -> Block {
+        [[200, 100, 30, 255]; 16]
+    }
+
+    /// Colors along a line through RGBA space, which mode 6 can follow closely
+    fn gradient() -> Block {
+        std::array::from_fn(|i| {
+            let i = i as u8;
+            [16 * i, 250 - 12 * i, 40 + 8 * i, 255 - 4 * i]
+        })
+    }
+
+    /// Texels from a fixed sequence of pseudorandom numbers
+    fn random() -> Block {
+        let mut state = 0x2545_f491_u32;
+        std::array::from_fn(|_| std::array::from_fn(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            (state >> 24) as u8
+        }))
+    }
+
+    /// Block of the mean of each channel, which is the best a single color can do
+    fn mean(block: &Block) -> Block {
+        let sum = block.iter().fold([0u32; 4], |sum, texel| std::array::from_fn(|c| sum[c] + texel[c] as u32));
+        [sum.map(|c| ((c + 8) / 16) as u8); 16]
+    }
+
+    #[test]
+    fn bc7_round_trip() {
+        let decode = |block: &Block| decode_bc7(&encode_bc7(block));
+        assert!(error(&solid(), &decode(&solid()), 4).0 <= 1);
+        let (max, rms) = error(&gradient(), &decode(&gradient()), 4);
+        assert!(max <= 3 && rms <= 1.5, "gradient is off by up to {max}, {rms} on average");
+        // Noise cannot be compressed well, but it should still beat a single color
+        let (_, rms) = error(&random(), &decode(&random()), 4);
+        let (_, flat) = error(&random(), &mean(&random()), 4);
+        assert!(rms < 0.8 * flat, "random block is off by {rms} on average, against {flat} for its mean");
+    }
+
+    #[test]
+    fn bc5_round_trip() {
+        let decode = |block: &Block| decode_bc5(&encode_bc5(block));
+        assert_eq!(error(&solid(), &decode(&solid()), 2).0, 0);
+        // Eight levels between the ends of each channel are at most half a step from any value
+        let (max, _) = error(&gradient(), &decode(&gradient()), 2);
+        assert!(max <= 18, "gradient is off by up to {max}");
+        let (max, _) = error(&random(), &decode(&random()), 2);
+        assert!(max <= 18, "random block is off by up to {max}");
+    }
+}
@@ -1,4 +1,4 @@
-use std::{alloc::Layout, borrow::Borrow, rc::Rc, sync::{Arc, Mutex}};
+use std::{borrow::Borrow, cell::UnsafeCell, rc::Rc, sync::{Arc, Mutex}};
 
 pub enum MaybeInstanced<T> {
     Singleton(Arc<T>),
@@ -6,13 +6,14 @@ pub enum MaybeInstanced<T> {
 }
 
 pub struct Container<const CAPACITY: usize, T> {
-    data: [*const T; CAPACITY],
+    /// Each slot is shared by every instance of it, and is only written through the cell by `reload`
+    data: [*const UnsafeCell<T>; CAPACITY],
     counters: [u32; CAPACITY],
     mutexes: [Mutex<u8>; CAPACITY],
 }
 
 pub struct Instance<T> {
-    data: *const T,
+    data: *const UnsafeCell<T>,
     counter: Option<*mut u32>,
     mutex: Option<*const Mutex<u8>>,
 }
@@ -51,7 +52,7 @@ impl<const CAPACITY: usize, T> Clone for Loader<'_, CAPACITY, T> {
 
 impl<T> Instance<T> {
     fn as_ref<'a>(&'a self) -> &'a T {
-        unsafe { &*self.data }
+        unsafe { &*(*self.data).get() }
     }
     
     pub(crate) fn identifier(&self) -> usize {
@@ -66,8 +67,7 @@ impl<T> Drop for Instance<T> {
     
                 // Drop the data
                 if *counter == 0 {
-                    std::ptr::drop_in_place(self.data as *mut u8);
-                    std::alloc::dealloc(self.data as *mut u8, Layout::new::<T>());
+                    drop(Box::from_raw(self.data as *mut UnsafeCell<T>));
                 }
             }
         }
@@ -111,7 +111,7 @@ impl<const CAPACITY: usize, T> Container<CAPACITY, T> {
             let self_ptr = self as *const Self as *mut Self;
             if self.counters[index] == 0 {
                 let model = match load_function(index) {
-                    MaybeInstanced::Singleton(data) => Box::new(Arc::try_unwrap(data).unwrap_or_else(|_| panic!("Your load function must return objects which are not borrowed by anyone else"))),
+                    MaybeInstanced::Singleton(data) => Box::new(UnsafeCell::new(Arc::try_unwrap(data).unwrap_or_else(|_| panic!("Your load function must return objects which are not borrowed by anyone else")))),
                     MaybeInstanced::Instance(_) => panic!("Your ModelContainer load_function must return singletons only")
                 };
                 unsafe {
@@ -128,10 +128,58 @@ impl<const CAPACITY: usize, T> Container<CAPACITY, T> {
             counter: Some(&self.counters[index] as *const u32 as *mut u32),
         })
     }
+
+    /// Replace the data at `index` in place, so that every instance which has already been borrowed
+    /// sees the new value. Nothing happens if the data is not loaded, because the next borrow will
+    /// load it afresh. Instances read the slot through an `UnsafeCell`, so they may be alive, but
+    /// no reference from `MaybeInstanced::inner` may be held, such as during rendering.
+    pub fn reload(&self, index: usize, value: MaybeInstanced<T>) {
+        let _lock = self.mutexes[index].lock();
+        if self.counters[index] == 0 {
+            return;
+        }
+        let value = match value {
+            MaybeInstanced::Singleton(data) => Arc::try_unwrap(data).unwrap_or_else(|_| panic!("Reloaded objects must not be borrowed by anyone else")),
+            MaybeInstanced::Instance(_) => panic!("Only singletons can be reloaded into a container"),
+        };
+        unsafe {
+            *(*self.data[index]).get() = value;
+        }
+    }
 }
 
 impl<'a, const CAPACITY: usize, T> Loader<'a, CAPACITY, T> {
     pub fn borrow(&self, index: usize) -> MaybeInstanced<T> {
         self.container.borrow(index, self.load_function.borrow())
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+
+    #[test]
+    fn reload_reaches_borrowed_instances() {
+        let container = Container::<2, String>::new();
+        let loads = Cell::new(0);
+        let loader = container.loader(|index| {
+            loads.set(loads.get() + 1);
+            MaybeInstanced::Singleton(Arc::new(format!("loaded {index}")))
+        });
+        let first = loader.borrow(1);
+        let second = loader.borrow(1);
+        assert_eq!(loads.get(), 1);
+        assert_eq!(first.inner(), "loaded 1");
+
+        container.reload(1, MaybeInstanced::Singleton(Arc::new("reloaded".to_string())));
+        assert_eq!(first.inner(), "reloaded");
+        assert_eq!(second.clone().inner(), "reloaded");
+
+        // Once every instance is gone the slot is loaded afresh, and reloading an empty slot does nothing
+        drop((first, second));
+        container.reload(1, MaybeInstanced::Singleton(Arc::new("ignored".to_string())));
+        assert_eq!(loader.borrow(1).inner(), "loaded 1");
+        assert_eq!(loads.get(), 2);
+    }
+}
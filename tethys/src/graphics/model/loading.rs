@@ -1,7 +1,22 @@
-use std::{fs::{self, File}, io::Write};
+use std::{cell::RefCell, fs, path::Path};
 
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, SquareMatrix, Vector3, Vector4};
 use serde::{Deserialize, Serialize};
 
+use super::optimize::Bounds;
+use super::texture::TextureData;
+
+/// glTF is Y up, whereas models are Z up with X forward, as exported from Blender. Columns are
+/// listed in turn, so this maps (x, y, z) to (x, -z, y).
+const GLTF_TO_MODEL: Matrix4<f32> = Matrix4::new(
+    1., 0., 0., 0.,
+    0., 0., 1., 0.,
+    0., -1., 0., 0.,
+    0., 0., 0., 1.,
+);
+
 
 #[derive(Serialize, Deserialize)]
 pub struct LoadModel {
@@ -17,74 +32,199 @@ pub struct LoadMesh {
     pub texcoords: Vec<f32>,
     pub indices: Vec<u32>,
     pub material_id: usize,
+    /// Four per vertex, with the handedness of the bitangent last. These are filled in by
+    /// `LoadModel::optimize` for meshes with a normal map, and are otherwise empty.
+    pub tangents: Vec<f32>,
+    /// Filled in by `LoadModel::optimize`
+    pub bounds: Option<Bounds>,
 }
 
+/// A material with both Blinn-Phong and metallic-roughness parameters. The diffuse color is also the
+/// base color, which multiplies the diffuse texture, and the metallic and roughness multiply the
+/// blue and green channels of the metallic-roughness texture, as in glTF.
 #[derive(Serialize, Deserialize)]
 pub struct LoadMaterial {
     pub name: String,
     pub diffuse: [f32; 3],
     pub specular: [f32; 3],
     pub shininess: f32,
+    pub metallic: f32,
+    pub roughness: f32,
+    /// How much the red channel of the occlusion texture darkens indirect light, from 0 to 1
+    pub occlusion_strength: f32,
     pub normal_texture: Vec<u8>,
     pub diffuse_texture: Vec<u8>,
+    pub metallic_roughness_texture: Vec<u8>,
+    pub occlusion_texture: Vec<u8>,
+    pub normal_sampler: SamplerDescription,
+    pub diffuse_sampler: SamplerDescription,
+    pub metallic_roughness_sampler: SamplerDescription,
+    pub occlusion_sampler: SamplerDescription,
 }
 
-impl LoadMaterial {
-    pub fn load_mtl(file_path: &str) -> Self {
-        let base_path = std::path::Path::new(&file_path).parent().unwrap();
-        let mat_buf = fs::read_to_string(&file_path).expect(&format!("Failed to read MTL file {:?}", file_path));
-        let materials = tobj::load_mtl_buf(&mut mat_buf.as_bytes()).unwrap_or_default().0;
-        if materials.len() == 0 {
-            panic!("The material file was empty");
+/// How a texture is read between and beyond its texels
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct SamplerDescription {
+    /// What happens beyond the edges of the texture along U and V
+    pub address_modes: [AddressMode; 2],
+    /// Filter used for magnification, minification and between mip levels
+    pub filter: FilterMode,
+    /// Most samples taken along the direction in which the texture is stretched, up to 16. This is
+    /// ignored by nearest filtering and on adapters without anisotropic filtering.
+    pub max_anisotropy: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AddressMode {
+    ClampToEdge,
+    Repeat,
+    MirrorRepeat,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FilterMode {
+    Nearest,
+    Linear,
+}
+
+impl SamplerDescription {
+    /// Linear filtering with as much anisotropy as is supported
+    pub const fn linear(address_mode: AddressMode) -> Self {
+        Self {
+            address_modes: [address_mode; 2],
+            filter: FilterMode::Linear,
+            max_anisotropy: 16,
         }
-        Self::load_mtl_type(&materials[0], base_path)
     }
+}
 
-    fn load_mtl_type(material: &tobj::Material, base_path: &std::path::Path) -> Self {
+impl LoadMaterial {
+    /// Load the first material in an MTL file, along with the textures it refers to
+    pub fn load_mtl(file_path: &str) -> Result<Self> {
+        let text = fs::read_to_string(file_path).with_context(|| format!("Could not read MTL file {file_path}"))?;
+        let (materials, _) = tobj::load_mtl_buf(&mut text.as_bytes())
+            .with_context(|| format!("Could not parse MTL file {file_path}"))?;
+        let material = materials.first().with_context(|| format!("MTL file {file_path} has no materials"))?;
+        Self::load_mtl_type(material, Path::new(file_path), &text)
+    }
+
+    /// Read a material from an MTL file. The metallic and roughness come from the `Pm` and `Pr`
+    /// statements of the PBR extension, and their maps `map_Pm` and `map_Pr` are combined into one
+    /// metallic-roughness texture. Without `Pr`, the roughness is found from the shininess.
+    fn load_mtl_type(material: &tobj::Material, mtl_path: &Path, mtl_text: &str) -> Result<Self> {
         let name = &material.name;
         let diffuse = material.diffuse.unwrap_or([0., 0., 0.]);
         let specular = material.specular.unwrap_or([0., 0., 0.]);
         let shininess = material.shininess.unwrap_or(0.);
-        let normal_texture: Vec<u8> = match &material.normal_texture {
-            Some(path) => {
-                let path = base_path.join(path);
-                fs::read(&path).expect(&format!("Could not open texture {:?}", path))
-            },
-            None => Vec::new()
+        let parameter = |key: &str| -> Result<Option<f32>> {
+            material.unknown_param.get(key).map(|value| value.parse::<f32>().with_context(|| {
+                format!("{}:{}: {key} is not a number", mtl_path.display(), line_of(mtl_text, key))
+            })).transpose()
         };
-        let diffuse_texture = match &material.diffuse_texture {
-            Some(path) => {
-                let path = base_path.join(path);
-                fs::read(&path).expect(&format!("Could not open texture {:?}", path))
-            },
-            None => Vec::new()
+        let metallic = parameter("Pm")?.unwrap_or(0.);
+        // Inverse of the shininess given to glTF materials
+        let roughness = parameter("Pr")?.unwrap_or_else(|| (2. / (shininess.max(0.) + 2.)).powf(0.25));
+        // Textures repeat unless they are clamped with -clamp on. Diffuse textures are mirrored so
+        // that textures which do not tile still look continuous across panels.
+        let (normal_texture, normal_sampler) = match &material.normal_texture {
+            Some(statement) => read_mtl_texture(statement, AddressMode::Repeat, mtl_path, mtl_text)?,
+            None => (Vec::new(), SamplerDescription::linear(AddressMode::Repeat))
+        };
+        let (diffuse_texture, diffuse_sampler) = match &material.diffuse_texture {
+            Some(statement) => read_mtl_texture(statement, AddressMode::MirrorRepeat, mtl_path, mtl_text)?,
+            None => (Vec::new(), SamplerDescription::linear(AddressMode::MirrorRepeat))
         };
+        let metallic_map = material.unknown_param.get("map_Pm")
+            .map(|statement| read_mtl_texture(statement, AddressMode::MirrorRepeat, mtl_path, mtl_text))
+            .transpose()?;
+        let roughness_map = material.unknown_param.get("map_Pr")
+            .map(|statement| read_mtl_texture(statement, AddressMode::MirrorRepeat, mtl_path, mtl_text))
+            .transpose()?;
+        let metallic_roughness_sampler = metallic_map.as_ref().or(roughness_map.as_ref())
+            .map_or(SamplerDescription::linear(AddressMode::MirrorRepeat), |(_, sampler)| *sampler);
+        let metallic_roughness_texture = combine_metallic_roughness(
+            metallic_map.as_ref().map(|(bytes, _)| bytes.as_slice()),
+            roughness_map.as_ref().map(|(bytes, _)| bytes.as_slice()),
+        ).with_context(|| format!("Could not combine the metallic and roughness maps of material {name} in {}", mtl_path.display()))?;
 
-        LoadMaterial {
+        Ok(LoadMaterial {
             name: name.to_string(),
             diffuse,
             specular,
             shininess, 
+            metallic,
+            roughness,
+            occlusion_strength: 1.,
             normal_texture,
             diffuse_texture,
+            metallic_roughness_texture,
+            occlusion_texture: Vec::new(),
+            normal_sampler,
+            diffuse_sampler,
+            metallic_roughness_sampler,
+            occlusion_sampler: SamplerDescription::linear(AddressMode::MirrorRepeat),
+        })
+    }
+
+    /// Check that each texture decodes, returning a description of every problem
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let textures = [
+            ("diffuse", &self.diffuse_texture, true),
+            ("normal", &self.normal_texture, false),
+            ("metallic-roughness", &self.metallic_roughness_texture, false),
+            ("occlusion", &self.occlusion_texture, false),
+        ];
+        for (kind, texture, srgb) in textures {
+            if texture.is_empty() {
+                continue;
+            }
+            if let Err(e) = TextureData::decode(texture, srgb) {
+                problems.push(format!("The {kind} texture of material {} does not decode: {e:#}", self.name));
+            }
         }
+        problems
     }
 
-    pub fn save(&self) {
-        let serialized = bincode::serialize(&self).unwrap();
+    /// Replace each texture with a KTX2 file holding its mip levels, compressed with BC7, or BC5
+    /// for the normal texture. This is slow, so it is done when the material is baked, and means
+    /// that the mip levels do not have to be made when the material is loaded.
+    pub fn compress_textures(&mut self) -> Result<()> {
+        // Only the diffuse texture holds colors, which are stored as sRGB
+        let textures = [
+            ("diffuse", &mut self.diffuse_texture, true, false),
+            ("normal", &mut self.normal_texture, false, true),
+            ("metallic-roughness", &mut self.metallic_roughness_texture, false, false),
+            ("occlusion", &mut self.occlusion_texture, false, false),
+        ];
+        for (kind, texture, srgb, normal_map) in textures {
+            if texture.is_empty() {
+                continue;
+            }
+            let data = TextureData::decode(texture, srgb)
+                .with_context(|| format!("Could not decode the {kind} texture of material {}", self.name))?;
+            *texture = data.compress(normal_map).to_ktx2();
+        }
+        Ok(())
+    }
 
-        let mut file = File::create(format!("build/{}.bin", self.name)).unwrap();
-        file.write_all(&serialized).unwrap();
+    pub fn save(&self) -> Result<()> {
+        let serialized = bincode::serialize(&self)?;
+        let path = format!("build/{}.bin", self.name);
+        fs::write(&path, serialized).with_context(|| format!("Could not write {path}"))
     }
 }
 
 impl LoadModel {
-    pub fn load_obj(file_path: &str) -> Self {
-        let base_path = std::path::Path::new(&file_path).parent().unwrap();
-    
-        let obj_buf = fs::read_to_string(&file_path).expect(&format!("Failed to read OBJ file {:?}", file_path));
+    pub fn load_obj(file_path: &str) -> Result<Self> {
+        let base_path = Path::new(file_path).parent().unwrap_or(Path::new(""));
+        let obj_text = fs::read_to_string(file_path).with_context(|| format!("Could not read OBJ file {file_path}"))?;
+
+        // Keep the text of each material library so that missing textures can be traced to a line
+        let libraries = RefCell::new(Vec::new());
+        let library_error = RefCell::new(None);
         let (models, materials) = tobj::load_obj_buf(
-            &mut obj_buf.as_bytes(),
+            &mut obj_text.as_bytes(),
             &tobj::LoadOptions {
                 triangulate: true,
                 single_index: true,
@@ -92,10 +232,24 @@ impl LoadModel {
             },
             |p| {
                 let mtl_path = base_path.join(p);
-                let mat_buf = fs::read_to_string(&mtl_path).expect(&format!("Failed to read MTL file {:?}", p));
-                tobj::load_mtl_buf(&mut mat_buf.as_bytes())
+                match fs::read_to_string(&mtl_path) {
+                    Ok(text) => {
+                        let result = tobj::load_mtl_buf(&mut text.as_bytes());
+                        libraries.borrow_mut().push((mtl_path, text));
+                        result
+                    },
+                    Err(e) => {
+                        *library_error.borrow_mut() = Some(anyhow::Error::new(e).context(format!("Could not read MTL file {}", mtl_path.display())));
+                        Err(tobj::LoadError::OpenFileFailed)
+                    },
+                }
             },
-        ).expect("Failed to load OBJ data");
+        ).with_context(|| format!("Could not parse OBJ file {file_path}"))?;
+        let materials = materials.map_err(|e| {
+            let line = line_of(&obj_text, "mtllib");
+            library_error.take().unwrap_or_else(|| e.into())
+                .context(format!("{file_path}:{line}: Could not load the material library"))
+        })?;
     
         let meshes = models.iter().map(|model| {
             let mesh = &model.mesh;
@@ -110,41 +264,327 @@ impl LoadModel {
                 texcoords,
                 indices,
                 material_id,
+                tangents: Vec::new(),
+                bounds: None,
             }
         }).collect::<Vec<_>>();
 
-        let materials = materials.unwrap_or_default().iter().map(|material| {
-            LoadMaterial::load_mtl_type(material, base_path)
-        }).collect::<Vec<_>>();
+        let libraries = libraries.into_inner();
+        let materials = materials.iter().map(|material| {
+            // Find the library which defines the material
+            let newmtl = format!("newmtl {}", material.name);
+            let (mtl_path, mtl_text) = libraries.iter()
+                .find(|(_, text)| text.lines().any(|line| line.trim() == newmtl))
+                .or(libraries.last())
+                .context("A material was loaded without a material library")?;
+            LoadMaterial::load_mtl_type(material, mtl_path, mtl_text)
+        }).collect::<Result<Vec<_>>>()?;
     
-        Self {
+        Ok(Self {
             file_path: file_path.to_owned(),
             meshes,
             materials
+        })
+    }
+
+    /// Load a `.gltf` or `.glb` file. Every mesh in the default scene is placed by its node
+    /// transform, and each primitive becomes a separate mesh. Metallic-roughness materials are
+    /// also approximated by the diffuse, specular and shininess of `LoadMaterial`.
+    pub fn load_gltf(file_path: &str) -> Result<Self> {
+        Self::read_gltf(file_path).with_context(|| format!("Could not load glTF file {file_path}"))
+    }
+
+    fn read_gltf(file_path: &str) -> Result<Self> {
+        let base_path = Path::new(file_path).parent().unwrap_or(Path::new(""));
+        let gltf::Gltf { document, mut blob } = gltf::Gltf::open(file_path)?;
+        let buffers = document.buffers().map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => blob.take().context("The glTF binary chunk is missing"),
+            gltf::buffer::Source::Uri(uri) => read_uri(uri, base_path),
+        }).collect::<Result<Vec<_>>>()?;
+
+        let mut materials = document.materials()
+            .map(|material| load_gltf_material(&material, &buffers, base_path))
+            .collect::<Result<Vec<_>>>()?;
+        let mut default_material = None;
+        let mut meshes = Vec::new();
+        let scene = document.default_scene().or_else(|| document.scenes().next()).context("The glTF file has no scenes")?;
+        let mut stack = scene.nodes().map(|node| (node, GLTF_TO_MODEL)).collect::<Vec<_>>();
+        while let Some((node, parent)) = stack.pop() {
+            let transform = parent * Matrix4::from(node.transform().matrix());
+            stack.extend(node.children().map(|child| (child, transform)));
+            let Some(mesh) = node.mesh() else { continue };
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    log::warn!("Skipping a primitive of {:?} in {}, which is not made of triangles", mesh.name(), file_path);
+                    continue;
+                }
+                let material_id = match (primitive.material().index(), default_material) {
+                    (Some(index), _) => index,
+                    (None, Some(index)) => index,
+                    (None, None) => {
+                        // Primitives without a material use the glTF default
+                        materials.push(load_gltf_material(&primitive.material(), &buffers, base_path)?);
+                        default_material = Some(materials.len() - 1);
+                        materials.len() - 1
+                    },
+                };
+                meshes.push(load_gltf_primitive(&primitive, &buffers, transform, material_id)?);
+            }
         }
+
+        Ok(Self {
+            file_path: file_path.to_owned(),
+            meshes,
+            materials,
+        })
     }
 
-    pub fn save(&self) {
-        let end = std::path::Path::new(&self.file_path).file_stem().unwrap().to_str().unwrap();
-        let serialized = bincode::serialize(&self).unwrap();
+    /// Check that the model can be drawn: every mesh needs a normal and a texture coordinate for
+    /// each vertex, indices within its vertices and a material which exists. The textures of the
+    /// materials must also decode. A description of every problem is returned.
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            let n_vertices = mesh.positions.len() / 3;
+            if mesh.normals.len() != 3 * n_vertices {
+                problems.push(format!("Mesh {i} has {} normals for {n_vertices} vertices", mesh.normals.len() / 3));
+            }
+            if mesh.texcoords.len() != 2 * n_vertices {
+                problems.push(format!("Mesh {i} has {} texture coordinates for {n_vertices} vertices", mesh.texcoords.len() / 2));
+            }
+            if mesh.indices.iter().any(|index| *index as usize >= n_vertices) {
+                problems.push(format!("Mesh {i} has indices beyond its {n_vertices} vertices"));
+            }
+            if mesh.material_id >= self.materials.len() {
+                problems.push(format!("Mesh {i} uses material {}, but there are only {}", mesh.material_id, self.materials.len()));
+            }
+        }
+        for material in &self.materials {
+            problems.extend(material.validate());
+        }
+        problems
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let end = Path::new(&self.file_path).file_stem().and_then(|stem| stem.to_str())
+            .with_context(|| format!("Could not name the baked file for {}", self.file_path))?;
+        let serialized = bincode::serialize(&self)?;
+        let path = format!("build/{end}.bin");
+        fs::write(&path, serialized).with_context(|| format!("Could not write {path}"))
+    }
+}
+
+fn load_gltf_primitive(primitive: &gltf::Primitive, buffers: &[Vec<u8>], transform: Matrix4<f32>, material_id: usize) -> Result<LoadMesh> {
+    let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(|data| data.as_slice()));
+    let normal_transform = Matrix3::from_cols(transform.x.truncate(), transform.y.truncate(), transform.z.truncate())
+        .invert()
+        .context("A glTF node transform is singular")?
+        .transpose();
+
+    let positions = reader.read_positions().context("A glTF primitive has no positions")?
+        .map(|p| (transform * Vector4::new(p[0], p[1], p[2], 1.)).truncate())
+        .collect::<Vec<_>>();
+    let mut indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect::<Vec<_>>(),
+        None => (0..positions.len() as u32).collect(),
+    };
+    // Mirroring transforms turn the triangles inside out
+    if transform.determinant() < 0. {
+        for triangle in indices.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+    let normals = match reader.read_normals() {
+        Some(normals) => normals.map(|n| (normal_transform * Vector3::from(n)).normalize()).collect(),
+        None => smooth_normals(&positions, &indices),
+    };
+    // The texture V axis points down in glTF but up in OBJ
+    let texcoords = match reader.read_tex_coords(0) {
+        Some(texcoords) => texcoords.into_f32().flat_map(|[u, v]| [u, 1. - v]).collect(),
+        None => vec![0.; 2 * positions.len()],
+    };
+
+    Ok(LoadMesh {
+        positions: positions.iter().flat_map(|p| [p.x, p.y, p.z]).collect(),
+        normals: normals.iter().flat_map(|n| [n.x, n.y, n.z]).collect(),
+        texcoords,
+        indices,
+        material_id,
+        tangents: Vec::new(),
+        bounds: None,
+    })
+}
+
+/// Average the normals of the triangles around each vertex, weighted by area
+fn smooth_normals(positions: &[Vector3<f32>], indices: &[u32]) -> Vec<Vector3<f32>> {
+    let mut normals = vec![Vector3::new(0., 0., 0.); positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+        let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        for i in [a, b, c] {
+            normals[i] += normal;
+        }
+    }
+    normals.into_iter().map(|n| if n.magnitude2() > 0. { n.normalize() } else { Vector3::unit_z() }).collect()
+}
+
+fn load_gltf_material(material: &gltf::Material, buffers: &[Vec<u8>], base_path: &Path) -> Result<LoadMaterial> {
+    let pbr = material.pbr_metallic_roughness();
+    let [r, g, b, _] = pbr.base_color_factor();
+    let metallic = pbr.metallic_factor();
+    let roughness = pbr.roughness_factor();
+    // Metals tint their reflections, and rough surfaces have dim, wide highlights. The Blinn-Phong
+    // exponent which matches a GGX lobe of roughness r is about 2 / r^4 - 2.
+    let specular = [r, g, b].map(|c| (1. - roughness) * (1. - metallic + metallic * c));
+    let shininess = (2. / roughness.powi(4).max(1e-6) - 2.).clamp(1., 1000.);
+    let (diffuse_texture, diffuse_sampler) = match pbr.base_color_texture() {
+        Some(info) => load_gltf_texture(&info.texture(), buffers, base_path)?,
+        None => (Vec::new(), SamplerDescription::linear(AddressMode::Repeat)),
+    };
+    let (normal_texture, normal_sampler) = match material.normal_texture() {
+        Some(info) => load_gltf_texture(&info.texture(), buffers, base_path)?,
+        None => (Vec::new(), SamplerDescription::linear(AddressMode::Repeat)),
+    };
+    let (metallic_roughness_texture, metallic_roughness_sampler) = match pbr.metallic_roughness_texture() {
+        Some(info) => load_gltf_texture(&info.texture(), buffers, base_path)?,
+        None => (Vec::new(), SamplerDescription::linear(AddressMode::Repeat)),
+    };
+    let (occlusion_texture, occlusion_sampler, occlusion_strength) = match material.occlusion_texture() {
+        Some(info) => {
+            let (texture, sampler) = load_gltf_texture(&info.texture(), buffers, base_path)?;
+            (texture, sampler, info.strength())
+        },
+        None => (Vec::new(), SamplerDescription::linear(AddressMode::Repeat), 1.),
+    };
+
+    Ok(LoadMaterial {
+        name: match (material.name(), material.index()) {
+            (Some(name), _) => name.to_owned(),
+            (None, Some(index)) => format!("material{index}"),
+            (None, None) => "default".to_owned(),
+        },
+        diffuse: [r, g, b],
+        specular,
+        shininess,
+        metallic,
+        roughness,
+        occlusion_strength,
+        normal_texture,
+        diffuse_texture,
+        metallic_roughness_texture,
+        occlusion_texture,
+        normal_sampler,
+        diffuse_sampler,
+        metallic_roughness_sampler,
+        occlusion_sampler,
+    })
+}
+
+/// Pack separate metallic and roughness maps into the blue and green channels of one PNG, as glTF
+/// stores them. A missing map leaves its channel white, so that only its factor applies. The
+/// roughness map is stretched to the size of the metallic map if they differ.
+fn combine_metallic_roughness(metallic: Option<&[u8]>, roughness: Option<&[u8]>) -> Result<Vec<u8>> {
+    let metallic = metallic.map(image::load_from_memory).transpose()?.map(|image| image.to_luma8());
+    let roughness = roughness.map(image::load_from_memory).transpose()?.map(|image| image.to_luma8());
+    let (width, height) = match (&metallic, &roughness) {
+        (Some(map), _) | (None, Some(map)) => map.dimensions(),
+        (None, None) => return Ok(Vec::new()),
+    };
+    let roughness = roughness.map(|map| if map.dimensions() == (width, height) {
+        map
+    } else {
+        image::imageops::resize(&map, width, height, image::imageops::FilterType::Triangle)
+    });
+    let combined = image::RgbaImage::from_fn(width, height, |x, y| {
+        let metallic = metallic.as_ref().map_or(255, |map| map.get_pixel(x, y).0[0]);
+        let roughness = roughness.as_ref().map_or(255, |map| map.get_pixel(x, y).0[0]);
+        image::Rgba([255, roughness, metallic, 255])
+    });
+    let mut bytes = Vec::new();
+    combined.write_to(&mut std::io::Cursor::new(&mut bytes), image::ImageFormat::Png)?;
+    Ok(bytes)
+}
+
+/// Read the encoded bytes of a texture's image, and describe its sampler
+fn load_gltf_texture(texture: &gltf::Texture, buffers: &[Vec<u8>], base_path: &Path) -> Result<(Vec<u8>, SamplerDescription)> {
+    let sampler = texture.sampler();
+    let address_mode = |mode| match mode {
+        gltf::texture::WrappingMode::ClampToEdge => AddressMode::ClampToEdge,
+        gltf::texture::WrappingMode::MirroredRepeat => AddressMode::MirrorRepeat,
+        gltf::texture::WrappingMode::Repeat => AddressMode::Repeat,
+    };
+    // Pixel art asks for nearest magnification, and then gets nearest filtering throughout
+    let filter = match sampler.mag_filter() {
+        Some(gltf::texture::MagFilter::Nearest) => FilterMode::Nearest,
+        _ => FilterMode::Linear,
+    };
+    let description = SamplerDescription {
+        address_modes: [address_mode(sampler.wrap_s()), address_mode(sampler.wrap_t())],
+        filter,
+        max_anisotropy: 16,
+    };
+    Ok((load_gltf_image(&texture.source(), buffers, base_path)?, description))
+}
 
-        let mut file = File::create(format!("build/{}.bin", end)).unwrap();
-        file.write_all(&serialized).unwrap();
+/// Read the encoded bytes of an image, which may be embedded in a buffer
+fn load_gltf_image(image: &gltf::Image, buffers: &[Vec<u8>], base_path: &Path) -> Result<Vec<u8>> {
+    match image.source() {
+        gltf::image::Source::View { view, .. } => {
+            let buffer = buffers.get(view.buffer().index()).context("A glTF image refers to a missing buffer")?;
+            let bytes = buffer.get(view.offset()..view.offset() + view.length()).context("A glTF image lies outside its buffer")?;
+            Ok(bytes.to_vec())
+        },
+        gltf::image::Source::Uri { uri, .. } => read_uri(uri, base_path),
     }
 }
 
-#[macro_export]
-macro_rules! include_model {
-    ($file:expr) => {{
-        let bytes: &[u8] = include_bytes!(concat!("../../build/", $file, ".bin"));
-        bincode::deserialize::<LoadModel>(bytes).expect("Failed to deserialize the file")
-    }};
+/// Read a texture statement from an MTL file, which is the texture's file name after any options,
+/// pointing at the line of the statement on failure. Only the `-clamp` option is used, which picks
+/// between clamping and `address_mode`.
+fn read_mtl_texture(statement: &str, address_mode: AddressMode, mtl_path: &Path, mtl_text: &str) -> Result<(Vec<u8>, SamplerDescription)> {
+    let line = line_of(mtl_text, statement);
+    let mut words = statement.split_whitespace().peekable();
+    let mut sampler = SamplerDescription::linear(address_mode);
+    while let Some(option) = words.next_if(|word| word.starts_with('-')) {
+        match option {
+            "-clamp" => if words.next() == Some("on") {
+                sampler.address_modes = [AddressMode::ClampToEdge; 2];
+            },
+            // These take up to three numbers
+            "-o" | "-s" | "-t" => while words.next_if(|word| word.parse::<f32>().is_ok()).is_some() {},
+            "-mm" => { words.nth(1); },
+            _ => { words.next(); },
+        }
+    }
+    let texture = words.collect::<Vec<_>>().join(" ");
+    if texture.is_empty() {
+        bail!("{}:{line}: The texture statement has no file name", mtl_path.display());
+    }
+
+    let path = mtl_path.parent().unwrap_or(Path::new("")).join(&texture);
+    let bytes = fs::read(&path).with_context(|| {
+        format!("{}:{line}: Could not read texture {}", mtl_path.display(), path.display())
+    })?;
+    Ok((bytes, sampler))
 }
 
-#[macro_export]
-macro_rules! include_material {
-    ($file:expr) => {{
-        let bytes: &[u8] = include_bytes!(concat!("../../build/", $file, ".bin"));
-        bincode::deserialize::<LoadMaterial>(bytes).expect("Failed to deserialize the file")
-    }};
-}
\ No newline at end of file
+/// One-based number of the first line containing `needle`, or 0 if there is none
+fn line_of(text: &str, needle: &str) -> usize {
+    text.lines().position(|line| line.contains(needle)).map_or(0, |i| i + 1)
+}
+
+/// Read a glTF URI, which is either a base64 data URI or a path relative to the glTF file
+fn read_uri(uri: &str, base_path: &Path) -> Result<Vec<u8>> {
+    match uri.strip_prefix("data:") {
+        Some(data) => {
+            let Some((_, encoded)) = data.split_once(";base64,") else {
+                bail!("Only base64 data URIs are supported");
+            };
+            Ok(BASE64.decode(encoded)?)
+        },
+        None => {
+            let path = base_path.join(uri.replace("%20", " "));
+            fs::read(&path).with_context(|| format!("Could not read {:?}", path))
+        },
+    }
+}
@@ -6,22 +6,36 @@ use wgpu::util::DeviceExt;
 use crate::prelude::ShaderBinding;
 use super::super::Graphics;
 use super::container::{Loader, Container, MaybeInstanced};
-use super::loading::LoadMaterial;
+use super::loading::{AddressMode, FilterMode, LoadMaterial, SamplerDescription};
+use super::texture::TextureData;
 
 pub type Material = MaybeInstanced<wgpu::BindGroup>;
 pub type MaterialContainer<const CAPACITY: usize> = Container<CAPACITY, wgpu::BindGroup>;
 pub type MaterialLoader<'a, const CAPACITY: usize> = Loader<'a, CAPACITY, wgpu::BindGroup>;
 
 
+/// Texel used in place of a missing texture, which leaves its factors unchanged
+const WHITE: [u8; 4] = [255, 255, 255, 255];
+/// Texel used in place of a missing normal map, pointing straight out of the surface
+const FLAT_NORMAL: [u8; 4] = [128, 128, 255, 255];
+
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct MateriallUniform {
     light_info: [f32; 4],
+    base_color: [f32; 4],
+    pbr_info: [f32; 4],
 }
 impl MateriallUniform {
-    fn new(diffuse: f32, specular: f32, shininess: f32) -> Self {
+    /// The diffuse and specular strengths are the averages of the diffuse and specular colors
+    fn new(material: &LoadMaterial) -> Self {
+        let diffuse = material.diffuse.iter().sum::<f32>() / 3.;
+        let specular = material.specular.iter().sum::<f32>() / 3.;
+        let [r, g, b] = material.diffuse;
         Self {
-            light_info: [diffuse, specular, shininess, 0.]
+            light_info: [diffuse, specular, material.shininess, 0.],
+            base_color: [r, g, b, 1.],
+            pbr_info: [material.metallic, material.roughness, material.occlusion_strength, 0.],
         }
     }
 }
@@ -32,10 +46,7 @@ impl Material {
 
         // Load diffuse texture
         let diffuse_info = if !material.diffuse_texture.is_empty() {
-            Some(make_texture(
-                graphics, &material.diffuse_texture,
-                wgpu::AddressMode::MirrorRepeat
-            ).unwrap())
+            Some(make_texture(graphics, &material.diffuse_texture, &material.diffuse_sampler, true).unwrap())
         } else {
             None
         };
@@ -51,10 +62,7 @@ impl Material {
         }
         // Load normal texture
         let normal_info = if !material.normal_texture.is_empty() {
-            Some(make_texture(
-                graphics, &material.normal_texture,
-                wgpu::AddressMode::Repeat,
-            ).unwrap())
+            Some(make_texture(graphics, &material.normal_texture, &material.normal_sampler, false).unwrap())
         } else {
             None
         };
@@ -70,14 +78,7 @@ impl Material {
         }
 
         // Load model buffer
-        let uniform = MateriallUniform::new(1., 1., material.shininess);
-        let material_buffer = graphics.device.create_buffer_init(
-            &wgpu::util::BufferInitDescriptor {
-                label: Some("Material Buffer"),
-                contents: bytemuck::cast_slice(&[uniform]),
-                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            }
-        );
+        let material_buffer = make_material_buffer(graphics, material);
         entries.push(wgpu::BindGroupEntry {
             binding: entries.len() as u32,
             resource: material_buffer.as_entire_binding(),
@@ -100,18 +101,75 @@ impl Material {
 
         Self::Singleton(Arc::new(bind_group))
     }
+
+    /// Make a material for shaders with `ShaderBinding::Pbr`. Every texture is bound, and missing
+    /// textures are replaced by a single texel which leaves the factors of the material unchanged.
+    pub fn new_pbr(graphics: &Graphics, material: &LoadMaterial) -> Self {
+        let textures = [
+            (&material.diffuse_texture, &material.diffuse_sampler, true, WHITE),
+            (&material.normal_texture, &material.normal_sampler, false, FLAT_NORMAL),
+            (&material.metallic_roughness_texture, &material.metallic_roughness_sampler, false, WHITE),
+            (&material.occlusion_texture, &material.occlusion_sampler, false, WHITE),
+        ].map(|(texture, sampler, srgb, default)| if texture.is_empty() {
+            let texel = image::RgbaImage::from_pixel(1, 1, image::Rgba(default));
+            upload_texture(graphics, TextureData::from_image(texel, srgb), sampler)
+        } else {
+            make_texture(graphics, texture, sampler, srgb).unwrap()
+        });
+
+        let mut entries = textures.iter().enumerate().flat_map(|(i, (texture_view, sampler))| [
+            wgpu::BindGroupEntry {
+                binding: 2 * i as u32,
+                resource: wgpu::BindingResource::TextureView(texture_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2 * i as u32 + 1,
+                resource: wgpu::BindingResource::Sampler(sampler),
+            },
+        ]).collect::<Vec<_>>();
+        let material_buffer = make_material_buffer(graphics, material);
+        entries.push(wgpu::BindGroupEntry {
+            binding: entries.len() as u32,
+            resource: material_buffer.as_entire_binding(),
+        });
+
+        let bind_group = graphics.device.create_bind_group(
+            &wgpu::BindGroupDescriptor {
+                layout: &ShaderBinding::Pbr.get_bind_group_layout(graphics),
+                entries: &entries,
+                label: Some("pbr_material_bind_group"),
+            }
+        );
+
+        Self::Singleton(Arc::new(bind_group))
+    }
 }
 
-pub(super) fn make_texture(graphics: &Graphics, texture: &[u8], address_mode: wgpu::AddressMode) -> Result<(wgpu::TextureView, wgpu::Sampler)> {
-    let image = image::load_from_memory(&texture)?;
-    let rgba = image.to_rgba8();
+fn make_material_buffer(graphics: &Graphics, material: &LoadMaterial) -> wgpu::Buffer {
+    graphics.device.create_buffer_init(
+        &wgpu::util::BufferInitDescriptor {
+            label: Some("Material Buffer"),
+            contents: bytemuck::cast_slice(&[MateriallUniform::new(material)]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        }
+    )
+}
 
-    use image::GenericImageView;
-    let dimensions = image.dimensions();
+/// Decode and upload a texture with every mip level. Images are given a full chain of mip levels.
+pub(super) fn make_texture(graphics: &Graphics, texture: &[u8], sampler: &SamplerDescription, srgb: bool) -> Result<(wgpu::TextureView, wgpu::Sampler)> {
+    Ok(upload_texture(graphics, TextureData::decode(texture, srgb)?, sampler))
+}
+
+/// Upload a texture with every mip level. Block compressed textures are decompressed if the
+/// adapter cannot sample them.
+fn upload_texture(graphics: &Graphics, mut data: TextureData, sampler: &SamplerDescription) -> (wgpu::TextureView, wgpu::Sampler) {
+    if data.format.is_compressed() && !graphics.texture_compression_bc {
+        data = data.decompress();
+    }
 
     let texture_size = wgpu::Extent3d {
-        width: dimensions.0,
-        height: dimensions.1,
+        width: data.width,
+        height: data.height,
         depth_or_array_layers: 1,
     };
     let texture = graphics.device.create_texture(
@@ -119,11 +177,10 @@ pub(super) fn make_texture(graphics: &Graphics, texture: &[u8], address_mode: wg
             // All textures are stored as 3D, we represent our 2D texture
             // by setting depth to 1.
             size: texture_size,
-            mip_level_count: 1, // We'll talk about this a little later
+            mip_level_count: data.levels.len() as u32,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            // Most images are stored using sRGB, so we need to reflect that here.
-            format: wgpu::TextureFormat::Rgba8UnormSrgb,
+            format: data.format.wgpu(),
             // TEXTURE_BINDING tells wgpu that we want to use this texture in shaders
             // COPY_DST means that we want to copy data to this texture
             usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
@@ -131,43 +188,72 @@ pub(super) fn make_texture(graphics: &Graphics, texture: &[u8], address_mode: wg
             // This is the same as with the SurfaceConfig. It
             // specifies what texture formats can be used to
             // create TextureViews for this texture. The base
-            // texture format (Rgba8UnormSrgb in this case) is
-            // always supported. Note that using a different
-            // texture format is not supported on the WebGL2
-            // backend.
+            // texture format is always supported. Note that
+            // using a different texture format is not supported
+            // on the WebGL2 backend.
             view_formats: &[],
         }
     );
 
-    graphics.queue.write_texture(
-        // Tells wgpu where to copy the pixel data
-        wgpu::ImageCopyTexture {
-            texture: &texture,
-            mip_level: 0,
-            origin: wgpu::Origin3d::ZERO,
-            aspect: wgpu::TextureAspect::All,
-        },
-        // The actual pixel data
-        &rgba,
-        // The layout of the texture
-        wgpu::ImageDataLayout {
-            offset: 0,
-            bytes_per_row: Some(4 * dimensions.0),
-            rows_per_image: Some(dimensions.1),
-        },
-        texture_size,
-    );
+    let (block_width, block_height) = data.format.wgpu().block_dimensions();
+    for (mip_level, level) in data.levels.iter().enumerate() {
+        let (width, height) = data.level_extent(mip_level as u32);
+        // Small levels of compressed textures are still copied as whole blocks
+        let (blocks_wide, blocks_high) = (width.div_ceil(block_width), height.div_ceil(block_height));
+        let block_size = data.format.level_size(1, 1) as u32;
+        graphics.queue.write_texture(
+            // Tells wgpu where to copy the pixel data
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: mip_level as u32,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            // The actual pixel data
+            level,
+            // The layout of the texture
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(block_size * blocks_wide),
+                rows_per_image: Some(blocks_high),
+            },
+            wgpu::Extent3d {
+                width: blocks_wide * block_width,
+                height: blocks_high * block_height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
 
     let texture_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let filter = match sampler.filter {
+        FilterMode::Nearest => wgpu::FilterMode::Nearest,
+        FilterMode::Linear => wgpu::FilterMode::Linear,
+    };
+    // Anisotropic filtering is only valid when every filter is linear
+    let anisotropy_clamp = if filter == wgpu::FilterMode::Linear && graphics.anisotropic_filtering {
+        sampler.max_anisotropy.clamp(1, 16)
+    } else {
+        1
+    };
     let sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
-        address_mode_u: address_mode,
-        address_mode_v: address_mode,
-        address_mode_w: address_mode,
-        mag_filter: wgpu::FilterMode::Linear,
-        min_filter: wgpu::FilterMode::Nearest,
-        mipmap_filter: wgpu::FilterMode::Nearest,
+        address_mode_u: address_mode(sampler.address_modes[0]),
+        address_mode_v: address_mode(sampler.address_modes[1]),
+        address_mode_w: address_mode(sampler.address_modes[1]),
+        mag_filter: filter,
+        min_filter: filter,
+        mipmap_filter: filter,
+        anisotropy_clamp,
         ..Default::default()
     });
 
-    Ok((texture_view, sampler))
-}
\ No newline at end of file
+    (texture_view, sampler)
+}
+
+fn address_mode(address_mode: AddressMode) -> wgpu::AddressMode {
+    match address_mode {
+        AddressMode::ClampToEdge => wgpu::AddressMode::ClampToEdge,
+        AddressMode::Repeat => wgpu::AddressMode::Repeat,
+        AddressMode::MirrorRepeat => wgpu::AddressMode::MirrorRepeat,
+    }
+}
@@ -4,20 +4,47 @@ use crate::graphics::primitives::{Vertex, TexVertex};
 
 use super::super::Graphics;
 use super::loading::LoadMesh;
+use super::optimize::Bounds;
+
+/// Integer types which can be used to index vertices
+pub trait Index: bytemuck::Pod {
+    fn to_u32(self) -> u32;
+}
+impl Index for u16 {
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+}
+impl Index for u32 {
+    fn to_u32(self) -> u32 {
+        self
+    }
+}
 
 pub struct Mesh {
     pub(crate) vertex_buffer: wgpu::Buffer,
     pub(crate) index_buffer: wgpu::Buffer,
+    pub(crate) index_format: wgpu::IndexFormat,
     pub(crate) num_indices: u32,
     pub(crate) material_index: usize,
+    bounds: Option<Bounds>,
 }
 
 impl Mesh {
-    pub(super) fn from_obj(graphics: &Graphics, mesh: &LoadMesh) -> Self {
+    /// Upload a loaded mesh. Tangents and bounds which were not baked are worked out here, though
+    /// tangents are only needed when the mesh is `normal_mapped`.
+    pub(super) fn from_obj(graphics: &Graphics, mesh: &LoadMesh, normal_mapped: bool) -> Self {
         let mut vertices = Vec::with_capacity(mesh.positions.len());
-        let n_triangles = mesh.positions.len()/3;
+        let n_vertices = mesh.positions.len()/3;
+        let generated_tangents;
+        let tangents = if mesh.tangents.len() == 4 * n_vertices || !normal_mapped {
+            &mesh.tangents
+        } else {
+            generated_tangents = mesh.generate_tangents();
+            &generated_tangents
+        };
 
-        for i in 0..n_triangles {
+        for i in 0..n_vertices {
             vertices.push(TexVertex {
                 position: [
                     mesh.positions[3*i + 0],
@@ -33,14 +60,21 @@ impl Mesh {
                     mesh.texcoords[2*i + 0],
                     1.-mesh.texcoords[2*i + 1],
                 ],
+                tangent: match tangents.get(4*i..4*i + 4) {
+                    Some(tangent) => [tangent[0], tangent[1], tangent[2], tangent[3]],
+                    None => [0., 0., 0., 1.],
+                },
             })
         }
 
-        let indices = mesh.indices.iter().map(|i| *i as u16).collect::<Vec<_>>();
-        Self::from_vertices(graphics, &vertices, &indices, mesh.material_id)
+        let mut output = Self::from_vertices(graphics, &vertices, &mesh.indices, mesh.material_id);
+        output.bounds = Some(mesh.bounds.unwrap_or_else(|| Bounds::from_positions(&mesh.positions)));
+        output
     }
 
-    pub(super) fn from_vertices<V: Vertex>(graphics: &Graphics, vertices: &[V], indices: &[u16], material_index: usize) -> Mesh {
+    /// Upload a mesh. The indices are stored in 16 bits when there are few enough vertices, and 32
+    /// bits otherwise.
+    pub(super) fn from_vertices<V: Vertex, I: Index>(graphics: &Graphics, vertices: &[V], indices: &[I], material_index: usize) -> Mesh {
         let vertex_buffer = graphics.device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("TexVertex Buffer"),
@@ -49,10 +83,17 @@ impl Mesh {
             }
         );
 
+        let (contents, index_format) = if vertices.len() <= u16::MAX as usize {
+            let indices = indices.iter().map(|i| i.to_u32() as u16).collect::<Vec<_>>();
+            (bytemuck::cast_slice(&indices).to_vec(), wgpu::IndexFormat::Uint16)
+        } else {
+            let indices = indices.iter().map(|i| i.to_u32()).collect::<Vec<_>>();
+            (bytemuck::cast_slice(&indices).to_vec(), wgpu::IndexFormat::Uint32)
+        };
         let index_buffer = graphics.device.create_buffer_init(
             &wgpu::util::BufferInitDescriptor {
                 label: Some("Index Buffer"),
-                contents: bytemuck::cast_slice(&indices),
+                contents: &contents,
                 usage: wgpu::BufferUsages::INDEX,
             }
         );
@@ -60,8 +101,15 @@ impl Mesh {
         Self {
             vertex_buffer,
             index_buffer,
+            index_format,
             num_indices: indices.len() as u32,
             material_index,
+            bounds: None,
         }
     }
-}
\ No newline at end of file
+
+    /// Bounds of the vertices in model space, which are only known for meshes loaded from files
+    pub fn bounds(&self) -> Option<&Bounds> {
+        self.bounds.as_ref()
+    }
+}
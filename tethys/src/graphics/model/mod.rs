@@ -1,7 +1,11 @@
 pub mod container;
+mod assets;
+mod bc;
 mod loading;
 mod mesh;
 mod material;
+mod optimize;
+mod texture;
 
 use std::sync::Arc;
 
@@ -9,10 +13,12 @@ use crate::graphics::primitives::*;
 
 use super::Graphics;
 use container::{Container, Loader, MaybeInstanced};
-pub use mesh::Mesh;
+pub use mesh::{Index, Mesh};
 
-pub use loading::{LoadModel, LoadMaterial};
+pub use assets::AssetManager;
+pub use loading::{AddressMode, FilterMode, LoadModel, LoadMaterial, SamplerDescription};
 pub use material::{Material, MaterialContainer, MaterialLoader};
+pub use optimize::Bounds;
 
 pub type Model = MaybeInstanced<(Vec<Mesh>, Vec<Material>)>;
 pub type ModelContainer<const CAPACITY: usize> = Container<CAPACITY, (Vec<Mesh>, Vec<Material>)>;
@@ -20,36 +26,49 @@ pub type ModelLoader<'a, const CAPACITY: usize> = Loader<'a, CAPACITY, (Vec<Mesh
 
 impl Model {
     pub fn from_obj(graphics: &Graphics, obj: LoadModel) -> Model {
+        Self::from_obj_with(graphics, obj, Material::new)
+    }
+
+    /// Load a model whose materials are made with `Material::new_pbr`, for shaders with
+    /// `ShaderBinding::Pbr`
+    pub fn from_obj_pbr(graphics: &Graphics, obj: LoadModel) -> Model {
+        Self::from_obj_with(graphics, obj, Material::new_pbr)
+    }
+
+    fn from_obj_with(graphics: &Graphics, obj: LoadModel, make_material: fn(&Graphics, &LoadMaterial) -> Material) -> Model {
         let mut meshes = Vec::with_capacity(obj.meshes.len());
         for load_mesh in &obj.meshes {
-            meshes.push(Mesh::from_obj(graphics, load_mesh));
+            let normal_mapped = obj.materials.get(load_mesh.material_id)
+                .is_some_and(|material| !material.normal_texture.is_empty());
+            meshes.push(Mesh::from_obj(graphics, load_mesh, normal_mapped));
         }
 
         let mut materials = Vec::with_capacity(obj.materials.len());
         for load_material in &obj.materials {
-            materials.push(Material::new(graphics, load_material));
+            materials.push(make_material(graphics, load_material));
         }
 
         Model::Singleton(Arc::new((meshes, materials)))
     }
 
     /// Generate a model that uses one material
-    pub fn from_vertices_and_material<V: Vertex>(graphics: &Graphics, vertices: &[V], indices: &[u16], material: Material) -> Model {
+    pub fn from_vertices_and_material<V: Vertex, I: Index>(graphics: &Graphics, vertices: &[V], indices: &[I], material: Material) -> Model {
         let meshes = vec![Mesh::from_vertices(graphics, vertices, indices, 0)];
         let materials = vec![material];
         Model::Singleton(Arc::new((meshes, materials)))
     }
 
     /// Generate a model that uses no materials
-    pub fn from_vertices<V: Vertex>(graphics: &Graphics, vertices: &[V], indices: &[u16]) -> Model {
+    pub fn from_vertices<V: Vertex, I: Index>(graphics: &Graphics, vertices: &[V], indices: &[I]) -> Model {
         let meshes = vec![Mesh::from_vertices(graphics, vertices, indices, 0)];
         Model::Singleton(Arc::new((meshes, Vec::new())))
     }
 
-    /// Get an identifier for this model for the sake of sorting the models
+    /// Get an identifier for this model for the sake of sorting the models. Models share an
+    /// identifier exactly when they share their meshes, so that they can be drawn together.
     pub(crate) fn identifier(&self) -> usize {
         match self {
-            Model::Singleton(_) => 0,
+            Model::Singleton(data) => Arc::as_ptr(data) as usize,
             Model::Instance(instance) => instance.identifier(),
         }
     }
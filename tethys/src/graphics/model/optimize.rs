@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+
+use cgmath::{InnerSpace, MetricSpace, Vector3};
+use serde::{Deserialize, Serialize};
+
+use super::loading::{LoadMesh, LoadModel};
+
+/// Number of vertices modelled in the post-transform cache when ordering triangles
+const CACHE_SIZE: usize = 32;
+
+/// Volumes which contain every vertex of a mesh, in model space
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Bounds {
+    /// Lower corner of the axis-aligned bounding box
+    pub min: [f32; 3],
+    /// Upper corner of the axis-aligned bounding box
+    pub max: [f32; 3],
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+impl Bounds {
+    /// Bound a flat list of positions. The sphere is found with Ritter's algorithm, which is within
+    /// a few percent of the smallest sphere.
+    pub fn from_positions(positions: &[f32]) -> Self {
+        let points = positions.chunks_exact(3).map(|p| Vector3::new(p[0], p[1], p[2])).collect::<Vec<_>>();
+        let Some(first) = points.first() else {
+            return Self { min: [0.; 3], max: [0.; 3], center: [0.; 3], radius: 0. };
+        };
+        let mut min = *first;
+        let mut max = *first;
+        for p in &points {
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+
+        // Start from two points which are nearly the farthest apart, then grow to cover the rest
+        let farthest = |from: Vector3<f32>| *points.iter()
+            .max_by(|a, b| a.distance2(from).total_cmp(&b.distance2(from)))
+            .unwrap();
+        let a = farthest(*first);
+        let b = farthest(a);
+        let mut center = (a + b) / 2.;
+        let mut radius = a.distance(b) / 2.;
+        for p in &points {
+            let distance = p.distance(center);
+            if distance > radius {
+                let new_radius = (radius + distance) / 2.;
+                center += (p - center) * ((new_radius - radius) / distance);
+                radius = new_radius;
+            }
+        }
+
+        Self {
+            min: min.into(),
+            max: max.into(),
+            center: center.into(),
+            radius,
+        }
+    }
+}
+
+impl LoadModel {
+    /// Prepare the model for drawing, which is worth doing once when the model is baked. Identical
+    /// vertices are merged, triangles are ordered so that recently transformed vertices are reused
+    /// and vertices are stored in the order they are first drawn. Meshes with a normal-mapped
+    /// material are given tangents, and every mesh is given its bounds.
+    pub fn optimize(&mut self) {
+        for mesh in &mut self.meshes {
+            let normal_mapped = self.materials.get(mesh.material_id)
+                .is_some_and(|material| !material.normal_texture.is_empty());
+            mesh.deduplicate();
+            mesh.indices = order_for_cache(&mesh.indices, mesh.positions.len() / 3);
+            mesh.order_vertices();
+            mesh.tangents = if normal_mapped { mesh.generate_tangents() } else { Vec::new() };
+            mesh.bounds = Some(Bounds::from_positions(&mesh.positions));
+        }
+    }
+}
+
+impl LoadMesh {
+    /// Point every index at the first of the vertices identical to its own. The duplicates are
+    /// dropped later by `order_vertices`.
+    fn deduplicate(&mut self) {
+        let mut first = HashMap::new();
+        let remap = (0..self.positions.len() / 3).map(|i| {
+            *first.entry(self.vertex_key(i)).or_insert(i as u32)
+        }).collect::<Vec<_>>();
+        for index in &mut self.indices {
+            *index = remap[*index as usize];
+        }
+    }
+
+    /// Bits of every attribute of a vertex, so that vertices can be compared exactly
+    fn vertex_key(&self, i: usize) -> [u32; 8] {
+        let attribute = |values: &[f32], n: usize, j: usize| values.get(n * i + j).map_or(0, |v| v.to_bits());
+        [
+            attribute(&self.positions, 3, 0), attribute(&self.positions, 3, 1), attribute(&self.positions, 3, 2),
+            attribute(&self.normals, 3, 0), attribute(&self.normals, 3, 1), attribute(&self.normals, 3, 2),
+            attribute(&self.texcoords, 2, 0), attribute(&self.texcoords, 2, 1),
+        ]
+    }
+
+    /// Store the vertices in the order the indices first use them, dropping unused vertices
+    fn order_vertices(&mut self) {
+        let mut new_index = vec![u32::MAX; self.positions.len() / 3];
+        let mut order = Vec::new();
+        for index in &mut self.indices {
+            if new_index[*index as usize] == u32::MAX {
+                new_index[*index as usize] = order.len() as u32;
+                order.push(*index as usize);
+            }
+            *index = new_index[*index as usize];
+        }
+        let gather = |values: &[f32], n: usize| {
+            if values.len() < n * new_index.len() {
+                return values.to_vec();
+            }
+            order.iter().flat_map(|i| &values[n * i..n * (i + 1)]).copied().collect()
+        };
+        self.positions = gather(&self.positions, 3);
+        self.normals = gather(&self.normals, 3);
+        self.texcoords = gather(&self.texcoords, 2);
+        self.tangents = gather(&self.tangents, 4);
+    }
+
+    /// Tangents along which the texture U coordinate increases, with the handedness of the
+    /// bitangent in W, averaged over the triangles around each vertex. The V coordinate is flipped
+    /// when meshes are uploaded, so the tangents match the flipped coordinates.
+    pub(crate) fn generate_tangents(&self) -> Vec<f32> {
+        let n_vertices = self.positions.len() / 3;
+        let position = |i: usize| Vector3::new(self.positions[3 * i], self.positions[3 * i + 1], self.positions[3 * i + 2]);
+        let texcoord = |i: usize| match self.texcoords.get(2 * i..2 * i + 2) {
+            Some(uv) => (uv[0], 1. - uv[1]),
+            None => (0., 0.),
+        };
+        let mut tangents = vec![Vector3::new(0., 0., 0.); n_vertices];
+        let mut bitangents = vec![Vector3::new(0., 0., 0.); n_vertices];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            let (edge_1, edge_2) = (position(b) - position(a), position(c) - position(a));
+            let (u_a, v_a) = texcoord(a);
+            let (du_1, dv_1) = (texcoord(b).0 - u_a, texcoord(b).1 - v_a);
+            let (du_2, dv_2) = (texcoord(c).0 - u_a, texcoord(c).1 - v_a);
+            let determinant = du_1 * dv_2 - du_2 * dv_1;
+            if determinant.abs() < f32::EPSILON {
+                continue;
+            }
+            let tangent = (edge_1 * dv_2 - edge_2 * dv_1) / determinant;
+            let bitangent = (edge_2 * du_1 - edge_1 * du_2) / determinant;
+            for i in [a, b, c] {
+                tangents[i] += tangent;
+                bitangents[i] += bitangent;
+            }
+        }
+
+        (0..n_vertices).flat_map(|i| {
+            let normal = match self.normals.get(3 * i..3 * i + 3) {
+                Some(n) => Vector3::new(n[0], n[1], n[2]),
+                None => Vector3::unit_z(),
+            };
+            // Gram-Schmidt, falling back on any perpendicular where the texture is degenerate
+            let mut tangent = tangents[i] - normal * normal.dot(tangents[i]);
+            if tangent.magnitude2() < f32::EPSILON {
+                let axis = if normal.x.abs() < 0.9 { Vector3::unit_x() } else { Vector3::unit_y() };
+                tangent = normal.cross(axis);
+            }
+            let tangent = tangent.normalize();
+            let handedness = if normal.cross(tangent).dot(bitangents[i]) < 0. { -1. } else { 1. };
+            [tangent.x, tangent.y, tangent.z, handedness]
+        }).collect()
+    }
+}
+
+/// Order triangles with Forsyth's linear-speed vertex cache optimisation. Each step draws the
+/// triangle whose vertices score best, favouring vertices in the cache and vertices with few
+/// triangles left to draw, so that fans are finished instead of left behind.
+fn order_for_cache(indices: &[u32], n_vertices: usize) -> Vec<u32> {
+    let n_triangles = indices.len() / 3;
+    let mut vertex_triangles = vec![Vec::new(); n_vertices];
+    for (triangle, vertices) in indices.chunks_exact(3).enumerate() {
+        for vertex in vertices {
+            vertex_triangles[*vertex as usize].push(triangle);
+        }
+    }
+    let mut cache_position = vec![None; n_vertices];
+    let mut vertex_score = (0..n_vertices)
+        .map(|v| score(None, vertex_triangles[v].len()))
+        .collect::<Vec<_>>();
+    let triangle_score = |triangle: usize, vertex_score: &[f32]| {
+        indices[3 * triangle..3 * triangle + 3].iter().map(|v| vertex_score[*v as usize]).sum::<f32>()
+    };
+    let mut drawn = vec![false; n_triangles];
+    let mut cache: Vec<u32> = Vec::with_capacity(CACHE_SIZE + 3);
+    let mut output = Vec::with_capacity(3 * n_triangles);
+    // Triangles before this have all been drawn, for when no triangle touches the cache
+    let mut next_undrawn = 0;
+
+    for _ in 0..n_triangles {
+        let best = cache.iter()
+            .flat_map(|v| &vertex_triangles[*v as usize])
+            .copied()
+            .max_by(|a, b| triangle_score(*a, &vertex_score).total_cmp(&triangle_score(*b, &vertex_score)))
+            .unwrap_or_else(|| {
+                while drawn[next_undrawn] {
+                    next_undrawn += 1;
+                }
+                next_undrawn
+            });
+        drawn[best] = true;
+        let vertices = &indices[3 * best..3 * best + 3];
+        output.extend_from_slice(vertices);
+
+        for vertex in vertices {
+            vertex_triangles[*vertex as usize].retain(|triangle| *triangle != best);
+            cache.retain(|cached| cached != vertex);
+        }
+        let evicted = cache.len().saturating_sub(CACHE_SIZE - 3);
+        let mut changed = cache.split_off(cache.len() - evicted);
+        for vertex in &changed {
+            cache_position[*vertex as usize] = None;
+        }
+        cache.splice(0..0, vertices.iter().copied());
+        for (position, vertex) in cache.iter().enumerate() {
+            cache_position[*vertex as usize] = Some(position);
+        }
+        changed.extend_from_slice(&cache);
+        for vertex in changed {
+            let v = vertex as usize;
+            vertex_score[v] = score(cache_position[v], vertex_triangles[v].len());
+        }
+    }
+    output
+}
+
+/// Forsyth's score for a vertex at `cache_position` which still has `remaining` triangles to draw
+fn score(cache_position: Option<usize>, remaining: usize) -> f32 {
+    if remaining == 0 {
+        return -1.;
+    }
+    let cache_score = match cache_position {
+        None => 0.,
+        // The last triangle's vertices score the same, so that its winding does not matter
+        Some(position) if position < 3 => 0.75,
+        Some(position) => (1. - (position - 3) as f32 / (CACHE_SIZE - 3) as f32).powf(1.5),
+    };
+    cache_score + 2. / (remaining as f32).sqrt()
+}
@@ -0,0 +1,303 @@
+use anyhow::{bail, Context, Result};
+
+use super::bc;
+
+/// Identifies a KTX2 file
+const KTX2_IDENTIFIER: [u8; 12] = [0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a];
+const DDS_MAGIC: &[u8; 4] = b"DDS ";
+
+/// How the texels of a texture are stored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum TextureFormat {
+    Rgba8 { srgb: bool },
+    /// Two linear channels, for normal maps
+    Bc5,
+    Bc7 { srgb: bool },
+}
+
+/// A texture with every mip level, largest first
+pub(crate) struct TextureData {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+impl TextureFormat {
+    pub fn is_compressed(self) -> bool {
+        !matches!(self, TextureFormat::Rgba8 { .. })
+    }
+
+    pub fn wgpu(self) -> wgpu::TextureFormat {
+        match self {
+            TextureFormat::Rgba8 { srgb: true } => wgpu::TextureFormat::Rgba8UnormSrgb,
+            TextureFormat::Rgba8 { srgb: false } => wgpu::TextureFormat::Rgba8Unorm,
+            TextureFormat::Bc5 => wgpu::TextureFormat::Bc5RgUnorm,
+            TextureFormat::Bc7 { srgb: true } => wgpu::TextureFormat::Bc7RgbaUnormSrgb,
+            TextureFormat::Bc7 { srgb: false } => wgpu::TextureFormat::Bc7RgbaUnorm,
+        }
+    }
+
+    /// Bytes in a level of the given size. Compressed levels are padded out to whole blocks.
+    pub fn level_size(self, width: u32, height: u32) -> usize {
+        match self {
+            TextureFormat::Rgba8 { .. } => 4 * width as usize * height as usize,
+            _ => 16 * width.div_ceil(4) as usize * height.div_ceil(4) as usize,
+        }
+    }
+
+    /// Vulkan format number, which is used by KTX2
+    fn vk_format(self) -> u32 {
+        match self {
+            TextureFormat::Rgba8 { srgb: false } => 37,
+            TextureFormat::Rgba8 { srgb: true } => 43,
+            TextureFormat::Bc5 => 141,
+            TextureFormat::Bc7 { srgb: false } => 145,
+            TextureFormat::Bc7 { srgb: true } => 146,
+        }
+    }
+
+    fn from_vk_format(vk_format: u32) -> Option<Self> {
+        [
+            TextureFormat::Rgba8 { srgb: false },
+            TextureFormat::Rgba8 { srgb: true },
+            TextureFormat::Bc5,
+            TextureFormat::Bc7 { srgb: false },
+            TextureFormat::Bc7 { srgb: true },
+        ].into_iter().find(|format| format.vk_format() == vk_format)
+    }
+}
+
+impl TextureData {
+    /// Decode a PNG or JPEG image, or read a KTX2 or DDS file. Images are given a full chain of mip
+    /// levels, each made by halving the last, and are read as sRGB if `srgb` is set. KTX2 and DDS
+    /// files keep the format they were saved with.
+    pub fn decode(bytes: &[u8], srgb: bool) -> Result<Self> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            Self::from_ktx2(bytes).context("Could not read KTX2 texture")
+        } else if bytes.starts_with(DDS_MAGIC) {
+            Self::from_dds(bytes).context("Could not read DDS texture")
+        } else {
+            Ok(Self::from_image(image::load_from_memory(bytes)?.to_rgba8(), srgb))
+        }
+    }
+
+    /// Give an image a full chain of mip levels, read as sRGB if `srgb` is set
+    pub fn from_image(image: image::RgbaImage, srgb: bool) -> Self {
+        let (width, height) = image.dimensions();
+        let mip_level_count = 32 - width.max(height).max(1).leading_zeros();
+        let mut levels = Vec::with_capacity(mip_level_count as usize);
+        let mut level = image;
+        for mip_level in 0..mip_level_count {
+            if mip_level > 0 {
+                let (width, height) = ((level.width() / 2).max(1), (level.height() / 2).max(1));
+                level = image::imageops::resize(&level, width, height, image::imageops::FilterType::Triangle);
+            }
+            levels.push(level.as_raw().clone());
+        }
+        Self {
+            format: TextureFormat::Rgba8 { srgb },
+            width,
+            height,
+            levels,
+        }
+    }
+
+    fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|e| anyhow::anyhow!("{e:?}"))?;
+        let header = reader.header();
+        if header.supercompression_scheme.is_some() {
+            bail!("Supercompressed textures are not supported");
+        }
+        if header.pixel_depth > 1 || header.layer_count > 1 || header.face_count > 1 {
+            bail!("Only single 2D textures are supported");
+        }
+        let vk_format = header.format.map_or(0, |format| format.value());
+        let format = TextureFormat::from_vk_format(vk_format)
+            .with_context(|| format!("Vulkan format {vk_format} is not supported"))?;
+        let levels = reader.levels().map(|level| level.data.to_vec()).collect();
+        Self::checked(format, header.pixel_width, header.pixel_height, levels)
+    }
+
+    fn from_dds(bytes: &[u8]) -> Result<Self> {
+        use ddsfile::DxgiFormat;
+        let dds = ddsfile::Dds::read(bytes)?;
+        let format = match dds.get_dxgi_format() {
+            Some(DxgiFormat::R8G8B8A8_UNorm) => TextureFormat::Rgba8 { srgb: false },
+            Some(DxgiFormat::R8G8B8A8_UNorm_sRGB) => TextureFormat::Rgba8 { srgb: true },
+            Some(DxgiFormat::BC5_UNorm) => TextureFormat::Bc5,
+            Some(DxgiFormat::BC7_UNorm) => TextureFormat::Bc7 { srgb: false },
+            Some(DxgiFormat::BC7_UNorm_sRGB) => TextureFormat::Bc7 { srgb: true },
+            format => bail!("The format {format:?} is not supported"),
+        };
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut data = dds.data.as_slice();
+        let mut levels = Vec::new();
+        for mip_level in 0..dds.get_num_mipmap_levels() {
+            let size = format.level_size((width >> mip_level).max(1), (height >> mip_level).max(1));
+            let level = data.get(..size).context("The mip levels are cut short")?;
+            levels.push(level.to_vec());
+            data = &data[size..];
+        }
+        Self::checked(format, width, height, levels)
+    }
+
+    /// Check that the levels are the right size to be uploaded
+    fn checked(format: TextureFormat, width: u32, height: u32, levels: Vec<Vec<u8>>) -> Result<Self> {
+        if format.is_compressed() && (!width.is_multiple_of(4) || !height.is_multiple_of(4)) {
+            bail!("Compressed textures must be a multiple of 4 texels wide and high, not {width}x{height}");
+        }
+        if levels.is_empty() {
+            bail!("The texture has no mip levels");
+        }
+        for (mip_level, level) in levels.iter().enumerate() {
+            let expected = format.level_size((width >> mip_level).max(1), (height >> mip_level).max(1));
+            if level.len() != expected {
+                bail!("Mip level {mip_level} has {} bytes instead of {expected}", level.len());
+            }
+        }
+        Ok(Self { format, width, height, levels })
+    }
+
+    /// Size of a mip level in texels
+    pub fn level_extent(&self, mip_level: u32) -> (u32, u32) {
+        ((self.width >> mip_level).max(1), (self.height >> mip_level).max(1))
+    }
+
+    /// Compress every level to BC5, for normal maps, or otherwise BC7. Textures which are not a
+    /// multiple of 4 texels across cannot be compressed and are returned unchanged.
+    pub fn compress(self, normal_map: bool) -> Self {
+        let TextureFormat::Rgba8 { srgb } = self.format else {
+            return self;
+        };
+        if !self.width.is_multiple_of(4) || !self.height.is_multiple_of(4) {
+            return self;
+        }
+        let (format, encode) = if normal_map {
+            (TextureFormat::Bc5, bc::encode_bc5 as fn(&bc::Block) -> [u8; 16])
+        } else {
+            (TextureFormat::Bc7 { srgb }, bc::encode_bc7 as fn(&bc::Block) -> [u8; 16])
+        };
+        let levels = self.levels.iter().enumerate().map(|(mip_level, level)| {
+            let (width, height) = self.level_extent(mip_level as u32);
+            bc::compress(level, width, height, encode)
+        }).collect();
+        Self { format, levels, ..self }
+    }
+
+    /// Decompress every level to RGBA8, for adapters which cannot sample compressed textures
+    pub fn decompress(self) -> Self {
+        let (format, decode) = match self.format {
+            TextureFormat::Rgba8 { .. } => return self,
+            TextureFormat::Bc5 => (TextureFormat::Rgba8 { srgb: false }, bc::decode_bc5 as fn(&[u8; 16]) -> bc::Block),
+            TextureFormat::Bc7 { srgb } => (TextureFormat::Rgba8 { srgb }, bc::decode_bc7 as fn(&[u8; 16]) -> bc::Block),
+        };
+        let levels = self.levels.iter().enumerate().map(|(mip_level, level)| {
+            let (width, height) = self.level_extent(mip_level as u32);
+            bc::decompress(level, width, height, decode)
+        }).collect();
+        Self { format, levels, ..self }
+    }
+
+    /// Write a KTX2 file holding every level, without supercompression
+    pub fn to_ktx2(&self) -> Vec<u8> {
+        let data_format_descriptor = self.data_format_descriptor();
+        let level_index_start = 80;
+        let dfd_start = level_index_start + 24 * self.levels.len();
+        // Levels are stored smallest first, each aligned to a whole block and to 4 bytes
+        let alignment = if self.format.is_compressed() { 16 } else { 4 };
+        let mut offsets = vec![0; self.levels.len()];
+        let mut end = dfd_start + data_format_descriptor.len();
+        for (mip_level, level) in self.levels.iter().enumerate().rev() {
+            end = end.next_multiple_of(alignment);
+            offsets[mip_level] = end;
+            end += level.len();
+        }
+
+        let mut output = Vec::with_capacity(end);
+        output.extend_from_slice(&KTX2_IDENTIFIER);
+        for field in [
+            self.format.vk_format(),
+            1, // Type size
+            self.width,
+            self.height,
+            0, // Depth
+            0, // Layers
+            1, // Faces
+            self.levels.len() as u32,
+            0, // Supercompression
+            dfd_start as u32,
+            data_format_descriptor.len() as u32,
+            0, // Key/value data offset
+            0, // Key/value data length
+        ] {
+            output.extend_from_slice(&field.to_le_bytes());
+        }
+        output.extend_from_slice(&[0; 16]); // Supercompression global data
+        for (level, offset) in self.levels.iter().zip(&offsets) {
+            for field in [*offset, level.len(), level.len()] {
+                output.extend_from_slice(&(field as u64).to_le_bytes());
+            }
+        }
+        output.extend_from_slice(&data_format_descriptor);
+        for (mip_level, level) in self.levels.iter().enumerate().rev() {
+            output.resize(offsets[mip_level], 0);
+            output.extend_from_slice(level);
+        }
+        output
+    }
+
+    /// The basic data format descriptor block, which repeats the format for readers that do not
+    /// know its Vulkan number
+    fn data_format_descriptor(&self) -> Vec<u8> {
+        // Each sample is a bit offset, bit length, channel and upper value
+        type Sample = (u16, u8, u8, u32);
+        let (color_model, block_size, srgb, samples): (u8, u8, bool, &[Sample]) = match self.format {
+            TextureFormat::Rgba8 { srgb } => (1, 0, srgb, &[(0, 8, 0, 255), (8, 8, 1, 255), (16, 8, 2, 255), (24, 8, 15, 255)]),
+            TextureFormat::Bc5 => (132, 3, false, &[(0, 64, 0, u32::MAX), (64, 64, 1, u32::MAX)]),
+            TextureFormat::Bc7 { srgb } => (134, 3, srgb, &[(0, 128, 0, u32::MAX)]),
+        };
+        let block_length = 24 + 16 * samples.len();
+        let mut output = Vec::with_capacity(4 + block_length);
+        output.extend_from_slice(&(4 + block_length as u32).to_le_bytes());
+        output.extend_from_slice(&0u32.to_le_bytes()); // Khronos basic descriptor
+        output.extend_from_slice(&2u16.to_le_bytes()); // Version
+        output.extend_from_slice(&(block_length as u16).to_le_bytes());
+        // BT.709 primaries, and an sRGB or linear transfer function
+        output.extend_from_slice(&[color_model, 1, if srgb { 2 } else { 1 }, 0]);
+        output.extend_from_slice(&[block_size, block_size, 0, 0]);
+        output.extend_from_slice(&[self.format.level_size(1, 1) as u8, 0, 0, 0, 0, 0, 0, 0]);
+        for (bit_offset, bit_length, channel, upper) in samples {
+            // Alpha is always linear
+            let qualifiers = if *channel == 15 && srgb { 0x10 } else { 0 };
+            output.extend_from_slice(&bit_offset.to_le_bytes());
+            output.extend_from_slice(&[bit_length - 1, channel | qualifiers, 0, 0, 0, 0]);
+            output.extend_from_slice(&0u32.to_le_bytes());
+            output.extend_from_slice(&upper.to_le_bytes());
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image() -> image::RgbaImage {
+        image::RgbaImage::from_fn(8, 4, |x, y| image::Rgba([30 * x as u8, 60 * y as u8, 100, 255]))
+    }
+
+    #[test]
+    fn ktx2_round_trip() {
+        for texture in [
+            TextureData::from_image(image(), true),
+            TextureData::from_image(image(), true).compress(false),
+            TextureData::from_image(image(), false).compress(true),
+        ] {
+            let read = TextureData::decode(&texture.to_ktx2(), false).unwrap();
+            assert_eq!(read.format, texture.format);
+            assert_eq!((read.width, read.height), (texture.width, texture.height));
+            assert_eq!(read.levels, texture.levels, "levels of {:?} differ", texture.format);
+        }
+    }
+}
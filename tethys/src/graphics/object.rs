@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+
 use wgpu::util::DeviceExt;
 
 use crate::prelude::Uniform;
@@ -8,17 +10,19 @@ pub struct Object {
     pub(crate) model: Model,
     pub(crate) object_buffer: wgpu::Buffer,
     pub(crate) bind_group: wgpu::BindGroup,
+    /// Bytes of the last uniform, which are gathered into the instance buffer by instanced shaders
+    pub(crate) uniform: RefCell<Vec<u8>>,
 }
 
 pub enum ObjectHandle<'a> {
     Ref(&'a Object),
-    Own(Object),
+    Own(Box<Object>),
 }
 impl<'a> ObjectHandle<'a> {
     pub fn as_ref(&'a self) -> &'a Object {
         match self {
             ObjectHandle::Ref(object) => object,
-            ObjectHandle::Own(object) => &object,
+            ObjectHandle::Own(object) => object,
         }
     }
 }
@@ -48,6 +52,7 @@ impl Object {
             model,
             object_buffer,
             bind_group,
+            uniform: RefCell::new(bytemuck::bytes_of(&uniform).to_vec()),
         }
     }
 
@@ -75,6 +80,7 @@ impl Object {
             model,
             object_buffer,
             bind_group,
+            uniform: RefCell::new(bytemuck::bytes_of(&U::zeroed()).to_vec()),
         }
     }
 
@@ -100,6 +106,9 @@ impl Object {
     // }
 
     pub fn update<U: Uniform>(&self, graphics: &Graphics, uniform: U) {
-        graphics.queue.write_buffer(&self.object_buffer, 0, bytemuck::cast_slice(&[uniform]))
+        graphics.queue.write_buffer(&self.object_buffer, 0, bytemuck::cast_slice(&[uniform]));
+        let mut bytes = self.uniform.borrow_mut();
+        bytes.clear();
+        bytes.extend_from_slice(bytemuck::bytes_of(&uniform));
     }
 }
\ No newline at end of file
@@ -46,7 +46,7 @@ impl SolidUniform {
         let psi = ((start + stop) / 2. - camera.position).cast::<f32>().unwrap().normalize().z.acos();
         let theta = (-delta.z / delta_mag).acos();
         let phi = f32::atan2(-delta.y, -delta.x);
-        let world = Matrix4::from_translation(start.cast::<f32>().unwrap()) * Matrix4::from_angle_z(Rad(phi)) * Matrix4::from_angle_y(Rad(theta)) * Matrix4::from_angle_z(Rad(psi)) * Matrix4::from_nonuniform_scale(delta_mag, 1., 1.);
+        let world = Matrix4::from_translation((start - camera.position).cast::<f32>().unwrap()) * Matrix4::from_angle_z(Rad(phi)) * Matrix4::from_angle_y(Rad(theta)) * Matrix4::from_angle_z(Rad(psi)) * Matrix4::from_nonuniform_scale(delta_mag, 1., 1.);
 
         Self {
             world: world.into(),
@@ -97,12 +97,15 @@ impl Primitive {
 
 /**
  # `TexVertex`
- Vertex for a model with a texture and normal vectors. Load into the shader using
- ```
+ Vertex for a model with a texture and normal vectors. The tangent points along increasing U, and
+ its W component is the handedness of the bitangent, which is `w * cross(normal, tangent.xyz)`.
+ Load into the shader using
+ ```wgsl
  struct VertexInput {
     @location(0) position: vec3<f32>,
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
+    @location(3) tangent: vec4<f32>,
     }
     ```
     */
@@ -112,6 +115,7 @@ impl Primitive {
     pub position: [f32; 3],
     pub tex_coords: [f32; 2],
     pub normal: [f32; 3],
+    pub tangent: [f32; 4],
 }
 
 /**
@@ -170,7 +174,7 @@ impl Vertex for TexVertex {
                 wgpu::VertexAttribute {
                     offset: std::mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
                     shader_location: 3,
-                    format: wgpu::VertexFormat::Float32x2,
+                    format: wgpu::VertexFormat::Float32x4,
                 },
             ]
         }
@@ -1,47 +1,92 @@
+use wgpu::{CommandEncoder, TextureView};
+
 use super::Graphics;
 use super::camera::Camera;
+use super::light::{Lights, CASCADES};
 use super::model::Material;
 use super::object::ObjectHandle;
-use super::shader::Shader;
+use super::shader::{Shader, ShaderBinding};
+use super::skybox::Skybox;
 
 pub struct RenderPass<'a> {
     graphics: &'a Graphics<'a>,
-    render_pass: &'a mut Option<wgpu::RenderPass<'a>>,
-    clear_depth_buffer: &'a dyn Fn(),
+    view: &'a TextureView,
+    encoder: Option<CommandEncoder>,
+    render_pass: Option<wgpu::RenderPass<'static>>,
     camera: Option<&'a Camera>,
+    lights: Option<&'a Lights>,
     objects: Vec<ObjectHandle<'a>>,
     global_material: bool,
+    /// Whether the current shader draws objects with `ShaderBinding::Instanced`
+    instanced: bool,
 }
 
 impl<'a> RenderPass<'a> {
-    pub(crate) fn new<'b: 'a>(graphics: &'a Graphics, render_pass: &'a mut Option<wgpu::RenderPass<'a>>, clear_depth_buffer: &'a dyn Fn()) -> Self {
+    /// Begin rendering a frame into the view. The frame is submitted when the render pass is dropped
+    pub(crate) fn new(graphics: &'a Graphics, view: &'a TextureView) -> Self {
+        let mut encoder = graphics.make_encoder();
+        let render_pass = graphics.make_render_pass(view, &mut encoder, true).forget_lifetime();
+        if let Some(instances) = graphics.instances.borrow_mut().as_mut() {
+            instances.used = 0; // The last frame has been submitted, so its instances can be overwritten
+        }
         Self {
             graphics,
-            render_pass,
+            view,
+            encoder: Some(encoder),
+            render_pass: Some(render_pass),
             camera: None,
+            lights: None,
             objects: Vec::new(),
             global_material: false,
-            clear_depth_buffer,
+            instanced: false,
+        }
+    }
+
+    /// End the wgpu render pass and submit its encoder
+    fn submit(&mut self) {
+        std::mem::drop(self.render_pass.take()); // The encoder cannot be finished until the render pass is dropped
+        if let Some(encoder) = self.encoder.take() {
+            self.graphics.queue_encoder(encoder);
         }
     }
 
     fn render_models(&mut self) {
+        if self.instanced {
+            self.render_instanced_models();
+            return;
+        }
         for object in self.objects.drain(0..self.objects.len()) {
             let object = object.as_ref();
             let render_pass = self.render_pass.as_mut().unwrap();
             render_pass.set_bind_group(1, &object.bind_group, &[]);
             let model_data = &object.model.inner();
-            for mesh in &model_data.0 {//TODO rearrange orderm instances
+            for mesh in &model_data.0 {
                 if !self.global_material && model_data.1.len() > mesh.material_index {
                     render_pass.set_bind_group(2, &model_data.1[mesh.material_index].inner(), &[]);
                 }
                 render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
-                render_pass.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+                render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
                 render_pass.draw_indexed(0..mesh.num_indices, 0, 0..1);
             }
         }
     }
 
+    /// Draw each run of objects which share a model with one instanced call. The objects are
+    /// sorted by model, so their uniforms are gathered in order into the instance buffer and each
+    /// run is a contiguous range of instances.
+    fn render_instanced_models(&mut self) {
+        if self.objects.is_empty() {
+            return;
+        }
+        let contents = self.objects.iter()
+            .flat_map(|object| object.as_ref().uniform.borrow().clone())
+            .collect::<Vec<_>>();
+        let render_pass = self.render_pass.as_mut().unwrap();
+        let first_instance = bind_instances(self.graphics, render_pass, 1, &contents, contents.len() / self.objects.len());
+        draw_instanced(render_pass, &self.objects, first_instance, !self.global_material);
+        self.objects.clear();
+    }
+
     pub fn set_camera(&mut self, camera: &'a Camera) {
         camera.update(&self.graphics);
         self.camera = Some(camera);
@@ -50,9 +95,71 @@ impl<'a> RenderPass<'a> {
     pub fn set_shader(&mut self, shader: &'a Shader) {
         self.render_models();
         self.global_material = false;
+        self.instanced = shader.instanced;
         let render_pass = self.render_pass.as_mut().unwrap();
         render_pass.set_pipeline(&shader.render_pipeline);
         render_pass.set_bind_group(0, &self.camera.expect("You must set a camera").bind_group, &[]);
+        if let Some(group) = shader.lights_group {
+            render_pass.set_bind_group(group, &self.lights.expect("You must set the lights").bind_group, &[]);
+        }
+    }
+
+    /// Draw the sky wherever nothing else is drawn this frame. The camera must be set first, and
+    /// the shader must be set again before rendering more objects.
+    pub fn render_skybox(&mut self, skybox: &'a Skybox) {
+        self.render_models();
+        skybox.update(self.graphics, self.camera.expect("You must set a camera before the skybox"));
+        let render_pass = self.render_pass.as_mut().unwrap();
+        render_pass.set_pipeline(&skybox.pipeline);
+        render_pass.set_bind_group(0, &skybox.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    /// Use these lights for shaders with `ShaderBinding::Lights`. The camera must be set first,
+    /// because the shadow cascades are fitted to its view.
+    pub fn set_lights(&mut self, lights: &'a Lights) {
+        lights.update(self.graphics, self.camera.expect("You must set a camera before the lights"));
+        self.lights = Some(lights);
+    }
+
+    /// Draw the objects into the shadow map of the lights, which must be set first. Call this
+    /// before drawing anything which is shadowed. The objects must be made of `TexVertex` meshes,
+    /// and the first matrix of their uniform must be their world matrix, as in `ObjectUniform`.
+    pub fn render_shadows(&mut self, objects: Vec<ObjectHandle<'_>>) {
+        let lights = self.lights.expect("You must set the lights before rendering shadows");
+        let mut objects = objects;
+        objects.sort_by_key(|object| object.as_ref().model.identifier());
+        let world_size = size_of::<[[f32; 4]; 4]>();
+        let worlds = objects.iter()
+            .flat_map(|object| object.as_ref().uniform.borrow()[..world_size].to_vec())
+            .collect::<Vec<_>>();
+
+        // The shadow passes are submitted before this render pass, so the map is ready to be read.
+        // Every cascade is cleared even when there is nothing to draw, so old shadows do not linger.
+        let mut encoder = self.graphics.make_encoder();
+        for cascade in 0..CASCADES {
+            let mut shadow_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Shadow Pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &lights.cascade_views[cascade],
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            if !objects.is_empty() && !lights.directional.is_empty() {
+                shadow_pass.set_pipeline(&lights.shadow_pipeline);
+                shadow_pass.set_bind_group(0, &lights.cascade_bind_groups[cascade], &[]);
+                let first_instance = bind_instances(self.graphics, &mut shadow_pass, 1, &worlds, world_size);
+                draw_instanced(&mut shadow_pass, &objects, first_instance, false);
+            }
+        }
+        self.graphics.queue_encoder(encoder);
     }
     
     pub fn set_global_material(&mut self, material: &Material) {
@@ -73,12 +180,95 @@ impl<'a> RenderPass<'a> {
     
     pub fn clear_depth(&mut self) {
         self.render_models();
-        (self.clear_depth_buffer)()
+        self.submit();
+        let mut encoder = self.graphics.make_encoder();
+        self.render_pass = Some(self.graphics.make_render_pass(self.view, &mut encoder, false).forget_lifetime());
+        self.encoder = Some(encoder);
     }
 }
 
 impl<'a> Drop for RenderPass<'a> {
     fn drop(&mut self) {
         self.render_models();
+        self.submit();
+    }
+}
+
+/// The storage buffer bound by `ShaderBinding::Instanced`, which is kept between frames. Every
+/// instanced draw in a frame writes its uniforms after those of the draws before it, because all
+/// the writes land before the frame is submitted.
+pub(crate) struct InstanceBuffer {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    /// Bytes written since the start of the frame
+    used: u64,
+}
+
+impl InstanceBuffer {
+    fn new(graphics: &Graphics, size: u64) -> Self {
+        let buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = graphics.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &ShaderBinding::Instanced.get_bind_group_layout(graphics),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }
+            ],
+            label: Some("instance_bind_group"),
+        });
+        Self {
+            buffer,
+            bind_group,
+            used: 0,
+        }
     }
-}
\ No newline at end of file
+}
+
+/// Write the uniforms of a list of objects, each `stride` bytes long, into the instance buffer
+/// and bind it to the group. Returns the index of the first object in the buffer. The buffer is
+/// replaced with one twice as large when it is full.
+fn bind_instances(graphics: &Graphics, render_pass: &mut wgpu::RenderPass, group: u32, contents: &[u8], stride: usize) -> u32 {
+    let stride = stride as u64;
+    let mut instances = graphics.instances.borrow_mut();
+    let offset = instances.as_ref().map_or(0, |instances| instances.used.div_ceil(stride) * stride);
+    let end = offset + contents.len() as u64;
+    let instances = match instances.as_mut() {
+        Some(instances) if end <= instances.buffer.size() => instances,
+        // Draws already recorded this frame keep the old buffer alive along with their uniforms
+        _ => {
+            let size = instances.as_ref().map_or(0, |instances| instances.buffer.size() * 2);
+            instances.insert(InstanceBuffer::new(graphics, size.max(contents.len() as u64).next_power_of_two().max(256)))
+        }
+    };
+    let offset = if instances.used == 0 { 0 } else { offset };
+    graphics.queue.write_buffer(&instances.buffer, offset, contents);
+    instances.used = offset + contents.len() as u64;
+    render_pass.set_bind_group(group, &instances.bind_group, &[]);
+    (offset / stride) as u32
+}
+
+/// Draw each run of objects sorted by model with one instanced call, numbering the instances in
+/// order from `first_instance`. The material of each mesh is bound to group 2 if `bind_materials`
+/// is set.
+fn draw_instanced(render_pass: &mut wgpu::RenderPass, objects: &[ObjectHandle], first_instance: u32, bind_materials: bool) {
+    let mut first_instance = first_instance;
+    for run in objects.chunk_by(|a, b| a.as_ref().model.identifier() == b.as_ref().model.identifier()) {
+        let instances = first_instance..first_instance + run.len() as u32;
+        let model_data = run[0].as_ref().model.inner();
+        for mesh in &model_data.0 {
+            if bind_materials && model_data.1.len() > mesh.material_index {
+                render_pass.set_bind_group(2, model_data.1[mesh.material_index].inner(), &[]);
+            }
+            render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            render_pass.set_index_buffer(mesh.index_buffer.slice(..), mesh.index_format);
+            render_pass.draw_indexed(0..mesh.num_indices, 0, instances.clone());
+        }
+        first_instance = instances.end;
+    }
+}
@@ -10,7 +10,6 @@ pub enum ShaderBinding {
     ```
     struct CameraUniform {
         view_proj: mat4x4<f32>,
-        light_pos: vec4<f32>,
     };
     ```
     and should be bound with 
@@ -35,6 +34,24 @@ pub enum ShaderBinding {
     ```
     */
     Object,
+    /** # Instanced shader binding
+    This binding replaces the object binding at group 1 for shaders which draw every object sharing
+    a model in one call. The uniforms of all the objects are gathered into a storage buffer, which
+    should be bound with
+    ```wgsl
+    @group(1) @binding(0)
+    var<storage, read> objects: array<ObjectUniform>;
+    ```
+    and indexed with the instance index, which is read in the vertex shader with
+    ```wgsl
+    @vertex
+    fn vs_main(in: VertexInput, @builtin(instance_index) instance: u32) -> VertexOutput {
+        let model = objects[instance];
+    }
+    ```
+    The size of the uniform must be a multiple of 16 bytes, so that it matches the array stride.
+    */
+    Instanced,
     /** # Texture shader binding
     This binding loads the texture and its sampler for the fragment shader. Include them in the vertex shader using
     ```
@@ -76,6 +93,107 @@ pub enum ShaderBinding {
     ```
     */
     NoisyTexture,
+    /** # PBR material shader binding
+    This binding loads a metallic-roughness material made with `Material::new_pbr`, with its base
+    color, normal, metallic-roughness and occlusion textures. Bind them like this:
+    ```wgsl
+    @group(2) @binding(0)
+    var t_albedo: texture_2d<f32>;
+    @group(2) @binding(1)
+    var s_albedo: sampler;
+    @group(2) @binding(2)
+    var t_normal: texture_2d<f32>;
+    @group(2) @binding(3)
+    var s_normal: sampler;
+    @group(2) @binding(4)
+    var t_metallic_roughness: texture_2d<f32>;
+    @group(2) @binding(5)
+    var s_metallic_roughness: sampler;
+    @group(2) @binding(6)
+    var t_occlusion: texture_2d<f32>;
+    @group(2) @binding(7)
+    var s_occlusion: sampler;
+    @group(2) @binding(8)
+    var<uniform> material: MaterialUniform;
+    ```
+    where `MaterialUniform` stores the factors of the material
+    ```wgsl
+    struct MaterialUniform {
+        light_info: vec4<f32>,
+        base_color: vec4<f32>,
+        pbr_info: vec4<f32>,
+    };
+    ```
+    The base color multiplies the albedo texture. `pbr_info` holds the metallic and roughness,
+    which multiply the blue and green channels of the metallic-roughness texture, and the strength
+    of the occlusion, which is read from the red channel of the occlusion texture. Normal maps may
+    be compressed to two channels, so the Z of a normal should be found from its X and Y.
+    */
+    Pbr,
+    /** # Lights shader binding
+    This binding loads the lights set with `RenderPass::set_lights`, the matrices of the cascades
+    of the shadow map of the first directional light, and the shadow map itself. Positions and
+    matrices are relative to the camera, like the world matrices of objects. Bind them with
+    ```wgsl
+    struct DirectionalLight {
+        direction: vec4<f32>,
+        color: vec4<f32>,
+    };
+    struct PointLight {
+        position: vec4<f32>,
+        color: vec4<f32>,
+    };
+    struct LightUniform {
+        cascades: array<mat4x4<f32>, 4>,
+        directional: array<DirectionalLight, 4>,
+        point: array<PointLight, 16>,
+        counts: vec4<u32>,
+        irradiance: array<vec4<f32>, 9>,
+    };
+    @group(3) @binding(0)
+    var<uniform> lights: LightUniform;
+    @group(3) @binding(1)
+    var shadow_map: texture_depth_2d_array;
+    @group(3) @binding(2)
+    var shadow_sampler: sampler_comparison;
+    @group(3) @binding(3)
+    var environment_map: texture_cube<f32>;
+    @group(3) @binding(4)
+    var environment_sampler: sampler;
+    ```
+    where the group is the position of this binding in the shader's list of bindings. Each light's
+    `direction` points towards the light, and its `color` is multiplied by its intensity. The
+    `position` of a point light holds its range in W. `counts` holds the number of directional
+    lights, point lights and cascades, which is zero when there is no directional light to cast
+    shadows. The first cascade which contains a point covers it with the most detail:
+    ```wgsl
+    fn shadow(position: vec3<f32>) -> f32 {
+        for (var i = 0u; i < lights.counts.z; i++) {
+            let clip = lights.cascades[i] * vec4(position, 1.);
+            let uv = clip.xy * vec2(0.5, -0.5) + 0.5;
+            if all(uv >= vec2(0.)) && all(uv <= vec2(1.)) && clip.z <= 1. {
+                return textureSampleCompareLevel(shadow_map, shadow_sampler, uv, i, clip.z);
+            }
+        }
+        return 1.;
+    }
+    ```
+    The environment set with `Lights::set_environment` lights surfaces from every direction. Its
+    map holds the light reflected by surfaces whose roughness rises evenly from 0 at the first mip
+    level to 1 at the last, and `irradiance` holds the spherical harmonics of the diffuse light,
+    already divided by pi, from which the light reaching a surface with normal `n` is
+    ```wgsl
+    fn irradiance(n: vec3<f32>) -> vec3<f32> {
+        let c = lights.irradiance;
+        return (c[0] * 0.282095
+            + (c[1] * n.y + c[2] * n.z + c[3] * n.x) * 0.488603
+            + (c[4] * n.x * n.y + c[5] * n.y * n.z + c[7] * n.x * n.z) * 1.092548
+            + c[6] * 0.315392 * (3. * n.z * n.z - 1.)
+            + c[8] * 0.546274 * (n.x * n.x - n.y * n.y)).rgb;
+    }
+    ```
+    */
+    Lights,
 }
 
 impl ShaderBinding {
@@ -105,6 +223,18 @@ impl ShaderBinding {
                     count: None,
                 }
             ],
+            ShaderBinding::Instanced => vec![
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }
+            ],
             ShaderBinding::Texture => vec![
                 wgpu::BindGroupLayoutEntry {
                     binding: 0,
@@ -177,6 +307,81 @@ impl ShaderBinding {
                     count: None,
                 }
             ],
+            ShaderBinding::Pbr => {
+                let mut entries = (0..4).flat_map(|i| [
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2 * i,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2 * i + 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ]).collect::<Vec<_>>();
+                entries.push(wgpu::BindGroupLayoutEntry {
+                    binding: 8,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                });
+                entries
+            },
+            ShaderBinding::Lights => vec![
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2Array,
+                        sample_type: wgpu::TextureSampleType::Depth,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Comparison),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
         };
 
         graphics.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -188,6 +393,10 @@ impl ShaderBinding {
 
 pub struct Shader {
     pub(crate) render_pipeline: wgpu::RenderPipeline,
+    /// Whether objects are drawn with `ShaderBinding::Instanced`
+    pub(crate) instanced: bool,
+    /// Group at which the lights are bound, if the shader uses them
+    pub(crate) lights_group: Option<u32>,
 }
 
 /**
@@ -301,6 +510,8 @@ impl<'a, V: Vertex> ShaderBuilder<'a, V> {
 
         Shader {
             render_pipeline,
+            instanced: self.bindings.iter().any(|binding| matches!(binding, ShaderBinding::Instanced)),
+            lights_group: self.bindings.iter().position(|binding| matches!(binding, ShaderBinding::Lights)).map(|group| group as u32),
         }
     }
 }
\ No newline at end of file
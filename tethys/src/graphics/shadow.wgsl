@@ -0,0 +1,21 @@
+// Depth-only pass which draws objects into one cascade of the shadow map
+struct CascadeUniform {
+    view_proj: mat4x4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> cascade: CascadeUniform;
+@group(1) @binding(0)
+var<storage, read> worlds: array<mat4x4<f32>>;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+}
+
+@vertex
+fn vs_main(
+    in: VertexInput,
+    @builtin(instance_index) instance: u32,
+) -> @builtin(position) vec4<f32> {
+    return cascade.view_proj * worlds[instance] * vec4<f32>(in.position, 1.0);
+}
@@ -0,0 +1,166 @@
+use wgpu::util::DeviceExt;
+
+use super::{camera::Camera, cube_map::{make_cube_texture, CubeMap}, Graphics};
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SkyUniform {
+    forward: [f32; 4],
+    right: [f32; 4],
+    up: [f32; 4],
+}
+
+/// A cube map drawn behind everything else with `RenderPass::render_skybox`. Only the rotation of
+/// the camera is used, so the sky is infinitely far away and does not move as the camera does.
+pub struct Skybox {
+    sky_buffer: wgpu::Buffer,
+    pub(crate) bind_group: wgpu::BindGroup,
+    pub(crate) pipeline: wgpu::RenderPipeline,
+}
+
+impl Skybox {
+    pub fn new(graphics: &Graphics, cube_map: &CubeMap) -> Self {
+        let sky_buffer = graphics.device.create_buffer_init(
+            &wgpu::util::BufferInitDescriptor {
+                label: Some("Sky Buffer"),
+                contents: bytemuck::bytes_of(&SkyUniform { forward: [0.; 4], right: [0.; 4], up: [0.; 4] }),
+                usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            }
+        );
+        // Stars smaller than a pixel are read from the averaged levels, so they do not flicker
+        let view = make_cube_texture(graphics, "Sky map", &cube_map.mip_levels());
+        let sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let layout = graphics.device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::Cube,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("sky_bind_group_layout"),
+        });
+        let bind_group = graphics.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: sky_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("sky_bind_group"),
+        });
+
+        Self {
+            sky_buffer,
+            bind_group,
+            pipeline: make_sky_pipeline(graphics, &layout),
+        }
+    }
+
+    /// Point the sky along the view of the camera
+    pub(crate) fn update(&self, graphics: &Graphics, camera: &Camera) {
+        // Corners of the screen one unit in front of the camera
+        let corners = camera.frustum_corners(1., 1.);
+        let forward = (corners[0] + corners[2]) / 2.;
+        let right = (corners[1] - corners[0]) / 2.;
+        let up = (corners[3] - corners[0]) / 2.;
+        let uniform = SkyUniform {
+            forward: forward.extend(0.).into(),
+            right: right.extend(0.).into(),
+            up: up.extend(0.).into(),
+        };
+        graphics.queue.write_buffer(&self.sky_buffer, 0, bytemuck::bytes_of(&uniform));
+    }
+}
+
+fn make_sky_pipeline(graphics: &Graphics, layout: &wgpu::BindGroupLayout) -> wgpu::RenderPipeline {
+    let shader = graphics.device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Sky Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("skybox.wgsl").into()),
+    });
+    let pipeline_layout = graphics.device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Sky Pipeline Layout"),
+        bind_group_layouts: &[layout],
+        push_constant_ranges: &[],
+    });
+    graphics.device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Sky Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: graphics.config.format,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            strip_index_format: None,
+            front_face: wgpu::FrontFace::Ccw,
+            cull_mode: None,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            unclipped_depth: false,
+            conservative: false,
+        },
+        // The sky is only drawn where the depth is still clear, so it does not cover objects which
+        // were drawn before it
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: wgpu::TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
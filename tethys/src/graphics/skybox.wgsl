@@ -0,0 +1,36 @@
+// Draws a cube map behind everything else with one triangle which covers the screen
+struct SkyUniform {
+    // Direction through the center of the screen, and the offsets to its right and top edges
+    forward: vec4<f32>,
+    right: vec4<f32>,
+    up: vec4<f32>,
+};
+
+@group(0) @binding(0)
+var<uniform> sky: SkyUniform;
+@group(0) @binding(1)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(2)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) screen: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let screen = vec2(f32(index / 2u) * 4. - 1., f32(index % 2u) * 4. - 1.);
+    var out: VertexOutput;
+    // On the far plane, so anything drawn in front hides the sky
+    out.clip_position = vec4(screen, 1., 1.);
+    out.screen = screen;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let direction = sky.forward.xyz + sky.right.xyz * in.screen.x + sky.up.xyz * in.screen.y;
+    let color = textureSample(t_sky, s_sky, direction).rgb;
+    return vec4(min(color, vec3(1.)), 1.);
+}
@@ -0,0 +1,80 @@
+use std::f32::consts::PI;
+
+use cgmath::Vector3;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use super::cube_map::CubeMap;
+
+/// Irradiance of a star of magnitude zero, chosen so that the brightest stars are white and the
+/// faintest are just visible
+const ZERO_MAGNITUDE_IRRADIANCE: f32 = 4e-5;
+/// Magnitude of the brightest star which is generated
+const BRIGHTEST_MAGNITUDE: f32 = -1.5;
+/// Magnitude of the faintest star which is generated, about the limit of the naked eye
+const FAINTEST_MAGNITUDE: f32 = 6.5;
+/// Surface temperatures in kelvin of the coolest and hottest stars which are generated
+const TEMPERATURE_RANGE: (f32, f32) = (3000., 30000.);
+
+/// A star at an infinite distance
+#[derive(Debug, Clone, Copy)]
+pub struct Star {
+    /// Unit vector towards the star
+    pub direction: Vector3<f32>,
+    /// Apparent magnitude, which is smaller for brighter stars
+    pub magnitude: f32,
+    /// Linear color with a luminance of one
+    pub color: Vector3<f32>,
+}
+
+impl Star {
+    /// Linear irradiance of the light from the star
+    pub fn irradiance(&self) -> Vector3<f32> {
+        self.color * (ZERO_MAGNITUDE_IRRADIANCE * 10f32.powf(-0.4 * self.magnitude))
+    }
+}
+
+/// A catalogue of stars scattered evenly over the sky. The same seed always gives the same stars.
+pub struct Starfield {
+    pub stars: Vec<Star>,
+}
+
+impl Starfield {
+    /// Generate `count` stars. Fainter stars are more common, with the number of stars brighter
+    /// than a magnitude growing by a factor of four for each magnitude, as for stars spread evenly
+    /// through space. Cool red stars are more common than hot blue ones.
+    pub fn new(seed: u64, count: usize) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let stars = (0..count).map(|_| {
+            let z = rng.gen_range(-1f32..1.);
+            let angle = rng.gen_range(0f32..2. * PI);
+            let radius = (1. - z * z).sqrt();
+            let magnitude = (FAINTEST_MAGNITUDE + rng.gen::<f32>().log10() / 0.6).max(BRIGHTEST_MAGNITUDE);
+            let temperature = TEMPERATURE_RANGE.0 * (TEMPERATURE_RANGE.1 / TEMPERATURE_RANGE.0).powf(rng.gen::<f32>().powi(2));
+            Star {
+                direction: Vector3::new(radius * angle.cos(), radius * angle.sin(), z),
+                magnitude,
+                color: blackbody_color(temperature),
+            }
+        }).collect();
+        Self { stars }
+    }
+
+    /// Add the light of every star to a cube map, which can be drawn by a `Skybox` and shrunk into
+    /// an `Environment`
+    pub fn draw(&self, cube_map: &mut CubeMap) {
+        for star in &self.stars {
+            cube_map.add_point(star.direction, star.irradiance());
+        }
+    }
+}
+
+/// Linear color of a black body at a temperature in kelvin, from Planck's law at the wavelengths of
+/// red, green and blue light, scaled to a luminance of one
+fn blackbody_color(temperature: f32) -> Vector3<f32> {
+    // Planck's constant times the speed of light over Boltzmann's constant, in meter kelvins
+    const SECOND_RADIATION_CONSTANT: f32 = 1.4388e-2;
+    let radiance = |wavelength: f32| 1. / (wavelength.powi(5) * ((SECOND_RADIATION_CONSTANT / (wavelength * temperature)).exp() - 1.));
+    let color = Vector3::new(radiance(610e-9), radiance(550e-9), radiance(465e-9));
+    color / (0.2126 * color.x + 0.7152 * color.y + 0.0722 * color.z)
+}
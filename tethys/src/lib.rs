@@ -9,36 +9,47 @@ pub mod prelude {
     pub use crate::io::mouse::Mouse;
     pub use crate::graphics::Graphics;
     pub use crate::graphics::render_pass::RenderPass;
-    pub use crate::graphics::model::{Model, ModelContainer, ModelLoader, Material, MaterialContainer, MaterialLoader, LoadModel, LoadMaterial};
+    pub use crate::graphics::model::{AssetManager, Model, ModelContainer, ModelLoader, Material, MaterialContainer, MaterialLoader, LoadModel, LoadMaterial};
     pub use crate::graphics::shader::{Shader, ShaderBuilder, ShaderBinding};
     pub use crate::graphics::camera::Camera;
+    pub use crate::graphics::cube_map::CubeMap;
+    pub use crate::graphics::environment::Environment;
+    pub use crate::graphics::light::{DirectionalLight, Lights, PointLight};
     pub use crate::graphics::object::{Object, ObjectHandle};
+    pub use crate::graphics::skybox::Skybox;
+    pub use crate::graphics::starfield::{Star, Starfield};
     pub use crate::graphics::primitives::*;
-    pub use crate::physics::RigidBody;
-    pub use crate::physics::collisions::{Collider, BoxCollider, LineCollider, GridCollider};
-    pub use crate::include_model;
-    pub use crate::include_material;
+    pub use crate::physics::{RigidBody, World};
+    pub use crate::physics::collisions::{Collider, BoxCollider, LineCollider, GridCollider, TerrainCollider};
 }
 
 use std::time::Instant;
 use graphics::Graphics;
 use graphics::render_pass::RenderPass;
 use io::{key::KeyState, mouse::Mouse};
-use wgpu::CommandEncoder;
 use winit::{dpi::LogicalSize, event::{ElementState, Event, KeyEvent, WindowEvent}, event_loop::EventLoop, window::WindowBuilder};
 
 
 pub use io::key::Key;
 
+/// Default length of a simulation tick in seconds
+pub const DEFAULT_TIMESTEP: f64 = 1. / 60.;
+/// Longest frame time that will be simulated. Slower frames are clamped so that the simulation does not fall further and further behind
+const MAX_FRAME_TIME: f64 = 0.25;
+
 pub trait App {
     fn new<'a>(graphics: Graphics<'a>) -> impl App;
+    /// Advance the simulation. This is called with a fixed `delta_t` equal to `App::timestep`, zero or more times per frame
     fn tick(&mut self, key_state: &KeyState, delta_t: f64);
-    fn render<'c, 'b: 'c>(&'b self, render_pass: RenderPass<'c>);
+    /// Draw a frame. `alpha` is the fraction of a timestep which has elapsed since the last tick, for interpolating between simulation states
+    fn render<'c, 'b: 'c>(&'b self, render_pass: RenderPass<'c>, alpha: f64);
     fn exit_check(&self) -> bool;
     fn get_graphics(&self) -> &Graphics;
     fn resize(&mut self, new_size: (u32, u32));
     
     fn initialize(&mut self) {}
+    /// Length of a simulation tick in seconds
+    fn timestep(&self) -> f64 { DEFAULT_TIMESTEP }
     fn key_up(&mut self, _key: Key) {}
     fn key_down(&mut self, _key: Key) {}
     fn mouse_down(&mut self, _mouse: &Mouse) {}
@@ -57,13 +68,11 @@ async fn main_internal<T: App>() {
     let mut key_state = KeyState::new();
     let mut mouse = Mouse::new();
     let mut time = Instant::now();
+    let mut accumulator = 0.;
     app.initialize();
 
     event_loop.run(move |event, control_flow| {
-        let delta_t = time.elapsed().as_micros() as f64/ 1e6;
-        time = Instant::now();
-        app.tick(&key_state, delta_t);
-        let window = app.get_graphics().window();
+        let window = app.get_graphics().window().expect("The main loop requires windowed graphics");
         
         match event {
             Event::WindowEvent { ref event, window_id, } if window_id == window.id() => match event {
@@ -113,29 +122,22 @@ async fn main_internal<T: App>() {
                 },
                 WindowEvent::RedrawRequested => {
                     window.request_redraw();
+                    let frame_time = time.elapsed().as_secs_f64().min(MAX_FRAME_TIME);
+                    time = Instant::now();
                     if !surface_configured { return; }
 
+                    // Run as many fixed ticks as fit in the elapsed time and carry the remainder to the next frame
+                    let timestep = app.timestep();
+                    accumulator += frame_time;
+                    while accumulator >= timestep {
+                        app.tick(&key_state, timestep);
+                        accumulator -= timestep;
+                    }
+                    let alpha = accumulator / timestep;
+
                     let graphics = app.get_graphics();
                     let (surface_texture, view) = graphics.start_render().unwrap();
-
-                    let mut encoder = Some(graphics.make_encoder());
-                    let encoder_ptr: *mut Option<CommandEncoder> = &mut encoder;
-                    {
-                        let mut render_pass = Some(graphics.make_render_pass(&view, encoder.as_mut().unwrap(), true));
-                        let render_pass_ptr: *mut Option<wgpu::RenderPass> = &mut render_pass;
-
-                        let clear_depth_buffer = || {
-                            let (encoder, render_pass) = unsafe { (&mut *encoder_ptr, &mut *render_pass_ptr) };
-                            std::mem::drop(render_pass.take().unwrap()); // Drop the render pass
-                            graphics.queue_encoder(encoder.take().unwrap()); // Submit the render pass
-                            // Render pass is now ended
-                            encoder.replace(graphics.make_encoder()); // Make a new encoder
-                            render_pass.replace(graphics.make_render_pass(&view, encoder.as_mut().unwrap(), false)); // Make a new render pass
-                        };
-
-                        app.render(RenderPass::new(graphics, &mut render_pass, &clear_depth_buffer));
-                    } // Render pass goes out of scope
-                    graphics.queue_encoder(encoder.take().unwrap()); // Submit the last render pass
+                    app.render(RenderPass::new(graphics, &view), alpha); // The frame is submitted when the render pass is dropped
                     surface_texture.present(); // Finish the render 
                 }
                 _ => {}
@@ -0,0 +1,123 @@
+use cgmath::Vector3;
+
+use super::collisions::{reorient, Collider};
+use super::RigidBody;
+
+/// Axis-aligned bounding box in the inertial frame
+#[derive(Clone, Copy, Debug)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+impl Aabb {
+    /// Bounding box of a collider placed by a rigid body. Returns None for unbounded colliders.
+    pub fn new(collider: &Collider, rigid_body: Option<&RigidBody>) -> Option<Self> {
+        let bounds = collider.bounds()?;
+        let mut min = Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for point in bounds.get_points() {
+            let p = reorient(point, rigid_body, None);
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        Some(Self { min, max })
+    }
+
+    pub fn overlaps(&self, o: &Aabb) -> bool {
+        self.min.x <= o.max.x && o.min.x <= self.max.x
+            && self.min.y <= o.max.y && o.min.y <= self.max.y
+            && self.min.z <= o.max.z && o.min.z <= self.max.z
+    }
+}
+
+/// Sweep and prune along the x axis. The sort order is kept between steps, so when bodies move a
+/// little each step the insertion sort is close to linear.
+pub(crate) struct SweepAndPrune {
+    order: Vec<usize>,
+}
+
+impl SweepAndPrune {
+    pub fn new() -> Self {
+        Self { order: Vec::new() }
+    }
+
+    /// Find all pairs of indices `(i, j)` with `i < j` whose boxes overlap. Entries which are None
+    /// are skipped.
+    pub fn candidate_pairs(&mut self, aabbs: &[Option<Aabb>]) -> Vec<(usize, usize)> {
+        // Keep the order in step with the boxes that exist
+        self.order.retain(|i| aabbs.get(*i).is_some_and(|a| a.is_some()));
+        let mut ordered = vec![false; aabbs.len()];
+        for &i in &self.order {
+            ordered[i] = true;
+        }
+        for (i, aabb) in aabbs.iter().enumerate() {
+            if aabb.is_some() && !ordered[i] {
+                self.order.push(i);
+            }
+        }
+
+        let min_x = |i: usize| aabbs[i].unwrap().min.x;
+        for i in 1..self.order.len() {
+            let mut j = i;
+            while j > 0 && min_x(self.order[j - 1]) > min_x(self.order[j]) {
+                self.order.swap(j - 1, j);
+                j -= 1;
+            }
+        }
+
+        let mut pairs = Vec::new();
+        let mut active: Vec<usize> = Vec::new();
+        for &i in &self.order {
+            let aabb = aabbs[i].unwrap();
+            active.retain(|j| aabbs[*j].unwrap().max.x >= aabb.min.x);
+            for &j in &active {
+                if aabb.overlaps(&aabbs[j].unwrap()) {
+                    pairs.push((i.min(j), i.max(j)));
+                }
+            }
+            active.push(i);
+        }
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn aabb(min: (f64, f64, f64), max: (f64, f64, f64)) -> Option<Aabb> {
+        Some(Aabb { min: Vector3::new(min.0, min.1, min.2), max: Vector3::new(max.0, max.1, max.2) })
+    }
+
+    #[test]
+    fn overlapping_boxes_are_paired() {
+        let mut sweep = SweepAndPrune::new();
+        let aabbs = [
+            aabb((2., 0., 0.), (3., 1., 1.)),
+            None,
+            aabb((0., 0., 0.), (2.5, 1., 1.)),
+            aabb((2.9, 0.5, 0.5), (4., 2., 2.)),
+        ];
+        let mut pairs = sweep.candidate_pairs(&aabbs);
+        pairs.sort();
+        assert_eq!(pairs, vec![(0, 2), (0, 3)]);
+    }
+
+    #[test]
+    fn separated_boxes_are_not_paired() {
+        let mut sweep = SweepAndPrune::new();
+        // Overlapping along x, which is swept, but apart along y and z
+        let aabbs = [
+            aabb((0., 0., 0.), (1., 1., 1.)),
+            aabb((0.5, 2., 0.), (1.5, 3., 1.)),
+            aabb((0.5, 0., 2.), (1.5, 1., 3.)),
+            aabb((5., 0., 0.), (6., 1., 1.)),
+        ];
+        assert!(sweep.candidate_pairs(&aabbs).is_empty());
+
+        // The order kept from the last sweep still finds boxes which have moved together
+        let moved = [aabbs[0], aabbs[1], aabbs[2], aabb((0.9, 0.9, 0.9), (1.2, 1.2, 1.2))];
+        assert_eq!(sweep.candidate_pairs(&moved), vec![(0, 3)]);
+    }
+}
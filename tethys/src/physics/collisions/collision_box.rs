@@ -1,10 +1,10 @@
 use std::vec::IntoIter;
 
-use cgmath::Vector3;
+use cgmath::{InnerSpace, Vector3, Zero};
 
 use crate::physics::RigidBody;
 
-use super::{reorient, LineCollider, CollisionReport};
+use super::{reorient, reorient_rot, LineCollider, CollisionReport};
 
 
 #[derive(Clone)]
@@ -24,23 +24,23 @@ impl BoxCollider {
 
     /// Check for collisions between a box and a point. The point is in the box frame
     pub(crate) fn check_point(&self, p: Vector3<f64>) -> CollisionReport {
-        if 0. < p.x && p.x < self.dimensions.x && 0. < p.y && p.y < self.dimensions.y && 0. < p.z && p.z < self.dimensions.z {
-            let min_dist = p.x.max(p.y.max(p.z.max((self.dimensions.x - p.x).max((self.dimensions.y - p.y).max(self.dimensions.z - p.z)))));
-            let depth = if min_dist == p.x {
+        let q = p - self.corner;
+        if 0. < q.x && q.x < self.dimensions.x && 0. < q.y && q.y < self.dimensions.y && 0. < q.z && q.z < self.dimensions.z {
+            // Push the point out through the closest face
+            let min_dist = q.x.min(q.y.min(q.z.min((self.dimensions.x - q.x).min((self.dimensions.y - q.y).min(self.dimensions.z - q.z)))));
+            let depth = if min_dist == q.x {
                 Vector3::new(-min_dist, 0., 0.)
-            } else if min_dist == p.y {
+            } else if min_dist == q.y {
                 Vector3::new(0., -min_dist, 0.)
-            } else if min_dist == p.z {
+            } else if min_dist == q.z {
                 Vector3::new(0., 0., -min_dist)
-            } else if min_dist == self.dimensions.x - p.x {
+            } else if min_dist == self.dimensions.x - q.x {
                 Vector3::new(min_dist, 0., 0.)
-            } else if min_dist == self.dimensions.y - p.y {
+            } else if min_dist == self.dimensions.y - q.y {
                 Vector3::new(0., min_dist, 0.)
-            } else if min_dist == self.dimensions.z - p.z {
-                Vector3::new(0., 0., min_dist)
             } else {
-                dbg!(min_dist, p.x, p.y, p.z, self.dimensions.x-p.x, self.dimensions.y-p.y, self.dimensions.z-p.z);
-                unreachable!()
+                // Only the +z face is left, and rounding or NaN falls through to it rather than panicking
+                Vector3::new(0., 0., self.dimensions.z - q.z)
             };
             CollisionReport::new(depth, p + depth/2.)
         } else {
@@ -56,12 +56,11 @@ impl BoxCollider {
         let mut min_alpha = f64::INFINITY;
 
         // x
-        for mut alpha in [
+        for alpha in [
             (self.corner.x - p.x) / v.x, // Close
             (self.corner.x + self.dimensions.x - p.x) / v.x// Far
         ] {
-            if let Some(start) = line.start { alpha = alpha.max(start); } 
-            if let Some(stop) = line.stop { alpha = alpha.min(stop); } 
+            if line.start.is_some_and(|start| alpha < start) || line.stop.is_some_and(|stop| alpha > stop) { continue; }
             let x = p + v * alpha;
             if self.corner.y < x.y && x.y < self.corner.y + self.dimensions.y {
                 if self.corner.z < x.z && x.z < self.corner.z + self.dimensions.z {
@@ -74,12 +73,11 @@ impl BoxCollider {
             }
         }
         // y
-        for mut alpha in [
+        for alpha in [
             (self.corner.y - p.y) / v.y, // Close
             (self.corner.y + self.dimensions.y - p.y) / v.y// Far
         ] {
-            if let Some(start) = line.start { alpha = alpha.max(start); } 
-            if let Some(stop) = line.stop { alpha = alpha.min(stop); } 
+            if line.start.is_some_and(|start| alpha < start) || line.stop.is_some_and(|stop| alpha > stop) { continue; }
             let x = p + v * alpha;
             if self.corner.x < x.x && x.x < self.corner.x + self.dimensions.x {
                 if self.corner.z < x.z && x.z < self.corner.z + self.dimensions.z {
@@ -92,12 +90,11 @@ impl BoxCollider {
             }
         }
         // z
-        for mut alpha in [
+        for alpha in [
             (self.corner.z - p.z) / v.z, // Close
             (self.corner.z + self.dimensions.z - p.z) / v.z// Far
         ] {
-            if let Some(start) = line.start { alpha = alpha.max(start); } 
-            if let Some(stop) = line.stop { alpha = alpha.min(stop); } 
+            if line.start.is_some_and(|start| alpha < start) || line.stop.is_some_and(|stop| alpha > stop) { continue; }
             let x = p + v * alpha;
             if self.corner.y < x.y && x.y < self.corner.y + self.dimensions.y {
                 if self.corner.x < x.x && x.x < self.corner.x + self.dimensions.x {
@@ -112,42 +109,73 @@ impl BoxCollider {
         closest_report
     }
     
-    /// Check for collisions between two boxes
+    /// Check for collisions between two boxes using the separating axis theorem. The report is in
+    /// the inertial frame, with the depth being the smallest translation of this box which would
+    /// separate the boxes.
     pub(crate) fn check_box(&self, rigid_body: Option<&RigidBody>, o: &BoxCollider, o_rigid_body: Option<&RigidBody>) -> CollisionReport {
-        let mut deepest_report = CollisionReport::none();
+        // Work in the frame of this box
+        let center = self.corner + self.dimensions / 2.;
+        let half = self.dimensions / 2.;
+        let o_center = reorient(o.corner + o.dimensions / 2., o_rigid_body, rigid_body);
+        let o_half = o.dimensions / 2.;
+        let axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+        let o_axes = axes.map(|axis| reorient_rot(axis, o_rigid_body, rigid_body));
+        let offset = o_center - center;
 
-        // Check points
-        for point in self.get_points() {
-            let report = o.check_point(reorient(point, rigid_body, o_rigid_body));
-            if report > deepest_report {
-                deepest_report = report.reorient(o_rigid_body);
-            }
-        }
-        for point in o.get_points() {
-            let report = self.check_point(reorient(point, o_rigid_body, rigid_body));
-            if report > deepest_report {
-                deepest_report = report.reorient(rigid_body);
+        let mut candidates = axes.to_vec();
+        candidates.extend(o_axes);
+        for axis in axes {
+            for o_axis in o_axes {
+                candidates.push(axis.cross(o_axis));
             }
         }
 
-        // Check lines
-        for line in self.get_lines() {
-            let report = o.check_line(line.reorient(rigid_body, o_rigid_body));
-            if report > deepest_report {
-                deepest_report = report.reorient(o_rigid_body);
+        let mut min_overlap = f64::INFINITY;
+        let mut normal = Vector3::zero();
+        for axis in candidates {
+            // Parallel edges give no new axis
+            if axis.magnitude2() < 1e-12 { continue; }
+            let axis = axis.normalize();
+            let radius = (0..3).map(|i| axis.dot(axes[i]).abs() * half[i]).sum::<f64>();
+            let o_radius = (0..3).map(|i| axis.dot(o_axes[i]).abs() * o_half[i]).sum::<f64>();
+            let distance = axis.dot(offset);
+            let overlap = radius + o_radius - distance.abs();
+            if overlap <= 0. {
+                return CollisionReport::none();
             }
-        }
-        for line in o.get_lines() {
-            let report = self.check_line(line.reorient(o_rigid_body, rigid_body));
-            if report > deepest_report {
-                deepest_report = report.reorient(rigid_body);
+            if overlap < min_overlap {
+                min_overlap = overlap;
+                // Point away from the other box
+                normal = if distance > 0. { -axis } else { axis };
             }
         }
 
-        deepest_report
+        // The contact is the average of the corners inside the other box. When only edges cross, use
+        // the midpoint of the deepest corners of each box instead.
+        let o_points = o.get_points().map(|p| reorient(p, o_rigid_body, rigid_body)).collect::<Vec<_>>();
+        let mut inside = self.get_points()
+            .filter(|p| o.contains(reorient(*p, rigid_body, o_rigid_body)))
+            .collect::<Vec<_>>();
+        inside.extend(o_points.iter().filter(|p| self.contains(**p)));
+        let position = if inside.is_empty() {
+            let deepest = self.get_points().fold(center, |best, p| if p.dot(normal) < best.dot(normal) { p } else { best });
+            let o_deepest = o_points.iter().fold(o_center, |best, p| if p.dot(normal) > best.dot(normal) { *p } else { best });
+            (deepest + o_deepest) / 2.
+        } else {
+            inside.iter().fold(Vector3::zero(), |sum, p| sum + p) / inside.len() as f64
+        };
+
+        CollisionReport::new(normal * min_overlap, position).reorient(rigid_body)
     }
 
-    fn get_points(&self) -> IntoIter<Vector3<f64>> {
+    /// Whether a point in the box frame lies inside or on the surface of the box
+    fn contains(&self, p: Vector3<f64>) -> bool {
+        const TOLERANCE: f64 = 1e-9;
+        let q = p - self.corner;
+        (0..3).all(|i| -TOLERANCE <= q[i] && q[i] <= self.dimensions[i] + TOLERANCE)
+    }
+
+    pub(crate) fn get_points(&self) -> IntoIter<Vector3<f64>> {
         vec![
             self.corner,
             self.corner + Vector3::new(self.dimensions.x, 0., 0.),
@@ -160,60 +188,6 @@ impl BoxCollider {
         ].into_iter()
     }
 
-    fn get_lines(&self) -> IntoIter<LineCollider> {
-        let opposite_corner = self.corner + Vector3::new(self.dimensions.x, self.dimensions.y, self.dimensions.z);
-        vec![
-            LineCollider::segment(
-                self.corner,
-                Vector3::new(self.dimensions.x, 0., 0.)
-            ),
-            LineCollider::segment(
-                self.corner,
-                Vector3::new(0., self.dimensions.y, 0.)
-            ),
-            LineCollider::segment(
-                self.corner,
-                Vector3::new(0., 0., self.dimensions.z)
-            ),
-            LineCollider::segment(
-                self.corner + Vector3::new(self.dimensions.x, 0., 0.),
-                self.corner + Vector3::new(0., self.dimensions.y, 0.),
-            ),
-            LineCollider::segment(
-                self.corner + Vector3::new(self.dimensions.x, 0., 0.),
-                self.corner + Vector3::new(0., 0., self.dimensions.z),
-            ),
-            LineCollider::segment(
-                self.corner + Vector3::new(0., self.dimensions.y, 0.),
-                self.corner + Vector3::new(self.dimensions.x, 0., 0.),
-            ),
-            LineCollider::segment(
-                self.corner + Vector3::new(0., self.dimensions.y, 0.),
-                self.corner + Vector3::new(0., 0., self.dimensions.z),
-            ),
-            LineCollider::segment(
-                self.corner + Vector3::new(0., 0., self.dimensions.z),
-                self.corner + Vector3::new(0., self.dimensions.y, 0.),
-            ),
-            LineCollider::segment(
-                self.corner + Vector3::new(0., 0., self.dimensions.z),
-                self.corner + Vector3::new(self.dimensions.x, 0., 0.),
-            ),
-            LineCollider::segment(
-                opposite_corner,
-                Vector3::new(-self.dimensions.x, 0., 0.)
-            ),
-            LineCollider::segment(
-                opposite_corner,
-                Vector3::new(0., -self.dimensions.y, 0.)
-            ),
-            LineCollider::segment(
-                opposite_corner,
-                Vector3::new(0., 0., -self.dimensions.z)
-            ),
-        ].into_iter()
-    }
-    
     pub fn volume(&self) -> f64 {
         self.dimensions.x * self.dimensions.y * self.dimensions.z
     }
@@ -2,7 +2,9 @@ use core::f64;
 
 use cgmath::{InnerSpace, Vector3};
 
-use super::{CollisionReport, LineCollider};
+use crate::physics::RigidBody;
+
+use super::{reorient, BoxCollider, CollisionReport, LineCollider};
 
 pub struct GridCollider {
     data: Vec<isize>,
@@ -35,7 +37,7 @@ impl GridCollider {
     /// Return the actual grid entry corresponding to this position. -1 for null
     pub fn get_entry(&self, x: i32, y: i32, z: i32) -> isize {
         if x < -self.cx || y < -self.cy || z < -self.cz { return -1; }
-        if x >=self.x as i32-self.cx || y >= self.z as i32-self.cz || z >= self.z as i32-self.cz { return -1; }
+        if x >= self.x as i32-self.cx || y >= self.y as i32-self.cy || z >= self.z as i32-self.cz { return -1; }
         let index = self.get_index(x, y, z);
         self.data[index as usize]
     }
@@ -87,6 +89,11 @@ impl GridCollider {
             (self.cz as f64 + line.p.z, -line.v.z),
             ((self.z as i32 - self.cz) as f64 - line.p.z, line.v.z),
         ] {
+            if normal == 0. {
+                // The line is parallel to this plane, so it misses the grid unless it is already inside
+                if vec < 0. { stop_alpha = -f64::INFINITY; }
+                continue;
+            }
             let alpha = vec / normal;
             if normal < 0. {
                 // Normal is pointing toward p
@@ -97,6 +104,11 @@ impl GridCollider {
             }
         }
 
+        if start_alpha > stop_alpha || self.data.is_empty() {
+            // The line misses the grid
+            return CollisionReport::none();
+        }
+
         // Initialize the algorithm
         let delta = line.v * (stop_alpha - start_alpha);
         let dx = delta.x.abs().round() as i32;
@@ -119,7 +131,13 @@ impl GridCollider {
             _ => unreachable!(),
         };
 
-        let start: Vector3<i32> = (line.p + line.v * start_alpha).cast().unwrap();
+        // The entry point lies on the boundary of the grid, so keep rounding errors from pushing it outside
+        let entry_point = line.p + line.v * start_alpha;
+        let start = Vector3::new(
+            (entry_point.x.floor() as i32).clamp(-self.cx, self.x as i32 - self.cx - 1),
+            (entry_point.y.floor() as i32).clamp(-self.cy, self.y as i32 - self.cy - 1),
+            (entry_point.z.floor() as i32).clamp(-self.cz, self.z as i32 - self.cz - 1),
+        );
         let mut point = start;
         
         loop {
@@ -129,7 +147,7 @@ impl GridCollider {
             if iy < 0 || iy >= self.y as i32 {break;}
             let iz = point.z + self.cz;
             if iz < 0 || iz >= self.z as i32 {break;}
-            let entry = self.get_entry(ix, iy, iz);
+            let entry = self.get_entry(point.x, point.y, point.z);
             if entry != -1 {
                 let mut collide_alpha = line.start.unwrap_or(-f64::INFINITY);
                 for (vec, normal) in [ // Defined such that vec / normal is the alpha of this plane
@@ -200,13 +218,7 @@ impl GridCollider {
     }
     
     pub(crate) fn check_point(&self, p: Vector3<f64>) -> CollisionReport {
-        let ix = (p.x as i32 + self.cx) as i32;
-        if ix < 0 || ix >= self.x as i32 {return CollisionReport::none();}
-        let iy = (p.y as i32 + self.cy) as i32;
-        if iy < 0 || iy >= self.y as i32 {return CollisionReport::none();}
-        let iz = (p.z as i32 + self.cz) as i32;
-        if iz < 0 || iz >= self.z as i32 {return CollisionReport::none();}
-        let entry = self.get_entry(ix, iy, iz);
+        let entry = self.get_entry(p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32);
         if entry != -1 {
             // I don't know the depth
             let mut report = CollisionReport::new(Vector3::new(0., 0., 0.,), p);
@@ -216,6 +228,62 @@ impl GridCollider {
             CollisionReport::none()
         }
     } 
+
+    /// Check for collisions between a grid and a box by treating every filled cell as a unit box.
+    /// The report is in the inertial frame, with the cell value of each contact in `index`.
+    pub(crate) fn check_box(&self, rigid_body: Option<&RigidBody>, o: &BoxCollider, o_rigid_body: Option<&RigidBody>) -> CollisionReport {
+        let mut output = CollisionReport::none();
+        for ((x, y, z), entry) in self.cells_near(o.get_points().map(|p| reorient(p, o_rigid_body, rigid_body))) {
+            let cell = BoxCollider::new(Vector3::new(x as f64, y as f64, z as f64), Vector3::new(1., 1., 1.));
+            let mut report = cell.check_box(rigid_body, o, o_rigid_body);
+            report.index = vec![entry; report.len()];
+            output += report;
+        }
+        output
+    }
+
+    /// Check for collisions between two grids. The report is in the inertial frame, with the cell
+    /// value of this grid at each contact in `index`.
+    pub(crate) fn check_grid(&self, rigid_body: Option<&RigidBody>, o: &GridCollider, o_rigid_body: Option<&RigidBody>) -> CollisionReport {
+        let mut output = CollisionReport::none();
+        for ((x, y, z), entry) in self.cells_near(o.bounds().get_points().map(|p| reorient(p, o_rigid_body, rigid_body))) {
+            let cell = BoxCollider::new(Vector3::new(x as f64, y as f64, z as f64), Vector3::new(1., 1., 1.));
+            let mut report = o.check_box(o_rigid_body, &cell, rigid_body).flip();
+            report.index = vec![entry; report.len()];
+            output += report;
+        }
+        output
+    }
+
+    /// Box in the grid frame which contains every cell
+    pub(crate) fn bounds(&self) -> BoxCollider {
+        BoxCollider::new(
+            Vector3::new(-self.cx as f64, -self.cy as f64, -self.cz as f64),
+            Vector3::new(self.x as f64, self.y as f64, self.z as f64),
+        )
+    }
+
+    /// Filled cells which overlap the bounding box of a set of points in the grid frame
+    fn cells_near(&self, points: impl Iterator<Item = Vector3<f64>>) -> Vec<((i32, i32, i32), isize)> {
+        let mut min = Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = -min;
+        for p in points {
+            min = Vector3::new(min.x.min(p.x), min.y.min(p.y), min.z.min(p.z));
+            max = Vector3::new(max.x.max(p.x), max.y.max(p.y), max.z.max(p.z));
+        }
+        let mut output = Vec::new();
+        for z in (min.z.floor() as i32).max(-self.cz)..=(max.z.floor() as i32).min(self.z as i32 - self.cz - 1) {
+            for y in (min.y.floor() as i32).max(-self.cy)..=(max.y.floor() as i32).min(self.y as i32 - self.cy - 1) {
+                for x in (min.x.floor() as i32).max(-self.cx)..=(max.x.floor() as i32).min(self.x as i32 - self.cx - 1) {
+                    let entry = self.get_entry(x, y, z);
+                    if entry != -1 {
+                        output.push(((x, y, z), entry));
+                    }
+                }
+            }
+        }
+        output
+    }
 }
 
 pub struct IndexedIterator<'a> {
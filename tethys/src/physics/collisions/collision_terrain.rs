@@ -0,0 +1,217 @@
+use cgmath::{InnerSpace, Vector3};
+
+use crate::physics::RigidBody;
+use crate::util::BinaryTree;
+
+use super::{reorient, BoxCollider, CollisionReport, GridCollider, LineCollider};
+
+/// Number of steps taken along a line through the bounding sphere when looking for the ground
+const LINE_STEPS: u32 = 1024;
+/// Halvings of the last step of a line which crossed the ground, to find where it did
+const LINE_REFINEMENTS: u32 = 16;
+/// Angle in radians between the samples used to find the slope of the ground
+const NORMAL_ANGLE: f64 = 1e-5;
+
+/// The ground of a planet, which is solid below a surface given by its distance from the center
+/// along each direction. Only the corners of boxes and grid cells are checked against it, which
+/// is enough for landing as long as the ground is smooth compared to the boxes.
+pub struct TerrainCollider {
+    /// Center of the planet in the collider frame
+    pub center: Vector3<f64>,
+    /// Distance from the center which no part of the ground reaches beyond
+    pub max_radius: f64,
+    radius: Box<dyn Fn(Vector3<f64>) -> f64>,
+}
+
+impl TerrainCollider {
+    /// Make the ground from its distance from the center along each unit vector
+    pub fn new(center: Vector3<f64>, max_radius: f64, radius: impl Fn(Vector3<f64>) -> f64 + 'static) -> Self {
+        Self {
+            center,
+            max_radius,
+            radius: Box::new(radius),
+        }
+    }
+
+    /// Distance from the center to the ground along a unit vector
+    pub fn radius(&self, direction: Vector3<f64>) -> f64 {
+        (self.radius)(direction)
+    }
+
+    /// Depth of a point in the collider frame below the ground, measured along the normal of the
+    /// ground, and the normal. Returns None if the point is above the ground.
+    fn depth(&self, p: Vector3<f64>) -> Option<(f64, Vector3<f64>)> {
+        let offset = p - self.center;
+        let distance = offset.magnitude();
+        if distance >= self.max_radius {
+            return None;
+        }
+        let direction = if distance > 0. { offset / distance } else { Vector3::unit_z() };
+        let height = self.radius(direction) - distance;
+        if height <= 0. {
+            return None;
+        }
+        let normal = self.normal(direction);
+        Some((height * direction.dot(normal), normal))
+    }
+
+    /// Outward normal of the ground above a unit vector, from the slope between nearby samples
+    fn normal(&self, direction: Vector3<f64>) -> Vector3<f64> {
+        let up = if direction.z.abs() < 0.9 { Vector3::unit_z() } else { Vector3::unit_x() };
+        let tangent = up.cross(direction).normalize();
+        let bitangent = direction.cross(tangent);
+        let surface = |d: Vector3<f64>| d.normalize() * self.radius(d.normalize());
+        let base = surface(direction);
+        let normal = (surface(direction + tangent * NORMAL_ANGLE) - base)
+            .cross(surface(direction + bitangent * NORMAL_ANGLE) - base);
+        if normal.magnitude2() > 0. { normal.normalize() } else { direction }
+    }
+
+    /// Check for collisions between the ground and a point in the collider frame. The depth moves
+    /// the point out of the ground.
+    pub(crate) fn check_point(&self, p: Vector3<f64>) -> CollisionReport {
+        match self.depth(p) {
+            Some((depth, normal)) => CollisionReport::new(normal * depth, p),
+            None => CollisionReport::none(),
+        }
+    }
+
+    /// Find where a line in the collider frame first enters the ground by stepping through the
+    /// bounding sphere
+    pub(crate) fn check_line(&self, line: LineCollider) -> CollisionReport {
+        // Clip the line to the bounding sphere
+        let offset = line.p - self.center;
+        let a = line.v.magnitude2();
+        let b = offset.dot(line.v);
+        let c = offset.magnitude2() - self.max_radius * self.max_radius;
+        let discriminant = b * b - a * c;
+        if a == 0. || discriminant < 0. {
+            return CollisionReport::none();
+        }
+        let start = ((-b - discriminant.sqrt()) / a).max(line.start.unwrap_or(f64::NEG_INFINITY));
+        let stop = ((-b + discriminant.sqrt()) / a).min(line.stop.unwrap_or(f64::INFINITY));
+        if start > stop {
+            return CollisionReport::none();
+        }
+
+        let below = |alpha: f64| self.depth(line.p + line.v * alpha).is_some();
+        let step = (stop - start) / LINE_STEPS as f64;
+        let mut above = start;
+        for i in 1..=LINE_STEPS {
+            let alpha = start + step * i as f64;
+            if below(alpha) {
+                let mut hit = alpha;
+                for _ in 0..LINE_REFINEMENTS {
+                    let middle = (above + hit) / 2.;
+                    if below(middle) { hit = middle } else { above = middle }
+                }
+                return CollisionReport::new(line.v * hit, line.p + line.v * hit);
+            }
+            above = alpha;
+        }
+        CollisionReport::none()
+    }
+
+    /// Check for collisions between the ground and the corners of a box. The report is in the
+    /// inertial frame, with depths that move the ground.
+    pub(crate) fn check_box(&self, rigid_body: Option<&RigidBody>, o: &BoxCollider, o_rigid_body: Option<&RigidBody>) -> CollisionReport {
+        if !self.may_touch(o, o_rigid_body, rigid_body) {
+            return CollisionReport::none();
+        }
+        let mut output = CollisionReport::none();
+        for corner in o.get_points().map(|p| reorient(p, o_rigid_body, rigid_body)) {
+            if let Some((depth, normal)) = self.depth(corner) {
+                output += CollisionReport::new(-normal * depth, corner);
+            }
+        }
+        output.reorient(rigid_body)
+    }
+
+    /// Check for collisions between the ground and every filled cell of a grid. The report is in
+    /// the inertial frame, with the cell value of each contact in `index`.
+    pub(crate) fn check_grid(&self, rigid_body: Option<&RigidBody>, o: &GridCollider, o_rigid_body: Option<&RigidBody>) -> CollisionReport {
+        let mut output = CollisionReport::none();
+        if !self.may_touch(&o.bounds(), o_rigid_body, rigid_body) {
+            return output;
+        }
+        for ((x, y, z), entry) in o.indexed_iter() {
+            if entry == -1 {
+                continue;
+            }
+            let cell = BoxCollider::new(Vector3::new(x as f64, y as f64, z as f64), Vector3::new(1., 1., 1.));
+            let mut report = self.check_box(rigid_body, &cell, o_rigid_body);
+            report.index = vec![entry; report.len()];
+            output += report;
+        }
+        output
+    }
+
+    /// Check for collisions between the ground and the leaves of a tree of boxes. The branches
+    /// only bound their leaves, so they are skipped by their bounding spheres alone.
+    pub(crate) fn check_tree(&self, rigid_body: Option<&RigidBody>, t: &BinaryTree<BoxCollider>, o_rigid_body: Option<&RigidBody>) -> CollisionReport {
+        let mut node_queue = vec![t.root()];
+        let mut report = CollisionReport::none();
+        while let Some(node) = node_queue.pop() {
+            if !self.may_touch(&node, o_rigid_body, rigid_body) {
+                continue;
+            }
+            if node.left().is_none() && node.right().is_none() {
+                report += self.check_box(rigid_body, &node, o_rigid_body);
+            }
+            if let Some(n) = node.left() { node_queue.push(n); }
+            if let Some(n) = node.right() { node_queue.push(n); }
+        }
+        report
+    }
+
+    /// Whether the sphere around a box reaches within the bounding sphere of the ground
+    fn may_touch(&self, o: &BoxCollider, o_rigid_body: Option<&RigidBody>, rigid_body: Option<&RigidBody>) -> bool {
+        let center = reorient(o.corner + o.dimensions / 2., o_rigid_body, rigid_body);
+        (center - self.center).magnitude() - o.dimensions.magnitude() / 2. < self.max_radius
+    }
+
+    /// Box in the collider frame which contains the bounding sphere
+    pub(crate) fn bounds(&self) -> BoxCollider {
+        let half = Vector3::new(self.max_radius, self.max_radius, self.max_radius);
+        BoxCollider::new(self.center - half, half * 2.)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Vector3};
+
+    use super::*;
+
+    /// A round planet of radius 10 around the origin
+    fn ball() -> TerrainCollider {
+        TerrainCollider::new(Vector3::new(0., 0., 0.), 10., |_| 10.)
+    }
+
+    #[test]
+    fn point_below_the_ground_is_pushed_out() {
+        let report = ball().check_point(Vector3::new(0., 0., 9.5));
+        assert_eq!(report.len(), 1);
+        assert!((report.depths[0] - Vector3::new(0., 0., 0.5)).magnitude() < 1e-4, "{:?}", report.depths[0]);
+    }
+
+    #[test]
+    fn point_above_the_ground_does_not_touch() {
+        assert!(!ball().check_point(Vector3::new(0., 0., 10.5)).collision());
+        assert!(!ball().check_point(Vector3::new(0., 0., 20.)).collision());
+    }
+
+    #[test]
+    fn box_corners_below_the_ground_push_the_ground_in() {
+        let sunk = BoxCollider::new(Vector3::new(-0.5, -0.5, 9.8), Vector3::new(1., 1., 1.));
+        let report = ball().check_box(None, &sunk, None);
+        assert_eq!(report.len(), 4);
+        for (depth, position) in report.depths.iter().zip(&report.positions) {
+            assert!(depth.dot(position.normalize()) < 0., "{depth:?} does not point into the ground");
+            assert!((depth.magnitude() - (10. - position.magnitude())).abs() < 1e-4);
+        }
+
+        let above = BoxCollider::new(Vector3::new(-0.5, -0.5, 10.1), Vector3::new(1., 1., 1.));
+        assert!(!ball().check_box(None, &above, None).collision());
+    }
+}
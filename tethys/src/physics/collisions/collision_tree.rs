@@ -131,7 +131,10 @@ pub(crate) fn check_tree(t: &BinaryTree<BoxCollider>, check_function: impl Fn(&B
     while let Some(node) = node_queue.pop() {
         let this_report = check_function(&node);
         if this_report.collision() {
-            report += this_report;
+            // Only the leaves are real boxes; the other nodes just bound their children
+            if node.left().is_none() && node.right().is_none() {
+                report += this_report;
+            }
             if let Some(n) = node.left() { node_queue.push(n);}
             if let Some(n) = node.right() { node_queue.push(n);}
         }
@@ -12,11 +12,13 @@ mod collision_box;
 mod collision_line;
 mod collision_grid;
 mod collision_tree;
+mod collision_terrain;
 
 pub use report::{ColliderPackage, CollisionReport};
 pub use collision_box::BoxCollider;
 pub use collision_line::LineCollider;
 pub use collision_grid::GridCollider;
+pub use collision_terrain::TerrainCollider;
 
 
 
@@ -26,6 +28,7 @@ pub enum Collider {
     Grid(GridCollider),
     Box(BoxCollider),
     BoxTree(BinaryTree<BoxCollider>),
+    Terrain(TerrainCollider),
 }
 
 impl Collider {
@@ -39,7 +42,7 @@ impl Collider {
             }
             (Collider::Box(x), Collider::Point { p }) => {
                 let p = reorient(*p, b.rigid_body, a.rigid_body);
-                x.check_point(p).reorient(a.rigid_body)
+                x.check_point(p).reorient(a.rigid_body).flip()
             },
 
             (Collider::Grid(grid), Collider::Point { p }) => {
@@ -53,13 +56,22 @@ impl Collider {
 
             (Collider::BoxTree(t), Collider::Point { p }) => {
                 let p = reorient(*p, b.rigid_body, a.rigid_body);
-                check_tree(t, |x| x.check_point(p)).reorient(a.rigid_body)
+                check_tree(t, |x| x.check_point(p)).reorient(a.rigid_body).flip()
             },
             (Collider::Point { p }, Collider::BoxTree(t)) => {
                 let p = reorient(*p, a.rigid_body, b.rigid_body);
                 check_tree(t, |x| x.check_point(p)).reorient(b.rigid_body)
             },
 
+            (Collider::Terrain(terrain), Collider::Point { p }) => {
+                let p = reorient(*p, b.rigid_body, a.rigid_body);
+                terrain.check_point(p).reorient(a.rigid_body).flip()
+            },
+            (Collider::Point { p }, Collider::Terrain(terrain)) => {
+                let p = reorient(*p, a.rigid_body, b.rigid_body);
+                terrain.check_point(p).reorient(b.rigid_body)
+            },
+
             // ==================================== LINE ====================================
             (Collider::Line(line), Collider::Box(x)) => {
                 x.check_line(line.reorient(a.rigid_body, b.rigid_body)).reorient(b.rigid_body)
@@ -81,42 +93,101 @@ impl Collider {
             (Collider::Line(line), Collider::BoxTree(t)) => {
                 check_tree(t, |x| x.check_line(line.reorient(a.rigid_body, b.rigid_body))).reorient(b.rigid_body)
             },
+
+            (Collider::Terrain(terrain), Collider::Line(line)) => {
+                terrain.check_line(line.reorient(b.rigid_body, a.rigid_body)).reorient(a.rigid_body)
+            },
+            (Collider::Line(line), Collider::Terrain(terrain)) => {
+                terrain.check_line(line.reorient(a.rigid_body, b.rigid_body)).reorient(b.rigid_body)
+            },
             
             // ==================================== BOX ====================================
+            // Box checks already report in the inertial frame, with depths that move the first box
             (Collider::Box(x1), Collider::Box(x2)) => {
-                x1.check_box(a.rigid_body, x2, b.rigid_body).reorient(a.rigid_body)
+                x1.check_box(a.rigid_body, x2, b.rigid_body)
             },
 
-            (Collider::Grid(_), Collider::Box(_)) => unimplemented!(),
-            (Collider::Box(_), Collider::Grid(_)) => unimplemented!(),
+            (Collider::Grid(grid), Collider::Box(x)) => {
+                grid.check_box(a.rigid_body, x, b.rigid_body)
+            },
+            (Collider::Box(x), Collider::Grid(grid)) => {
+                grid.check_box(b.rigid_body, x, a.rigid_body).flip()
+            },
 
             (Collider::BoxTree(t), Collider::Box(x)) => {
-                check_tree(t, |xx| xx.check_box(a.rigid_body, x, b.rigid_body)).reorient(a.rigid_body)
+                check_tree(t, |xx| xx.check_box(a.rigid_body, x, b.rigid_body))
             },
             (Collider::Box(x), Collider::BoxTree(t)) => {
-                check_tree(t, |xx| xx.check_box(b.rigid_body, x, a.rigid_body)).reorient(b.rigid_body)
+                check_tree(t, |xx| xx.check_box(b.rigid_body, x, a.rigid_body)).flip()
+            },
+
+            (Collider::Terrain(terrain), Collider::Box(x)) => {
+                terrain.check_box(a.rigid_body, x, b.rigid_body)
+            },
+            (Collider::Box(x), Collider::Terrain(terrain)) => {
+                terrain.check_box(b.rigid_body, x, a.rigid_body).flip()
             },
             
             // ==================================== GRID ====================================
-            (Collider::Grid(_), Collider::Grid(_)) => unimplemented!(),
+            (Collider::Grid(g1), Collider::Grid(g2)) => {
+                g1.check_grid(a.rigid_body, g2, b.rigid_body)
+            },
+
+            (Collider::Grid(grid), Collider::BoxTree(t)) => {
+                check_tree(t, |x| grid.check_box(a.rigid_body, x, b.rigid_body))
+            },
+            (Collider::BoxTree(t), Collider::Grid(grid)) => {
+                check_tree(t, |x| grid.check_box(b.rigid_body, x, a.rigid_body)).flip()
+            },
 
-            (Collider::Grid(_), Collider::BoxTree(_)) => unimplemented!(),
-            (Collider::BoxTree(_), Collider::Grid(_)) => unimplemented!(),
+            (Collider::Terrain(terrain), Collider::Grid(grid)) => {
+                terrain.check_grid(a.rigid_body, grid, b.rigid_body)
+            },
+            (Collider::Grid(grid), Collider::Terrain(terrain)) => {
+                terrain.check_grid(b.rigid_body, grid, a.rigid_body).flip()
+            },
 
             // ==================================== TREE ====================================
             (Collider::BoxTree(t1), Collider::BoxTree(t2)) => {
                 //OPTIMIZE
-                check_tree(t1, |x1| check_tree(t2, |x2| x1.check_box(a.rigid_body, x2, b.rigid_body))).reorient(a.rigid_body)
+                check_tree(t1, |x1| check_tree(t2, |x2| x1.check_box(a.rigid_body, x2, b.rigid_body)))
+            },
+
+            (Collider::Terrain(terrain), Collider::BoxTree(t)) => {
+                terrain.check_tree(a.rigid_body, t, b.rigid_body)
+            },
+            (Collider::BoxTree(t), Collider::Terrain(terrain)) => {
+                terrain.check_tree(b.rigid_body, t, a.rigid_body).flip()
             },
 
             // ==================================== NULL ====================================
             (Collider::Point { .. }, Collider::Point { .. }) |
             (Collider::Point { .. }, Collider::Line { .. }) |
             (Collider::Line { .. }, Collider::Point { .. }) |
-            (Collider::Line { .. }, Collider::Line { .. }) => CollisionReport::none(),
+            (Collider::Line { .. }, Collider::Line { .. }) |
+            (Collider::Terrain(_), Collider::Terrain(_)) => CollisionReport::none(),
         }
     }
     
+    /// Box in the collider's own frame which contains the whole collider. Returns None if the
+    /// collider is unbounded, such as a ray.
+    pub fn bounds(&self) -> Option<BoxCollider> {
+        match self {
+            Collider::Point { p } => Some(BoxCollider::new(*p, Vector3::new(0., 0., 0.))),
+            Collider::Line(line) => {
+                let start = line.p + line.v * line.start?;
+                let stop = line.p + line.v * line.stop?;
+                let corner = Vector3::new(start.x.min(stop.x), start.y.min(stop.y), start.z.min(stop.z));
+                let far = Vector3::new(start.x.max(stop.x), start.y.max(stop.y), start.z.max(stop.z));
+                Some(BoxCollider::new(corner, far - corner))
+            },
+            Collider::Grid(grid) => Some(grid.bounds()),
+            Collider::Box(x) => Some(x.clone()),
+            Collider::BoxTree(t) => Some((*t.root()).clone()),
+            Collider::Terrain(terrain) => Some(terrain.bounds()),
+        }
+    }
+
     pub fn get_grid_collider_mut(&mut self) -> Result<&mut GridCollider> {
         match self {
             Self::Grid(c) => Ok(c),
@@ -134,12 +205,9 @@ impl Collider {
 
 pub(crate) fn reorient(v: Vector3<f64>, from: Option<&RigidBody>, to: Option<&RigidBody>) -> Vector3<f64> {
     match (from, to) {
-        (Some(from), Some(to)) => to.orientation.invert().rotate_vector(
-            from.orientation.rotate_vector(v)
-            + from.pos - to.pos
-        ),
-        (None, Some(to)) => to.orientation.invert().rotate_vector(v - to.pos),
-        (Some(from), None) => from.orientation.rotate_vector(v) + from.pos,
+        (Some(from), Some(to)) => to.to_local(from.to_global(v)),
+        (None, Some(to)) => to.to_local(v),
+        (Some(from), None) => from.to_global(v),
         (None, None) => v,
     }
 }
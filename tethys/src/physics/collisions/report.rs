@@ -40,10 +40,12 @@ impl<'a> From<&'a Collider> for ColliderPackage<'a> {
 
 #[derive(Clone, Debug)]
 pub struct CollisionReport {
-    /// Vector that points from the surface of the shape to the collision point
+    /// Translation of the first collider which would separate it from the second at each contact.
+    /// Its direction is the contact normal and its length is the penetration depth.
     pub depths: Vec<Vector3<f64>>,
     pub positions: Vec<Vector3<f64>>,
-    /// Value of the grid if collided with a grid
+    /// Value of the grid cell at each collision if collided with a grid. When two grids collide,
+    /// this is the cell of the first grid.
     pub index: Vec<isize>,
 }
 
@@ -67,7 +69,7 @@ impl CollisionReport {
         Self {
             depths: self.depths.iter().map(|v| reorient_rot(*v, from, None)).collect(),
             positions: self.positions.iter().map(|v| reorient(*v, from, None)).collect(),
-            index: Vec::new(),
+            index: self.index.clone(),
         }
     }
     /// Rotate into the frame of a given body
@@ -75,16 +77,24 @@ impl CollisionReport {
         Self {
             depths: self.depths.iter().map(|v| reorient_rot(*v, None, Some(to))).collect(),
             positions: self.positions.iter().map(|v| reorient(*v, None, Some(to))).collect(),
-            index: Vec::new(),
+            index: self.index.clone(),
         }
     }
     
+    /// Swap the roles of the two colliders
+    pub(crate) fn flip(mut self) -> Self {
+        for depth in &mut self.depths {
+            *depth = -*depth;
+        }
+        self
+    }
+
     pub fn collision(&self) -> bool {
         !self.positions.is_empty()
     }
 
     fn deepest_mag2(&self) -> f64 {
-        self.positions.iter().fold(0., |accum, v| v.magnitude2().max(accum))
+        self.depths.iter().fold(0., |accum, v| v.magnitude2().max(accum))
     }
 
     pub fn len(&self) -> usize {
@@ -0,0 +1,148 @@
+use std::f64::consts::PI;
+
+use cgmath::{InnerSpace, Vector3, Zero};
+
+use super::RigidBody;
+
+/// Newton's gravitational constant in SI units
+pub const G: f64 = 6.674e-11;
+
+/// Eccentricities and inclinations below this are treated as zero, where the angles they define
+/// become degenerate
+const DEGENERATE_TOLERANCE: f64 = 1e-10;
+
+/// A spherically symmetric body which attracts everything around it
+#[derive(Clone, Copy, Debug)]
+pub struct PointMass {
+    pub pos: Vector3<f64>,
+    /// Standard gravitational parameter `G M`
+    pub mu: f64,
+}
+
+impl PointMass {
+    pub fn new(pos: Vector3<f64>, mass: f64) -> Self {
+        Self { pos, mu: G * mass }
+    }
+
+    /// Gravitational acceleration felt at `pos`
+    pub fn acceleration(&self, pos: Vector3<f64>) -> Vector3<f64> {
+        let delta = self.pos - pos;
+        let r2 = delta.magnitude2();
+        if r2 == 0. {
+            return Vector3::zero();
+        }
+        delta * (self.mu / (r2 * r2.sqrt()))
+    }
+
+    /// Speed of a circular orbit at distance `r`
+    pub fn circular_speed(&self, r: f64) -> f64 {
+        (self.mu / r).sqrt()
+    }
+}
+
+/// The combined gravity of any number of point masses
+#[derive(Clone, Debug, Default)]
+pub struct GravityField {
+    pub sources: Vec<PointMass>,
+}
+
+impl GravityField {
+    pub fn new(sources: Vec<PointMass>) -> Self {
+        Self { sources }
+    }
+
+    pub fn acceleration(&self, pos: Vector3<f64>) -> Vector3<f64> {
+        self.sources.iter().map(|s| s.acceleration(pos)).sum()
+    }
+
+    /// Add the gravitational force on a body to its accumulated force
+    pub fn apply(&self, body: &mut RigidBody) {
+        body.force += self.acceleration(body.pos) * body.mass;
+    }
+}
+
+/// Classical Keplerian orbital elements. Angles are in radians.
+///
+/// For circular orbits the argument of periapsis is zero and the true anomaly is measured from the
+/// ascending node. For equatorial orbits the longitude of the ascending node is zero and angles are
+/// measured from the x axis. Hyperbolic orbits have a negative semi-major axis.
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitalElements {
+    pub semi_major_axis: f64,
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub longitude_of_ascending_node: f64,
+    pub argument_of_periapsis: f64,
+    pub true_anomaly: f64,
+}
+
+impl OrbitalElements {
+    /// Find the orbit of a body with position `pos` and velocity `vel` relative to a central body
+    /// with gravitational parameter `mu`
+    pub fn from_state(pos: Vector3<f64>, vel: Vector3<f64>, mu: f64) -> Self {
+        let r = pos.magnitude();
+        let h = pos.cross(vel);
+        let node = Vector3::unit_z().cross(h);
+        let e_vec = vel.cross(h) / mu - pos / r;
+        let eccentricity = e_vec.magnitude();
+        let energy = vel.magnitude2() / 2. - mu / r;
+        let semi_major_axis = -mu / (2. * energy);
+        let inclination = (h.z / h.magnitude()).clamp(-1., 1.).acos();
+
+        // Angle from `from` to `to` measured in the orbital plane
+        let angle = |from: Vector3<f64>, to: Vector3<f64>| {
+            let cos = (from.dot(to) / (from.magnitude() * to.magnitude())).clamp(-1., 1.);
+            let angle = cos.acos();
+            if from.cross(to).dot(h) < 0. { 2. * PI - angle } else { angle }
+        };
+
+        let equatorial = inclination < DEGENERATE_TOLERANCE || PI - inclination < DEGENERATE_TOLERANCE;
+        let circular = eccentricity < DEGENERATE_TOLERANCE;
+        let reference = if equatorial { Vector3::unit_x() } else { node };
+        let longitude_of_ascending_node = if equatorial { 0. } else { node.y.atan2(node.x).rem_euclid(2. * PI) };
+        let (argument_of_periapsis, true_anomaly) = if circular {
+            (0., angle(reference, pos))
+        } else {
+            (angle(reference, e_vec), angle(e_vec, pos))
+        };
+
+        Self {
+            semi_major_axis,
+            eccentricity,
+            inclination,
+            longitude_of_ascending_node,
+            argument_of_periapsis,
+            true_anomaly,
+        }
+    }
+
+    /// Position and velocity relative to a central body with gravitational parameter `mu`
+    pub fn to_state(&self, mu: f64) -> (Vector3<f64>, Vector3<f64>) {
+        let e = self.eccentricity;
+        let p = self.semi_major_axis * (1. - e * e);
+        let (sin_nu, cos_nu) = self.true_anomaly.sin_cos();
+        let r = p / (1. + e * cos_nu);
+
+        // Perifocal frame, with x toward periapsis
+        let pos = Vector3::new(r * cos_nu, r * sin_nu, 0.);
+        let vel = Vector3::new(-sin_nu, e + cos_nu, 0.) * (mu / p).sqrt();
+
+        let rotate = |v: Vector3<f64>| {
+            let (sin_w, cos_w) = self.argument_of_periapsis.sin_cos();
+            let (sin_o, cos_o) = self.longitude_of_ascending_node.sin_cos();
+            let (sin_i, cos_i) = self.inclination.sin_cos();
+            let v = Vector3::new(cos_w * v.x - sin_w * v.y, sin_w * v.x + cos_w * v.y, v.z);
+            let v = Vector3::new(v.x, cos_i * v.y - sin_i * v.z, sin_i * v.y + cos_i * v.z);
+            Vector3::new(cos_o * v.x - sin_o * v.y, sin_o * v.x + cos_o * v.y, v.z)
+        };
+        (rotate(pos), rotate(vel))
+    }
+
+    /// Time taken to complete one orbit. Returns None for unbound orbits.
+    pub fn period(&self, mu: f64) -> Option<f64> {
+        if self.eccentricity >= 1. {
+            return None;
+        }
+        Some(2. * PI * (self.semi_major_axis.powi(3) / mu).sqrt())
+    }
+}
@@ -0,0 +1,201 @@
+use cgmath::{InnerSpace, Matrix3, Quaternion, Rotation, SquareMatrix, Vector3, Zero};
+use serde::{Deserialize, Serialize};
+
+use super::RigidBody;
+
+/// Maximum number of fixed point iterations used to solve the implicit midpoint step
+const MAX_MIDPOINT_ITERATIONS: usize = 16;
+/// Convergence threshold of the implicit midpoint step, relative to the angular momentum
+const MIDPOINT_TOLERANCE: f64 = 1e-14;
+
+/// Numerical method used to advance a rigid body. Forces and torques are held constant over a step.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Integrator {
+    /// First order. Velocities are updated before positions, which keeps orbits bounded. Angular momentum is conserved but the energy of tumbling bodies drifts
+    SemiImplicitEuler,
+    /// Fourth order Runge-Kutta. Very accurate for short times, but energy slowly decays. Since gravity is
+    /// held constant over the step this is only first order for orbits, which spiral outwards
+    RungeKutta4,
+    /// Conserves the energy and angular momentum of a torque-free body exactly. Rotation is a second order
+    /// splitting which uses the implicit midpoint rule on the body-frame angular momentum. Translation is
+    /// symplectic Euler, a kick from the force followed by a drift. That is only first order, but the energy
+    /// of an orbit stays bounded, so orbits neither decay nor spiral outwards over thousands of periods.
+    #[default]
+    Symplectic,
+}
+
+impl Integrator {
+    pub(crate) fn step(self, body: &mut RigidBody, delta_t: f64) {
+        match self {
+            Integrator::SemiImplicitEuler => semi_implicit_euler(body, delta_t),
+            Integrator::RungeKutta4 => runge_kutta_4(body, delta_t),
+            Integrator::Symplectic => symplectic(body, delta_t),
+        }
+        body.orientation = body.orientation.normalize();
+    }
+}
+
+/// Inverse of the body-frame inertia tensor. A singular tensor cannot be spun by torques.
+fn inverse_moi(body: &RigidBody) -> Matrix3<f64> {
+    body.moi.invert().unwrap_or(Matrix3::zero())
+}
+
+/// Rate of change of the body-frame angular velocity according to Euler's equations
+fn body_angular_acceleration(moi: Matrix3<f64>, inv_moi: Matrix3<f64>, body_angvel: Vector3<f64>, body_torque: Vector3<f64>) -> Vector3<f64> {
+    inv_moi * (body_torque - body_angvel.cross(moi * body_angvel))
+}
+
+fn semi_implicit_euler(body: &mut RigidBody, delta_t: f64) {
+    body.vel += body.force * delta_t / body.mass;
+    body.pos += body.vel * delta_t;
+
+    // Stepping the angular momentum rather than the angular velocity includes the gyroscopic term
+    let momentum = body.angular_momentum() + body.torque * delta_t;
+    let angvel = body.world_inverse_moi() * momentum;
+    body.orientation = rotation_from_vector(angvel * delta_t) * body.orientation;
+    body.angvel = body.world_inverse_moi() * momentum;
+}
+
+fn runge_kutta_4(body: &mut RigidBody, delta_t: f64) {
+    #[derive(Clone, Copy)]
+    struct State {
+        pos: Vector3<f64>,
+        vel: Vector3<f64>,
+        orientation: Quaternion<f64>,
+        body_angvel: Vector3<f64>,
+    }
+    impl State {
+        fn advance(&self, d: &State, dt: f64) -> State {
+            State {
+                pos: self.pos + d.pos * dt,
+                vel: self.vel + d.vel * dt,
+                orientation: self.orientation + d.orientation * dt,
+                body_angvel: self.body_angvel + d.body_angvel * dt,
+            }
+        }
+    }
+
+    let inv_moi = inverse_moi(body);
+    let accel = body.force / body.mass;
+    let derivative = |s: &State| {
+        // The torque is fixed in the inertial frame, so it turns in the body frame as the body rotates
+        let body_torque = s.orientation.normalize().invert().rotate_vector(body.torque);
+        State {
+            pos: s.vel,
+            vel: accel,
+            orientation: s.orientation * Quaternion::from_sv(0., s.body_angvel) * 0.5,
+            body_angvel: body_angular_acceleration(body.moi, inv_moi, s.body_angvel, body_torque),
+        }
+    };
+
+    let y = State {
+        pos: body.pos,
+        vel: body.vel,
+        orientation: body.orientation,
+        body_angvel: body.orientation.invert().rotate_vector(body.angvel),
+    };
+    let k1 = derivative(&y);
+    let k2 = derivative(&y.advance(&k1, delta_t / 2.));
+    let k3 = derivative(&y.advance(&k2, delta_t / 2.));
+    let k4 = derivative(&y.advance(&k3, delta_t));
+    let slope = State {
+        pos: (k1.pos + k2.pos * 2. + k3.pos * 2. + k4.pos) / 6.,
+        vel: (k1.vel + k2.vel * 2. + k3.vel * 2. + k4.vel) / 6.,
+        orientation: (k1.orientation + k2.orientation * 2. + k3.orientation * 2. + k4.orientation) * (1. / 6.),
+        body_angvel: (k1.body_angvel + k2.body_angvel * 2. + k3.body_angvel * 2. + k4.body_angvel) / 6.,
+    };
+    let y = y.advance(&slope, delta_t);
+
+    body.pos = y.pos;
+    body.vel = y.vel;
+    body.orientation = y.orientation.normalize();
+    body.angvel = body.orientation.rotate_vector(y.body_angvel);
+}
+
+fn symplectic(body: &mut RigidBody, delta_t: f64) {
+    // Translation: kick then drift
+    body.vel += body.force * delta_t / body.mass;
+    body.pos += body.vel * delta_t;
+
+    // Rotation: half kick from the torque, free rotation, then the other half kick
+    let inv_moi = inverse_moi(body);
+    let mut momentum = body.angular_momentum() + body.torque * delta_t / 2.;
+    let start_momentum = body.orientation.invert().rotate_vector(momentum);
+
+    // Implicit midpoint on the body-frame angular momentum. Its solution is a Cayley rotation of the
+    // starting momentum, so the same rotation is applied to the orientation and the inertial momentum
+    // is left unchanged.
+    let mut end_momentum = start_momentum;
+    let mut step_rotation = Quaternion::new(1., 0., 0., 0.);
+    for _ in 0..MAX_MIDPOINT_ITERATIONS {
+        let midpoint_angvel = inv_moi * (start_momentum + end_momentum) / 2.;
+        step_rotation = cayley_rotation(midpoint_angvel * delta_t);
+        let next = step_rotation.invert().rotate_vector(start_momentum);
+        let converged = (next - end_momentum).magnitude2() <= MIDPOINT_TOLERANCE.powi(2) * start_momentum.magnitude2();
+        end_momentum = next;
+        if converged { break; }
+    }
+    body.orientation = (body.orientation * step_rotation).normalize();
+
+    momentum = body.orientation.rotate_vector(end_momentum) + body.torque * delta_t / 2.;
+    body.angvel = body.orientation.rotate_vector(inv_moi * body.orientation.invert().rotate_vector(momentum));
+}
+
+/// Rotation by the angle `|v|` around `v`
+fn rotation_from_vector(v: Vector3<f64>) -> Quaternion<f64> {
+    let angle = v.magnitude();
+    if angle < 1e-12 {
+        return Quaternion::from_sv(1., v / 2.).normalize();
+    }
+    Quaternion::from_sv((angle / 2.).cos(), v * ((angle / 2.).sin() / angle))
+}
+
+/// Cayley transform of the skew matrix of `v`, which is a rotation by `2 atan(|v|/2)` around `v`
+fn cayley_rotation(v: Vector3<f64>) -> Quaternion<f64> {
+    Quaternion::from_sv(1., v / 2.).normalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{InnerSpace, Matrix3, Quaternion, SquareMatrix, Vector3};
+
+    use crate::physics::gravity::PointMass;
+    use crate::physics::RigidBody;
+
+    /// A free body spinning close to its intermediate axis tumbles over and over, which is where
+    /// integrators that do not conserve energy drift the most
+    #[test]
+    fn tumbling_conserves_energy_and_momentum() {
+        let moi = Matrix3::from_diagonal(Vector3::new(1., 2., 3.));
+        let mut body = RigidBody::new(Vector3::new(0., 0., 0.), Vector3::new(0., 0., 0.), Quaternion::new(1., 0., 0., 0.), Vector3::new(0.01, 2., 0.01), 1., moi);
+        let energy = body.kinetic_energy();
+        let momentum = body.angular_momentum();
+        for _ in 0..100_000 {
+            body.update(0.01);
+        }
+        assert!((body.kinetic_energy() - energy).abs() < 1e-6 * energy, "energy went from {energy} to {}", body.kinetic_energy());
+        assert!((body.angular_momentum() - momentum).magnitude() < 1e-6 * momentum.magnitude(), "momentum went from {momentum:?} to {:?}", body.angular_momentum());
+    }
+
+    /// A circular orbit integrated for a thousand periods keeps its radius and energy
+    #[test]
+    fn circular_orbits_are_stable() {
+        let planet = PointMass { pos: Vector3::new(0., 0., 0.), mu: 1. };
+        let radius = 1.;
+        let speed = planet.circular_speed(radius);
+        let mut body = RigidBody::new(Vector3::new(radius, 0., 0.), Vector3::new(0., speed, 0.), Quaternion::new(1., 0., 0., 0.), Vector3::new(0., 0., 0.), 1., Matrix3::from_diagonal(Vector3::new(1., 1., 1.)));
+        let energy = |body: &RigidBody| body.vel.magnitude2() / 2. - planet.mu / (body.pos - planet.pos).magnitude();
+        let start_energy = energy(&body);
+        let steps_per_period = 1000;
+        let delta_t = 2. * std::f64::consts::PI * radius / speed / steps_per_period as f64;
+        let (mut radius_drift, mut energy_drift) = (0f64, 0f64);
+        for _ in 0..1000 * steps_per_period {
+            body.force += planet.acceleration(body.pos) * body.mass;
+            body.update(delta_t);
+            radius_drift = radius_drift.max(((body.pos - planet.pos).magnitude() - radius).abs() / radius);
+            energy_drift = energy_drift.max(((energy(&body) - start_energy) / start_energy).abs());
+        }
+        assert!(radius_drift < 1e-2, "radius drifted by {radius_drift}");
+        assert!(energy_drift < 1e-3, "energy drifted by {energy_drift}");
+    }
+}
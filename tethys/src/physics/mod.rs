@@ -1,63 +1,132 @@
-use cgmath::{InnerSpace, Matrix4, Quaternion, Rotation, Vector3, Zero};
+use cgmath::{InnerSpace, Matrix, Matrix3, Matrix4, Quaternion, Rotation, SquareMatrix, Vector3, Zero};
 use serde::{Deserialize, Serialize};
 
 pub mod collisions;
+mod broad_phase;
+pub mod gravity;
+mod integrator;
+mod response;
+mod world;
 
+pub use broad_phase::Aabb;
+pub use integrator::Integrator;
+pub use response::{resolve_collision, ContactMaterial};
+pub use world::{Body, BodyHandle, World};
 
-#[derive(Debug, Serialize, Deserialize)]
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct RigidBody {
+    /// Position of the center of mass in the inertial frame
     #[serde(with = "serde_vector3")]
     pub pos: Vector3<f64>,
+    /// Position of the center of mass in the local frame. The body rotates around this point.
+    #[serde(with = "serde_vector3")]
+    pub com: Vector3<f64>,
     #[serde(with = "serde_quaternion")]
     pub orientation: Quaternion<f64>,
     #[serde(with = "serde_vector3")]
     pub vel: Vector3<f64>,
-    #[serde(with = "serde_quaternion")]
-    pub angvel: Quaternion<f64>,
+    /// Angular velocity in the inertial frame
+    #[serde(with = "serde_vector3")]
+    pub angvel: Vector3<f64>,
     #[serde(with = "serde_vector3")]
     pub force: Vector3<f64>,
-    #[serde(with = "serde_quaternion")]
-    pub torque: Quaternion<f64>,
+    /// Torque in the inertial frame
+    #[serde(with = "serde_vector3")]
+    pub torque: Vector3<f64>,
     pub mass: f64,
-    pub moi: (f64, f64, f64),
+    /// Inertia tensor in the body frame
+    #[serde(with = "serde_matrix3")]
+    pub moi: Matrix3<f64>,
+    #[serde(default)]
+    pub integrator: Integrator,
 }
 
 impl RigidBody {
-    pub fn new(pos: Vector3<f64>, vel: Vector3<f64>, orientation: Quaternion<f64>, angvel: Vector3<f64>, mass: f64, moi: (f64, f64, f64)) -> Self {
+    pub fn new(pos: Vector3<f64>, vel: Vector3<f64>, orientation: Quaternion<f64>, angvel: Vector3<f64>, mass: f64, moi: Matrix3<f64>) -> Self {
         Self {
             pos,
+            com: Vector3::new(0., 0., 0.),
             orientation,
             vel,
-            angvel: Quaternion::from_sv(0., angvel),
+            angvel,
             mass,
             moi,
             force: Vector3::new(0., 0., 0.),
-            torque: Quaternion::new(0., 0., 0., 0.),
+            torque: Vector3::new(0., 0., 0.),
+            integrator: Integrator::default(),
         }
     }
 
+    /// Advance the body by one timestep using the accumulated force and torque, which are then cleared
     pub fn update(&mut self, delta_t: f64) {
-        self.vel += self.force * delta_t / self.mass;
-        self.angvel += Quaternion::new(
-            0.,
-            self.torque.v.x * delta_t / self.moi.0,
-            self.torque.v.y * delta_t / self.moi.1,
-            self.torque.v.z * delta_t / self.moi.2
-        );
-        self.pos += self.vel * delta_t;
-        self.orientation += self.angvel * self.orientation * 0.5 * delta_t;
-        self.orientation = self.orientation.normalize();
+        self.integrator.step(self, delta_t);
 
         self.force = Vector3::new(0., 0., 0.);
-        self.torque = Quaternion::new(0., 0., 0., 0.);
+        self.torque = Vector3::new(0., 0., 0.);
+    }
+
+    /// Accumulate a force acting at `point`, both in the inertial frame. Forces that do not act
+    /// through the center of mass also produce a torque.
+    pub fn apply_force_at(&mut self, force: Vector3<f64>, point: Vector3<f64>) {
+        self.force += force;
+        self.torque += (point - self.pos).cross(force);
+    }
+
+    /// Apply an instantaneous impulse at `point`, both in the inertial frame
+    pub fn apply_impulse_at(&mut self, impulse: Vector3<f64>, point: Vector3<f64>) {
+        self.vel += impulse / self.mass;
+        self.angvel += self.world_inverse_moi() * (point - self.pos).cross(impulse);
+    }
+
+    /// Velocity of the point of the body which is currently at `point`
+    pub fn velocity_at(&self, point: Vector3<f64>) -> Vector3<f64> {
+        self.vel + self.angvel.cross(point - self.pos)
+    }
+
+    /// Inverse of the inertia tensor rotated into the inertial frame. A singular tensor cannot be
+    /// spun, so its inverse is zero.
+    pub fn world_inverse_moi(&self) -> Matrix3<f64> {
+        let rot = Matrix3::from(self.orientation);
+        rot * self.moi.invert().unwrap_or(Matrix3::zero()) * rot.transpose()
+    }
+
+    /// The inertia tensor rotated into the inertial frame
+    pub fn world_moi(&self) -> Matrix3<f64> {
+        let rot = Matrix3::from(self.orientation);
+        rot * self.moi * rot.transpose()
+    }
+
+    /// Angular momentum in the inertial frame
+    pub fn angular_momentum(&self) -> Vector3<f64> {
+        self.world_moi() * self.angvel
+    }
+
+    /// Translational plus rotational kinetic energy
+    pub fn kinetic_energy(&self) -> f64 {
+        0.5 * self.mass * self.vel.magnitude2() + 0.5 * self.angvel.dot(self.angular_momentum())
     }
     
     pub fn to_local(&self, v: Vector3<f64>) -> Vector3<f64> {
-        self.orientation.invert().rotate_vector(v - self.pos)
+        self.orientation.invert().rotate_vector(v - self.pos) + self.com
     }
     
     pub fn to_global(&self, v: Vector3<f64>) -> Vector3<f64> {
-        self.orientation.rotate_vector(v) + self.pos
+        self.orientation.rotate_vector(v - self.com) + self.pos
+    }
+
+    /// Move the body so that the origin of its local frame lies at `origin`
+    pub fn set_origin(&mut self, origin: Vector3<f64>) {
+        self.pos = origin + self.orientation.rotate_vector(self.com);
+    }
+
+    /// Move the center of mass within the local frame without moving the body. The velocity is
+    /// updated to that of the new center of mass.
+    pub fn set_com(&mut self, com: Vector3<f64>) {
+        let shift = self.orientation.rotate_vector(com - self.com);
+        self.pos += shift;
+        self.vel += self.angvel.cross(shift);
+        self.com = com;
     }
     
     pub fn get_mvp(&self, camera: &crate::prelude::Camera) -> Matrix4<f32> {
@@ -67,8 +136,12 @@ impl RigidBody {
             self.orientation.v.y as f32,
             self.orientation.v.z as f32,
         ));
-        let world = Matrix4::from_translation((self.pos - camera.position).cast::<f32>().unwrap()) * rot;
-        return world * camera.get_view() * camera.get_proj();
+        // Translate relative to the camera in double precision so that bodies far from the origin
+        // do not lose precision
+        let world = Matrix4::from_translation((self.pos - camera.position).cast::<f32>().unwrap())
+            * rot
+            * Matrix4::from_translation(-self.com.cast::<f32>().unwrap());
+        camera.get_view_proj() * world
     }
 }
 
@@ -80,7 +153,7 @@ impl Default for RigidBody {
             Quaternion::new(1., 0., 0., 0.),
             Vector3::zero(),
             1.,
-            (1., 1., 1.)
+            Matrix3::identity()
         )
     }
 }
@@ -105,6 +178,27 @@ mod serde_vector3 {
     }
 }
 
+mod serde_matrix3 {
+    use cgmath::Matrix3;
+    use serde::{Serialize, Deserialize, Serializer, Deserializer};
+
+    pub fn serialize<S>(m: &Matrix3<f64>, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let columns: [[f64; 3]; 3] = (*m).into();
+        columns.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Matrix3<f64>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let columns = <[[f64; 3]; 3]>::deserialize(deserializer)?;
+        Ok(Matrix3::from(columns))
+    }
+}
+
 mod serde_quaternion {
     use cgmath::Quaternion;
     use serde::{Serialize, Deserialize, Serializer, Deserializer};
@@ -0,0 +1,152 @@
+use cgmath::{InnerSpace, Matrix3, Vector3, Zero};
+
+use super::collisions::CollisionReport;
+use super::RigidBody;
+
+/// Number of passes made over the contacts when solving for the impulses
+const SOLVER_ITERATIONS: usize = 8;
+/// Penetration depth which is tolerated without positional correction, to stop resting contacts jittering
+const PENETRATION_SLOP: f64 = 0.005;
+/// Fraction of the remaining penetration which is removed each time contacts are resolved
+const CORRECTION_FRACTION: f64 = 0.8;
+/// Approach speeds below this do not bounce, so resting bodies settle
+const RESTITUTION_THRESHOLD: f64 = 0.05;
+
+/// Surface properties used when resolving a contact
+#[derive(Clone, Copy, Debug)]
+pub struct ContactMaterial {
+    /// Fraction of the approach speed which is returned after the collision, from 0 to 1
+    pub restitution: f64,
+    /// Coulomb friction coefficient
+    pub friction: f64,
+}
+
+impl Default for ContactMaterial {
+    fn default() -> Self {
+        Self {
+            restitution: 0.2,
+            friction: 0.5,
+        }
+    }
+}
+
+/// One side of a contact. Static bodies have no rigid body, and so infinite mass.
+struct Side<'a>(Option<&'a mut RigidBody>);
+
+impl Side<'_> {
+    fn inverse_mass(&self) -> f64 {
+        self.0.as_ref().map_or(0., |b| 1. / b.mass)
+    }
+
+    fn inverse_moi(&self) -> Matrix3<f64> {
+        self.0.as_ref().map_or(Matrix3::zero(), |b| b.world_inverse_moi())
+    }
+
+    fn velocity_at(&self, point: Vector3<f64>) -> Vector3<f64> {
+        self.0.as_ref().map_or(Vector3::zero(), |b| b.velocity_at(point))
+    }
+
+    fn lever(&self, point: Vector3<f64>) -> Vector3<f64> {
+        self.0.as_ref().map_or(Vector3::zero(), |b| point - b.pos)
+    }
+
+    fn apply_impulse_at(&mut self, impulse: Vector3<f64>, point: Vector3<f64>) {
+        if let Some(b) = &mut self.0 {
+            b.apply_impulse_at(impulse, point);
+        }
+    }
+
+    /// Inverse of the effective mass felt by an impulse along `direction` at `point`
+    fn inverse_effective_mass(&self, direction: Vector3<f64>, point: Vector3<f64>) -> f64 {
+        let r = self.lever(point);
+        self.inverse_mass() + direction.dot((self.inverse_moi() * r.cross(direction)).cross(r))
+    }
+}
+
+struct Contact {
+    point: Vector3<f64>,
+    normal: Vector3<f64>,
+    depth: f64,
+    /// Normal speed the solver aims for, which makes the bodies bounce
+    target_speed: f64,
+    inverse_normal_mass: f64,
+    normal_impulse: f64,
+    friction_impulse: Vector3<f64>,
+}
+
+/// Respond to a collision between two bodies by applying impulses and separating them.
+///
+/// `report` must come from `Collider::check_intersection` with `a` as the first collider, so that its
+/// depths point away from `b`. Either body may be `None` for static geometry. Normal impulses make
+/// the bodies bounce according to the restitution, friction impulses are limited by the Coulomb
+/// cone, and finally the bodies are moved apart to remove most of the deepest penetration.
+pub fn resolve_collision(report: &CollisionReport, a: Option<&mut RigidBody>, b: Option<&mut RigidBody>, material: ContactMaterial) {
+    let mut a = Side(a);
+    let mut b = Side(b);
+    if a.inverse_mass() + b.inverse_mass() == 0. {
+        return;
+    }
+
+    // Contacts without a depth (such as raycasts) carry no normal, so they cannot be resolved
+    let mut contacts = report.depths.iter().zip(&report.positions).filter_map(|(depth, point)| {
+        let length = depth.magnitude();
+        if length == 0. {
+            return None;
+        }
+        let normal = depth / length;
+        let approach = (a.velocity_at(*point) - b.velocity_at(*point)).dot(normal);
+        Some(Contact {
+            point: *point,
+            normal,
+            depth: length,
+            target_speed: if approach < -RESTITUTION_THRESHOLD { -material.restitution * approach } else { 0. },
+            inverse_normal_mass: a.inverse_effective_mass(normal, *point) + b.inverse_effective_mass(normal, *point),
+            normal_impulse: 0.,
+            friction_impulse: Vector3::zero(),
+        })
+    }).collect::<Vec<_>>();
+    if contacts.is_empty() {
+        return;
+    }
+
+    // Sequential impulses, clamping the accumulated impulse of each contact
+    for _ in 0..SOLVER_ITERATIONS {
+        for contact in &mut contacts {
+            let relative = a.velocity_at(contact.point) - b.velocity_at(contact.point);
+            let normal_speed = relative.dot(contact.normal);
+            let total = (contact.normal_impulse + (contact.target_speed - normal_speed) / contact.inverse_normal_mass).max(0.);
+            let impulse = contact.normal * (total - contact.normal_impulse);
+            contact.normal_impulse = total;
+            a.apply_impulse_at(impulse, contact.point);
+            b.apply_impulse_at(-impulse, contact.point);
+
+            let relative = a.velocity_at(contact.point) - b.velocity_at(contact.point);
+            let slip = relative - contact.normal * relative.dot(contact.normal);
+            let slip_speed = slip.magnitude();
+            if slip_speed == 0. {
+                continue;
+            }
+            let tangent = slip / slip_speed;
+            let inverse_tangent_mass = a.inverse_effective_mass(tangent, contact.point) + b.inverse_effective_mass(tangent, contact.point);
+            let mut total = contact.friction_impulse - tangent * (slip_speed / inverse_tangent_mass);
+            let limit = material.friction * contact.normal_impulse;
+            if total.magnitude() > limit {
+                total = total.normalize_to(limit);
+            }
+            let impulse = total - contact.friction_impulse;
+            contact.friction_impulse = total;
+            a.apply_impulse_at(impulse, contact.point);
+            b.apply_impulse_at(-impulse, contact.point);
+        }
+    }
+
+    // Contacts overlap, so only the deepest one is used to push the bodies apart
+    let deepest = contacts.iter().fold(&contacts[0], |deepest, c| if c.depth > deepest.depth { c } else { deepest });
+    let correction = (deepest.depth - PENETRATION_SLOP).max(0.) * CORRECTION_FRACTION / (a.inverse_mass() + b.inverse_mass());
+    if let Some(body) = &mut a.0 {
+        body.pos += deepest.normal * correction / body.mass;
+    }
+    if let Some(body) = &mut b.0 {
+        body.pos -= deepest.normal * correction / body.mass;
+    }
+}
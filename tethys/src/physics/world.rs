@@ -0,0 +1,194 @@
+use super::broad_phase::{Aabb, SweepAndPrune};
+use super::collisions::{Collider, ColliderPackage, CollisionReport};
+use super::gravity::GravityField;
+use super::{resolve_collision, ContactMaterial, RigidBody};
+
+/// Identifies a body within a `World`. Slots are reused once their body is removed, so the
+/// handle also holds the generation of the slot, and handles to removed bodies find nothing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct BodyHandle {
+    index: usize,
+    generation: u32,
+}
+
+/// A collider together with the rigid body that moves it. Bodies without a rigid body are static.
+pub struct Body {
+    pub rigid_body: Option<RigidBody>,
+    pub collider: Collider,
+}
+
+/// A place for a body, which counts how many bodies have been removed from it
+struct Slot {
+    generation: u32,
+    body: Option<Body>,
+}
+
+/// Owns a set of bodies and steps them together, resolving the collisions between them
+pub struct World {
+    slots: Vec<Slot>,
+    broad_phase: SweepAndPrune,
+    /// Pairs of bodies which collided during the last step
+    collisions: Vec<(BodyHandle, BodyHandle, CollisionReport)>,
+    pub material: ContactMaterial,
+    pub gravity: GravityField,
+}
+
+impl World {
+    pub fn new() -> Self {
+        Self {
+            slots: Vec::new(),
+            broad_phase: SweepAndPrune::new(),
+            collisions: Vec::new(),
+            material: ContactMaterial::default(),
+            gravity: GravityField::default(),
+        }
+    }
+
+    /// Add a body to the world. Pass None as the rigid body for static geometry.
+    pub fn add(&mut self, rigid_body: Option<RigidBody>, collider: Collider) -> BodyHandle {
+        let body = Some(Body { rigid_body, collider });
+        let index = match self.slots.iter().position(|slot| slot.body.is_none()) {
+            Some(index) => {
+                self.slots[index].body = body;
+                index
+            },
+            None => {
+                self.slots.push(Slot { generation: 0, body });
+                self.slots.len() - 1
+            },
+        };
+        self.handle(index)
+    }
+
+    /// Remove a body from the world, returning it. Its handle and any copies of it become stale.
+    pub fn remove(&mut self, handle: BodyHandle) -> Option<Body> {
+        let slot = self.slot_mut(handle)?;
+        let body = slot.body.take()?;
+        slot.generation += 1;
+        Some(body)
+    }
+
+    pub fn get(&self, handle: BodyHandle) -> Option<&Body> {
+        self.slots.get(handle.index)
+            .filter(|slot| slot.generation == handle.generation)?
+            .body.as_ref()
+    }
+
+    pub fn get_mut(&mut self, handle: BodyHandle) -> Option<&mut Body> {
+        self.slot_mut(handle)?.body.as_mut()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BodyHandle, &Body)> {
+        self.slots.iter().enumerate().filter_map(|(i, slot)| Some((self.handle(i), slot.body.as_ref()?)))
+    }
+
+    fn slot_mut(&mut self, handle: BodyHandle) -> Option<&mut Slot> {
+        self.slots.get_mut(handle.index).filter(|slot| slot.generation == handle.generation)
+    }
+
+    /// Handle to the body now in a slot
+    fn handle(&self, index: usize) -> BodyHandle {
+        BodyHandle { index, generation: self.slots[index].generation }
+    }
+
+    /// Pairs of bodies which collided during the last step, with the report from the first body's point of view
+    pub fn collisions(&self) -> &[(BodyHandle, BodyHandle, CollisionReport)] {
+        &self.collisions
+    }
+
+    /// Add gravity to and advance every rigid body, then find and resolve collisions. Other forces
+    /// and torques should be applied to the rigid bodies before calling this.
+    pub fn step(&mut self, delta_t: f64) {
+        for body in self.slots.iter_mut().filter_map(|slot| slot.body.as_mut()) {
+            if let Some(rigid_body) = &mut body.rigid_body {
+                self.gravity.apply(rigid_body);
+                rigid_body.update(delta_t);
+            }
+        }
+
+        let aabbs = self.slots.iter().map(|slot| {
+            let b = slot.body.as_ref()?;
+            Aabb::new(&b.collider, b.rigid_body.as_ref())
+        }).collect::<Vec<_>>();
+
+        self.collisions.clear();
+        for (i, j) in self.broad_phase.candidate_pairs(&aabbs) {
+            let (left, right) = self.slots.split_at_mut(j);
+            let (Some(a), Some(b)) = (&mut left[i].body, &mut right[0].body) else { continue };
+            // Two static bodies never need resolving
+            if a.rigid_body.is_none() && b.rigid_body.is_none() {
+                continue;
+            }
+
+            let report = Collider::check_intersection(
+                ColliderPackage { collider: &a.collider, rigid_body: a.rigid_body.as_ref() },
+                ColliderPackage { collider: &b.collider, rigid_body: b.rigid_body.as_ref() },
+            );
+            if !report.collision() {
+                continue;
+            }
+            resolve_collision(&report, a.rigid_body.as_mut(), b.rigid_body.as_mut(), self.material);
+            self.collisions.push((self.handle(i), self.handle(j), report));
+        }
+    }
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::{Matrix3, Quaternion, SquareMatrix, Vector3};
+
+    use crate::physics::collisions::BoxCollider;
+
+    use super::*;
+
+    /// A unit cube around a rigid body at `pos`, falling slowly
+    fn cube(world: &mut World, pos: Vector3<f64>) -> BodyHandle {
+        let rigid_body = RigidBody::new(pos, Vector3::new(0., 0., -0.1), Quaternion::new(1., 0., 0., 0.), Vector3::new(0., 0., 0.), 1., Matrix3::identity() / 6.);
+        world.add(Some(rigid_body), Collider::Box(BoxCollider::new(Vector3::new(-0.5, -0.5, -0.5), Vector3::new(1., 1., 1.))))
+    }
+
+    /// A static slab whose top is at z = 0
+    fn ground(world: &mut World) -> BodyHandle {
+        world.add(None, Collider::Box(BoxCollider::new(Vector3::new(-5., -5., -1.), Vector3::new(10., 10., 1.))))
+    }
+
+    #[test]
+    fn stale_handles_find_nothing() {
+        let mut world = World::new();
+        let removed = ground(&mut world);
+        assert!(world.remove(removed).is_some());
+        let added = cube(&mut world, Vector3::new(0., 0., 3.));
+        assert!(world.get(removed).is_none());
+        assert!(world.remove(removed).is_none());
+        assert!(world.get(added).is_some());
+        assert_eq!(world.iter().map(|(handle, _)| handle).collect::<Vec<_>>(), vec![added]);
+    }
+
+    #[test]
+    fn step_resolves_overlapping_bodies() {
+        let mut world = World::new();
+        let ground = ground(&mut world);
+        let cube = cube(&mut world, Vector3::new(0., 0., 0.45));
+        world.step(0.01);
+        assert_eq!(world.collisions().len(), 1);
+        let (a, b, _) = &world.collisions()[0];
+        assert_eq!((*a, *b), (ground, cube));
+        assert!(world.get(cube).unwrap().rigid_body.as_ref().unwrap().vel.z >= 0., "the cube still falls into the ground");
+    }
+
+    #[test]
+    fn step_leaves_separated_bodies_alone() {
+        let mut world = World::new();
+        ground(&mut world);
+        let cube = cube(&mut world, Vector3::new(0., 0., 2.));
+        world.step(0.01);
+        assert!(world.collisions().is_empty());
+        assert!(world.get(cube).unwrap().rigid_body.as_ref().unwrap().vel.z < 0.);
+    }
+}